@@ -0,0 +1,101 @@
+#[cfg(any(test, not(windows)))]
+mod recording;
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod win32;
+
+#[cfg(any(test, not(windows)))]
+pub use recording::RecordingBackend;
+#[cfg(test)]
+pub use recording::{BackendCall, FakeSystemInfo};
+#[cfg(unix)]
+pub use unix::UnixSystemInfo;
+#[cfg(windows)]
+pub use win32::Win32Backend;
+
+use crate::{
+    config::PowerScheme,
+    display::{ModeInfo, Monitor, SavedMode},
+    rules::TimeOfDay,
+};
+
+type Result<T> = crate::Result<T>;
+
+pub trait PowerBackend: Send + Sync {
+    fn set_active_scheme(&self, scheme: &PowerScheme) -> Result<()>;
+    /// The GUID of the active scheme, in lowercase like [`PowerScheme::guid`].
+    fn get_active_scheme(&self) -> Result<String>;
+}
+
+pub trait DisplayBackend: Send + Sync {
+    /// Every display attached to the desktop.
+    fn get_monitors(&self) -> Result<Vec<Monitor>>;
+    /// Every mode the monitor supports, and the one it is in now.
+    fn get_modes(&self, monitor: &Monitor) -> Result<(Vec<ModeInfo>, ModeInfo)>;
+    /// Switches to one of the modes from [`get_modes`](Self::get_modes).
+    fn set_mode(&self, monitor: &Monitor, mode: &ModeInfo) -> Result<()>;
+    /// The current mode of the monitor, exactly as the system reports it.
+    fn save_mode(&self, monitor: &Monitor) -> Result<SavedMode>;
+    fn restore_mode(&self, monitor: &Monitor, mode: &SavedMode) -> Result<()>;
+}
+
+pub trait NotificationBackend: Send + Sync {
+    fn send_notification(&self, title: &str, description: &str) -> Result<()>;
+}
+
+/// Read-only facts about the system that rules can match on.
+pub trait SystemInfoBackend: Send + Sync {
+    fn get_local_time(&self) -> Result<TimeOfDay>;
+    /// Whether any display other than a built-in panel is connected.
+    fn has_external_display(&self) -> Result<bool>;
+    fn get_running_processes(&self) -> Result<Vec<String>>;
+}
+
+/// The set of backends a state change is applied through.
+/// Shared with the thread that watches the config, which only sends notifications.
+pub struct Backends {
+    pub power: Box<dyn PowerBackend>,
+    pub display: Box<dyn DisplayBackend>,
+    pub notification: Box<dyn NotificationBackend>,
+    pub system: Box<dyn SystemInfoBackend>,
+}
+
+impl Backends {
+    pub fn new<B>(backend: B) -> Self
+    where
+        B: PowerBackend
+            + DisplayBackend
+            + NotificationBackend
+            + SystemInfoBackend
+            + Clone
+            + 'static,
+    {
+        Self {
+            power: Box::new(backend.clone()),
+            display: Box::new(backend.clone()),
+            notification: Box::new(backend.clone()),
+            system: Box::new(backend),
+        }
+    }
+
+    #[cfg(windows)]
+    pub fn platform() -> Self {
+        Self::new(Win32Backend)
+    }
+
+    /// There is nothing to switch outside of Windows, so everything is only recorded and logged,
+    /// and there are no monitors. Rules can still be matched against the real system.
+    #[cfg(unix)]
+    pub fn platform() -> Self {
+        Self {
+            system: Box::new(UnixSystemInfo),
+            ..Self::new(RecordingBackend::without_monitors())
+        }
+    }
+
+    #[cfg(not(any(windows, unix)))]
+    pub fn platform() -> Self {
+        Self::new(RecordingBackend::without_monitors())
+    }
+}
@@ -0,0 +1,201 @@
+use super::{DisplayBackend, NotificationBackend, PowerBackend, Result, SystemInfoBackend};
+use crate::{
+    config::PowerScheme,
+    display::{ModeInfo, Monitor, SavedMode},
+    rules::TimeOfDay,
+};
+use autopower_shared::logging::Logger;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+static LOGGER: Logger = Logger::new("recording_backend", "autopower_proxy");
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendCall {
+    SetActiveScheme(PowerScheme),
+    SetMode { monitor: String, mode: ModeInfo },
+    RestoreMode { monitor: String, mode: SavedMode },
+    SendNotification { title: String, description: String },
+}
+
+/// The system facts a [`RecordingBackend`] reports.
+#[derive(Debug, Clone)]
+pub struct FakeSystemInfo {
+    pub time: TimeOfDay,
+    pub external_display: bool,
+    pub processes: Vec<String>,
+    pub monitors: Vec<Monitor>,
+    /// The modes every monitor supports.
+    pub supported_modes: Vec<ModeInfo>,
+    /// The current mode of each monitor, which changes with every call that sets one.
+    /// Monitors that are not listed are in the first supported mode.
+    pub modes: BTreeMap<String, ModeInfo>,
+    /// The GUID of the active power scheme, which changes with every call that sets one.
+    /// Reading it fails while there is none.
+    pub active_scheme: Option<String>,
+    /// Makes listing the monitors fail with this error, like a display driver that is gone.
+    pub monitor_error: Option<String>,
+}
+
+/// A single built-in 1080p panel, like a laptop that is not docked.
+impl Default for FakeSystemInfo {
+    fn default() -> Self {
+        Self {
+            time: TimeOfDay::default(),
+            external_display: false,
+            processes: vec![],
+            monitors: vec![Monitor {
+                name: r"\\.\DISPLAY1".to_owned(),
+                id: String::new(),
+                internal: true,
+                primary: true,
+            }],
+            supported_modes: [60, 144]
+                .into_iter()
+                .map(|frequency| ModeInfo {
+                    width: 1920,
+                    height: 1080,
+                    bits_per_pixel: 32,
+                    frequency,
+                })
+                .collect(),
+            modes: BTreeMap::new(),
+            active_scheme: None,
+            monitor_error: None,
+        }
+    }
+}
+
+/// An in-memory backend that records every call instead of touching the system.
+/// Clones share the same record.
+#[derive(Clone, Default)]
+pub struct RecordingBackend {
+    calls: Arc<Mutex<Vec<BackendCall>>>,
+    system: Arc<Mutex<FakeSystemInfo>>,
+}
+
+impl RecordingBackend {
+    /// Reports no monitors, for platforms that cannot list them, so plans show no made up ones.
+    pub fn without_monitors() -> Self {
+        let backend = Self::default();
+        backend.system.lock().unwrap().monitors.clear();
+        backend
+    }
+
+    fn record(&self, call: BackendCall) {
+        LOGGER.debug(format!("Recorded backend call: {:?}", call));
+        self.calls.lock().unwrap().push(call);
+    }
+
+    #[cfg(test)]
+    pub fn calls(&self) -> Vec<BackendCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    #[cfg(test)]
+    pub fn set_system_info(&self, system: FakeSystemInfo) {
+        *self.system.lock().unwrap() = system;
+    }
+}
+
+impl PowerBackend for RecordingBackend {
+    fn set_active_scheme(&self, scheme: &PowerScheme) -> Result<()> {
+        self.record(BackendCall::SetActiveScheme(scheme.clone()));
+        self.system.lock().unwrap().active_scheme = Some(scheme.guid());
+        Ok(())
+    }
+
+    fn get_active_scheme(&self) -> Result<String> {
+        self.system
+            .lock()
+            .unwrap()
+            .active_scheme
+            .clone()
+            .ok_or_else(|| "No power scheme was set yet!".into())
+    }
+}
+
+impl DisplayBackend for RecordingBackend {
+    fn get_monitors(&self) -> Result<Vec<Monitor>> {
+        let system = self.system.lock().unwrap();
+        match &system.monitor_error {
+            Some(e) => Err(e.clone().into()),
+            None => Ok(system.monitors.clone()),
+        }
+    }
+
+    fn get_modes(&self, monitor: &Monitor) -> Result<(Vec<ModeInfo>, ModeInfo)> {
+        let system = self.system.lock().unwrap();
+        let current = system
+            .modes
+            .get(&monitor.name)
+            .or(system.supported_modes.first())
+            .copied()
+            .ok_or_else(|| format!("{} has no display modes!", monitor.name))?;
+        Ok((system.supported_modes.clone(), current))
+    }
+
+    fn set_mode(&self, monitor: &Monitor, mode: &ModeInfo) -> Result<()> {
+        self.record(BackendCall::SetMode {
+            monitor: monitor.name.clone(),
+            mode: *mode,
+        });
+        self.system
+            .lock()
+            .unwrap()
+            .modes
+            .insert(monitor.name.clone(), *mode);
+        Ok(())
+    }
+
+    fn save_mode(&self, monitor: &Monitor) -> Result<SavedMode> {
+        let (_, current) = self.get_modes(monitor)?;
+        Ok(SavedMode(serde_json::to_string(&current)?))
+    }
+
+    fn restore_mode(&self, monitor: &Monitor, mode: &SavedMode) -> Result<()> {
+        let restored = serde_json::from_str(&mode.0)
+            .map_err(|e| format!("Could not read the saved display mode!\n{}", e))?;
+        self.record(BackendCall::RestoreMode {
+            monitor: monitor.name.clone(),
+            mode: mode.clone(),
+        });
+        self.system
+            .lock()
+            .unwrap()
+            .modes
+            .insert(monitor.name.clone(), restored);
+        Ok(())
+    }
+}
+
+impl NotificationBackend for RecordingBackend {
+    fn send_notification(&self, title: &str, description: &str) -> Result<()> {
+        self.record(BackendCall::SendNotification {
+            title: title.to_owned(),
+            description: description.to_owned(),
+        });
+        Ok(())
+    }
+}
+
+impl SystemInfoBackend for RecordingBackend {
+    fn get_local_time(&self) -> Result<TimeOfDay> {
+        Ok(self.system.lock().unwrap().time)
+    }
+
+    /// Fails like listing the monitors does, since that is how Windows finds out.
+    fn has_external_display(&self) -> Result<bool> {
+        let system = self.system.lock().unwrap();
+        match &system.monitor_error {
+            Some(e) => Err(e.clone().into()),
+            None => Ok(system.external_display),
+        }
+    }
+
+    fn get_running_processes(&self) -> Result<Vec<String>> {
+        Ok(self.system.lock().unwrap().processes.clone())
+    }
+}
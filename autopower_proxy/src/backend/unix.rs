@@ -0,0 +1,65 @@
+use super::{Result, SystemInfoBackend};
+use crate::rules::TimeOfDay;
+use std::{fs, path::Path};
+
+const DRM_ROOT: &str = "/sys/class/drm";
+const PROC_ROOT: &str = "/proc";
+/// DRM connector types that are built into the machine.
+const INTERNAL_CONNECTORS: &[&str] = &["eDP", "LVDS", "DSI"];
+
+/// Reads system facts from procfs, sysfs and the C library.
+#[derive(Clone, Copy)]
+pub struct UnixSystemInfo;
+
+impl SystemInfoBackend for UnixSystemInfo {
+    fn get_local_time(&self) -> Result<TimeOfDay> {
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        let now = unsafe { libc::time(std::ptr::null_mut()) };
+        if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        TimeOfDay::new(tm.tm_hour as u8, tm.tm_min as u8)
+    }
+
+    fn has_external_display(&self) -> Result<bool> {
+        if !Path::new(DRM_ROOT).exists() {
+            return Ok(false);
+        }
+        for entry in fs::read_dir(DRM_ROOT)? {
+            let entry = entry?;
+            // Connectors are named like "card0-HDMI-A-1".
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some((_, connector)) = name.split_once('-') else {
+                continue;
+            };
+            if INTERNAL_CONNECTORS.iter().any(|x| connector.starts_with(x)) {
+                continue;
+            }
+            let status = fs::read_to_string(entry.path().join("status")).unwrap_or_default();
+            if status.trim() == "connected" {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn get_running_processes(&self) -> Result<Vec<String>> {
+        let mut processes = vec![];
+        for entry in fs::read_dir(PROC_ROOT)? {
+            let entry = entry?;
+            if !entry
+                .file_name()
+                .to_string_lossy()
+                .bytes()
+                .all(|x| x.is_ascii_digit())
+            {
+                continue;
+            }
+            // Processes can exit while being listed.
+            if let Ok(name) = fs::read_to_string(entry.path().join("comm")) {
+                processes.push(name.trim_end().to_owned());
+            }
+        }
+        Ok(processes)
+    }
+}
@@ -0,0 +1,106 @@
+use super::{DisplayBackend, NotificationBackend, PowerBackend, Result, SystemInfoBackend};
+use crate::{
+    config::PowerScheme,
+    display::{
+        get_monitors, get_supported_modes, restore_display_mode, save_display_mode,
+        set_display_mode, ModeInfo, Monitor, SavedMode,
+    },
+    rules::TimeOfDay,
+    toast::Toast,
+};
+use std::ffi::c_void;
+use windows::core::GUID;
+use windows::Win32::{
+    Foundation::{CloseHandle, LocalFree, HLOCAL},
+    System::{
+        Diagnostics::ToolHelp::{
+            CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+            TH32CS_SNAPPROCESS,
+        },
+        Power::{PowerGetActiveScheme, PowerSetActiveScheme},
+        SystemInformation::GetLocalTime,
+    },
+};
+
+#[derive(Clone, Copy)]
+pub struct Win32Backend;
+
+impl PowerBackend for Win32Backend {
+    fn set_active_scheme(&self, scheme: &PowerScheme) -> Result<()> {
+        unsafe {
+            PowerSetActiveScheme(None, Some(&scheme.to_guid())).ok()?;
+        }
+        Ok(())
+    }
+
+    fn get_active_scheme(&self) -> Result<String> {
+        let mut guid: *mut GUID = std::ptr::null_mut();
+        unsafe {
+            PowerGetActiveScheme(None, &mut guid).ok()?;
+            let active = format!("{:?}", *guid).to_ascii_lowercase();
+            LocalFree(HLOCAL(guid as *mut c_void));
+            Ok(active)
+        }
+    }
+}
+
+impl DisplayBackend for Win32Backend {
+    fn get_monitors(&self) -> Result<Vec<Monitor>> {
+        get_monitors()
+    }
+
+    fn get_modes(&self, monitor: &Monitor) -> Result<(Vec<ModeInfo>, ModeInfo)> {
+        get_supported_modes(monitor)
+    }
+
+    fn set_mode(&self, monitor: &Monitor, mode: &ModeInfo) -> Result<()> {
+        set_display_mode(monitor, mode)
+    }
+
+    fn save_mode(&self, monitor: &Monitor) -> Result<SavedMode> {
+        save_display_mode(monitor)
+    }
+
+    fn restore_mode(&self, monitor: &Monitor, mode: &SavedMode) -> Result<()> {
+        restore_display_mode(monitor, mode)
+    }
+}
+
+impl NotificationBackend for Win32Backend {
+    fn send_notification(&self, title: &str, description: &str) -> Result<()> {
+        Toast::new(title, description).send()
+    }
+}
+
+impl SystemInfoBackend for Win32Backend {
+    fn get_local_time(&self) -> Result<TimeOfDay> {
+        let time = unsafe { GetLocalTime() };
+        TimeOfDay::new(time.wHour as u8, time.wMinute as u8)
+    }
+
+    fn has_external_display(&self) -> Result<bool> {
+        Ok(get_monitors()?.iter().any(|x| !x.internal))
+    }
+
+    fn get_running_processes(&self) -> Result<Vec<String>> {
+        let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0)? };
+        let mut entry = PROCESSENTRY32W {
+            dwSize: size_of::<PROCESSENTRY32W>() as u32,
+            ..Default::default()
+        };
+
+        let mut processes = vec![];
+        let mut next = unsafe { Process32FirstW(snapshot, &mut entry) };
+        while next.is_ok() {
+            let len = entry
+                .szExeFile
+                .iter()
+                .position(|x| *x == 0)
+                .unwrap_or(entry.szExeFile.len());
+            processes.push(String::from_utf16_lossy(&entry.szExeFile[..len]));
+            next = unsafe { Process32NextW(snapshot, &mut entry) };
+        }
+        unsafe { CloseHandle(snapshot)? };
+        Ok(processes)
+    }
+}
@@ -0,0 +1,48 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A profile that applies while on battery power, and the battery is charged at least `min_percent`.
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[schemars(deny_unknown_fields)]
+pub struct BatteryTier {
+    pub(super) min_percent: u8,
+    pub(super) profile: String,
+}
+
+/// The profile that was picked, and the battery tier that picked it, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    pub profile: String,
+    pub tier: Option<usize>,
+}
+
+impl Selection {
+    pub fn profile(name: &str) -> Self {
+        Self {
+            profile: name.to_owned(),
+            tier: None,
+        }
+    }
+}
+
+/// Picks the tier for the battery percentage. `current` is the active tier, if any.
+/// Falling below a boundary switches tiers right away, but rising above one only does once the
+/// battery is `hysteresis` percent past it, so the profile does not flap while charging.
+pub(super) fn select_tier(
+    tiers: &[BatteryTier],
+    hysteresis: u8,
+    percent: u8,
+    current: Option<usize>,
+) -> Option<usize> {
+    let candidate = tiers.iter().position(|x| percent >= x.min_percent)?;
+    match current {
+        Some(current) if candidate < current && current < tiers.len() => Some(
+            tiers
+                .iter()
+                .position(|x| percent >= x.min_percent.saturating_add(hysteresis))
+                .filter(|x| *x < current)
+                .unwrap_or(current),
+        ),
+        _ => Some(candidate),
+    }
+}
@@ -0,0 +1,360 @@
+//! Changes made to the config files from the command line.
+//! A change is validated together with the other layers before anything is written.
+
+use super::{
+    format::{self, ConfigFormat},
+    layers::{self, ConfigDirs, Layer, Scope},
+    validation::{self, KeyPath, Locations, Segment},
+    PowerConfig, CONFIG_NAME,
+};
+use serde_json::{json, Value};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+type Result<T> = crate::Result<T>;
+
+/// The inline configs of version 1, which now stand for the profiles bound to each power source.
+const LEGACY_ALIASES: [(&str, &str); 2] = [
+    ("wired_config", "wired_profile"),
+    ("battery_config", "battery_profile"),
+];
+
+/// Keys whose values are a refresh rate mode, where a bare number is short for `Value`.
+const REFRESH_RATE_KEYS: [&str; 2] = ["screen_refresh_rate", "refresh_rate"];
+
+/// Validates a single config file over the built-in defaults, without the other layers.
+pub fn validate_file(path: &Path) -> Result<()> {
+    validation::build(&[Layer::defaults(), Layer::read_file(path, Scope::Machine)?])?;
+    Ok(())
+}
+
+/// Reads the value of an assignment as YAML, so JSON works too, and plain words are strings.
+fn parse_value(text: &str) -> Result<Value> {
+    let (value, _) = ConfigFormat::Yaml
+        .parse(text)
+        .map_err(|e| format!("Could not read the value {}!\n{}", text, e.message))?;
+    Ok(value)
+}
+
+/// Turns `battery_config.x` into `profiles.<battery_profile>.x`, with the profile the layers bind
+/// now.
+fn resolve_alias(key: KeyPath, layers: &[Layer]) -> Result<KeyPath> {
+    let Some((Segment::Key(first), rest)) = key.segments().split_first() else {
+        return Ok(key);
+    };
+    let Some((_, binding)) = LEGACY_ALIASES.iter().find(|(x, _)| x == first) else {
+        return Ok(key);
+    };
+    let (merged, _) = layers::merge(layers);
+    let profile = merged
+        .get(binding)
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("{} does not name a profile!", binding))?;
+    Ok([
+        Segment::Key("profiles".to_owned()),
+        Segment::Key(profile.to_owned()),
+    ]
+    .into_iter()
+    .chain(rest.iter().cloned())
+    .collect())
+}
+
+/// Writes the value in the form the config expects, such as `60` as `{ "Value": 60 }`.
+fn expand_value(key: &KeyPath, value: Value) -> Value {
+    match key.segments().last() {
+        Some(Segment::Key(name))
+            if REFRESH_RATE_KEYS.contains(&name.as_str()) && value.is_u64() =>
+        {
+            json!({ "Value": value })
+        }
+        _ => value,
+    }
+}
+
+/// Sets a value from an assignment like `profiles.Battery.power_scheme=PowerSaver` in the
+/// config file of the scope, creating it if needed. `wired_config` and `battery_config` still
+/// work, and change the bound profiles. Returns the path of the file.
+pub fn set(dirs: &ConfigDirs, scope: Scope, assignment: &str) -> Result<PathBuf> {
+    let (key, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| format!("Expected key=value, got {}!", assignment))?;
+    let key: KeyPath = key.trim().parse()?;
+    let value = parse_value(value)?;
+
+    let dir = dirs.get(scope)?;
+    let path = format::find_config(dir, CONFIG_NAME)?;
+    let mut layers = layers::read_layers(dirs)?;
+    let key = resolve_alias(key, &layers)?;
+    let value = expand_value(&key, value);
+    let index = match layers.iter().position(|x| x.source.path() == Some(&path)) {
+        Some(index) => index,
+        None => {
+            // The machine config goes below the user config.
+            let index = match scope {
+                Scope::Machine => 1,
+                Scope::User => layers.len(),
+            };
+            layers.insert(index, Layer::empty(path.clone()));
+            index
+        }
+    };
+
+    let layer = &mut layers[index];
+    format::set_value(&mut layer.value, &key, value.clone())?;
+    // The positions in the file no longer match once it is changed.
+    layer.locations = Locations::default();
+    validation::build(&layers)?;
+
+    let layer = &layers[index];
+    let format = PowerConfig::get_format(&path)?;
+    let text = match fs::read_to_string(&path) {
+        // Only the key changes, unless the whole file has to be migrated.
+        Ok(text) if !layer.migrated => format
+            .edit(&text, &key, value)
+            .map_err(|e| format!("Could not change {} in {}!\n{}", key, path.display(), e))?,
+        Ok(_) => {
+            PowerConfig::backup(&path)?;
+            format.serialize(&layer.value)?
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => format.serialize(&layer.value)?,
+        Err(e) => return Err(format!("Could not read {}!\n{}", path.display(), e).into()),
+    };
+    fs::create_dir_all(dir).map_err(|e| format!("Could not create {}!\n{}", dir.display(), e))?;
+    PowerConfig::write(&path, &text)
+        .map_err(|e| format!("Could not write {}!\n{}", path.display(), e))?;
+    Ok(path)
+}
+
+/// Writes the defaults to the machine config, or removes the user config so only the machine
+/// config applies. Returns the path of the backup, if one was made.
+pub fn reset(dirs: &ConfigDirs, scope: Scope, backup: bool) -> Result<Option<PathBuf>> {
+    let path = format::find_config(dirs.get(scope)?, CONFIG_NAME)?;
+    let backup_path = match backup && path.exists() {
+        true => Some(PowerConfig::backup(&path)?),
+        false => None,
+    };
+    match scope {
+        Scope::Machine => PowerConfig::write_defaults(&path)
+            .map_err(|e| format!("Could not write {}!\n{}", path.display(), e))?,
+        Scope::User if path.exists() => fs::remove_file(&path)
+            .map_err(|e| format!("Could not remove {}!\n{}", path.display(), e))?,
+        Scope::User => (),
+    }
+    Ok(backup_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{Effective, PowerScheme},
+        display::RefreshRateMode,
+    };
+
+    fn dirs(dir: &Path) -> ConfigDirs {
+        ConfigDirs {
+            machine: dir.join("machine"),
+            user: Some(dir.join("user")),
+        }
+    }
+
+    fn battery_scheme(dirs: &ConfigDirs) -> PowerScheme {
+        let effective = Effective::read(dirs).unwrap();
+        let battery = effective.config.get_profile("Battery").unwrap();
+        battery.power_scheme.clone()
+    }
+
+    #[test]
+    fn values_are_set_in_the_scope() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = dirs(dir.path());
+        fs::create_dir(&dirs.machine).unwrap();
+
+        let path = set(
+            &dirs,
+            Scope::User,
+            "profiles.Battery.power_scheme=PowerSaver",
+        )
+        .unwrap();
+        assert_eq!(path, dirs.user.as_ref().unwrap().join("config.json"));
+        assert_eq!(battery_scheme(&dirs), PowerScheme::PowerSaver);
+
+        set(
+            &dirs,
+            Scope::Machine,
+            "profiles.Battery.power_scheme={ Custom: 381b4222-f694-41f0-9685-ff5bb260df2e }",
+        )
+        .unwrap();
+        // The user config still wins.
+        assert_eq!(battery_scheme(&dirs), PowerScheme::PowerSaver);
+
+        reset(&dirs, Scope::User, false).unwrap();
+        assert_eq!(
+            battery_scheme(&dirs),
+            PowerScheme::Custom("381b4222-f694-41f0-9685-ff5bb260df2e".to_owned())
+        );
+    }
+
+    #[test]
+    fn legacy_keys_and_bare_refresh_rates_are_accepted() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = dirs(dir.path());
+        fs::create_dir(&dirs.machine).unwrap();
+        let refresh_rate = |dirs: &ConfigDirs, profile: &str| {
+            let effective = Effective::read(dirs).unwrap();
+            let profile = effective.config.get_profile(profile).unwrap();
+            profile.screen_refresh_rate.clone()
+        };
+
+        set(
+            &dirs,
+            Scope::Machine,
+            "battery_config.screen_refresh_rate=60",
+        )
+        .unwrap();
+        assert_eq!(refresh_rate(&dirs, "Battery"), RefreshRateMode::Value(60));
+
+        set(
+            &dirs,
+            Scope::Machine,
+            "profiles.Battery.screen_refresh_rate=75",
+        )
+        .unwrap();
+        assert_eq!(refresh_rate(&dirs, "Battery"), RefreshRateMode::Value(75));
+
+        // The alias follows the binding.
+        set(&dirs, Scope::Machine, "wired_profile=Battery").unwrap();
+        set(
+            &dirs,
+            Scope::Machine,
+            "wired_config.screen_refresh_rate=Max",
+        )
+        .unwrap();
+        assert_eq!(refresh_rate(&dirs, "Battery"), RefreshRateMode::Max);
+        assert_eq!(refresh_rate(&dirs, "Wired"), RefreshRateMode::Max);
+        set(&dirs, Scope::Machine, "wired_profile=Wired").unwrap();
+        set(
+            &dirs,
+            Scope::Machine,
+            "wired_config.screen_refresh_rate=Min",
+        )
+        .unwrap();
+        assert_eq!(refresh_rate(&dirs, "Wired"), RefreshRateMode::Min);
+    }
+
+    #[test]
+    fn invalid_values_are_not_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = dirs(dir.path());
+        fs::create_dir(&dirs.machine).unwrap();
+        let path = dirs.machine.join("config.toml");
+        let original = "version = 2\nwired_profile = \"Battery\"\n";
+        fs::write(&path, original).unwrap();
+
+        for assignment in [
+            "wired_profile=Gaming",
+            "battery_hysteresis=three",
+            "battery_tiers[0].profile=Battery",
+            "wired_profile",
+        ] {
+            assert!(
+                set(&dirs, Scope::Machine, assignment).is_err(),
+                "{}",
+                assignment
+            );
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+
+        set(&dirs, Scope::Machine, "battery_hysteresis=5").unwrap();
+        let effective = Effective::read(&dirs).unwrap();
+        assert_eq!(effective.config.battery_hysteresis, 5);
+        assert_eq!(effective.config.wired_profile, "Battery");
+    }
+
+    #[test]
+    fn toml_comments_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = dirs(dir.path());
+        fs::create_dir(&dirs.machine).unwrap();
+        let path = dirs.machine.join("config.toml");
+        let original = "# Shared by the team.\nversion = 2\n\n[profiles.Battery]\n# Our panels flicker below 75.\nscreen_refresh_rate = { Value = 60 }\n";
+        fs::write(&path, original).unwrap();
+
+        set(
+            &dirs,
+            Scope::Machine,
+            "profiles.Battery.screen_refresh_rate=75",
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            original.replace("60", "75")
+        );
+    }
+
+    #[test]
+    fn only_the_changed_key_is_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = dirs(dir.path());
+        fs::create_dir(&dirs.machine).unwrap();
+        let path = dirs.machine.join("config.json");
+        // Written by fleet tooling, without a version and in its own order.
+        let original = "{\n    \"wired_profile\": \"Battery\",\n    \"battery_hysteresis\": 4\n}\n";
+        fs::write(&path, original).unwrap();
+
+        set(&dirs, Scope::Machine, "battery_hysteresis=5").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            original.replace('4', "5")
+        );
+        set(&dirs, Scope::Machine, "battery_profile=Battery").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            original
+                .replace('4', "5")
+                .replace("5\n", "5,\n    \"battery_profile\": \"Battery\"\n")
+        );
+        let backups = fs::read_dir(&dirs.machine)
+            .unwrap()
+            .filter(|x| x.as_ref().unwrap().path().extension().unwrap() == "bak");
+        assert_eq!(backups.count(), 0);
+    }
+
+    #[test]
+    fn yaml_configs_with_comments_are_not_rewritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = dirs(dir.path());
+        fs::create_dir(&dirs.machine).unwrap();
+        let path = dirs.machine.join("config.yaml");
+        let original = "version: 2\nbattery_hysteresis: 4 # Settles slowly.\n";
+        fs::write(&path, original).unwrap();
+
+        let error = set(&dirs, Scope::Machine, "battery_hysteresis=5").unwrap_err();
+        assert!(error.to_string().contains("comments"), "{}", error);
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+
+        fs::write(&path, "version: 2\nbattery_hysteresis: 4\n").unwrap();
+        set(&dirs, Scope::Machine, "battery_hysteresis=5").unwrap();
+        let effective = Effective::read(&dirs).unwrap();
+        assert_eq!(effective.config.battery_hysteresis, 5);
+    }
+
+    #[test]
+    fn reset_keeps_a_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = dirs(dir.path());
+        fs::create_dir(&dirs.machine).unwrap();
+        let path = dirs.machine.join("config.json");
+        fs::write(&path, "{ \"version\": 2, \"wired_profile\": \"Battery\" }").unwrap();
+
+        let backup = reset(&dirs, Scope::Machine, true).unwrap().unwrap();
+        assert!(fs::read_to_string(backup).unwrap().contains("Battery"));
+        assert_eq!(
+            Effective::read(&dirs).unwrap().config.wired_profile,
+            "Wired"
+        );
+        validate_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+use crate::config::validation::{KeyPath, Locations, Position, Problem, Segment};
+use serde::Serialize;
+use serde_json::{ser::PrettyFormatter, Serializer, Value};
+use std::{iter::Peekable, str::Chars};
+
+type Result<T> = crate::Result<T>;
+
+/// Parses a JSON config, and finds where each key and array element starts.
+pub fn parse(text: &str) -> std::result::Result<(Value, Locations), Problem> {
+    let value = serde_json::from_str(text).map_err(|e| {
+        // The position is reported separately, so only keep the message itself.
+        let message = e.to_string();
+        let message = match message.rfind(" at line ") {
+            Some(end) => message[..end].to_owned(),
+            None => message,
+        };
+        Problem::syntax(
+            Position {
+                line: e.line(),
+                column: e.column(),
+            },
+            message,
+        )
+    })?;
+
+    let mut scanner = Scanner {
+        chars: text.chars().peekable(),
+        position: Position { line: 1, column: 1 },
+        path: KeyPath::default(),
+        locations: Locations::default(),
+    };
+    scanner.value();
+    Ok((value, scanner.locations))
+}
+
+/// Writes the value with the indentation and final newline of the original document. The keys
+/// keep their order, as long as the value was read from it.
+pub fn serialize_like(original: &str, value: &Value) -> Result<String> {
+    let indent = original
+        .lines()
+        .skip(1)
+        .map(|x| &x[..x.len() - x.trim_start().len()])
+        .find(|x| !x.is_empty())
+        .unwrap_or("  ");
+    let mut text = vec![];
+    let formatter = PrettyFormatter::with_indent(indent.as_bytes());
+    value.serialize(&mut Serializer::with_formatter(&mut text, formatter))?;
+    let mut text = String::from_utf8(text)?;
+    if original.ends_with('\n') {
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+/// Only meant for documents that already parsed, so it does not report syntax errors itself.
+struct Scanner<'a> {
+    chars: Peekable<Chars<'a>>,
+    position: Position,
+    path: KeyPath,
+    locations: Locations,
+}
+
+impl Scanner<'_> {
+    fn bump(&mut self) -> Option<char> {
+        let next = self.chars.next()?;
+        if next == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(next)
+    }
+
+    fn skip_whitespace(&mut self) -> Option<char> {
+        while self.chars.peek()?.is_whitespace() {
+            self.bump();
+        }
+        self.chars.peek().copied()
+    }
+
+    fn value(&mut self) {
+        match self.skip_whitespace() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => {
+                self.string();
+            }
+            Some(_) => {
+                while self
+                    .chars
+                    .peek()
+                    .is_some_and(|x| !matches!(x, ',' | '}' | ']') && !x.is_whitespace())
+                {
+                    self.bump();
+                }
+            }
+            None => (),
+        }
+    }
+
+    fn string(&mut self) -> String {
+        let mut string = String::new();
+        self.bump();
+        while let Some(next) = self.bump() {
+            match next {
+                '"' => break,
+                '\\' => match self.bump() {
+                    Some('u') => {
+                        let hex: String = (0..4).filter_map(|_| self.bump()).collect();
+                        let code = u32::from_str_radix(&hex, 16).ok();
+                        string.extend(code.and_then(char::from_u32));
+                    }
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some('r') => string.push('\r'),
+                    Some('b') => string.push('\u{8}'),
+                    Some('f') => string.push('\u{c}'),
+                    Some(x) => string.push(x),
+                    None => break,
+                },
+                x => string.push(x),
+            }
+        }
+        string
+    }
+
+    fn record(&mut self, segment: Segment, position: Position) {
+        self.path.push(segment);
+        self.locations.record(self.path.clone(), position);
+    }
+
+    fn object(&mut self) {
+        self.bump();
+        while let Some(next) = self.skip_whitespace() {
+            match next {
+                '}' => {
+                    self.bump();
+                    return;
+                }
+                '"' => {
+                    let position = self.position;
+                    let key = self.string();
+                    self.record(Segment::Key(key), position);
+                    if self.skip_whitespace() == Some(':') {
+                        self.bump();
+                    }
+                    self.value();
+                    self.path.pop();
+                }
+                _ => {
+                    self.bump();
+                }
+            }
+        }
+    }
+
+    fn array(&mut self) {
+        self.bump();
+        let mut index = 0;
+        while let Some(next) = self.skip_whitespace() {
+            match next {
+                ']' => {
+                    self.bump();
+                    return;
+                }
+                ',' => {
+                    self.bump();
+                }
+                _ => {
+                    self.record(Segment::Index(index), self.position);
+                    self.value();
+                    self.path.pop();
+                    index += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(segments: &[Segment]) -> KeyPath {
+        segments.iter().cloned().collect()
+    }
+
+    fn scan(text: &str) -> Locations {
+        parse(text).unwrap().1
+    }
+
+    fn at(line: usize, column: usize) -> Option<Position> {
+        Some(Position { line, column })
+    }
+
+    #[test]
+    fn keys_and_elements_are_located() {
+        let locations = scan("{\n  \"a\": { \"b\\\"c\": [1, {\"d\": \"}\"}] },\n  \"e\": null\n}");
+        let a = Segment::Key("a".to_owned());
+        let bc = Segment::Key("b\"c".to_owned());
+        assert_eq!(locations.find(&key(std::slice::from_ref(&a))), at(2, 3));
+        assert_eq!(locations.find(&key(&[a.clone(), bc.clone()])), at(2, 10));
+        assert_eq!(
+            locations.find(&key(&[a.clone(), bc.clone(), Segment::Index(1)])),
+            at(2, 22)
+        );
+        assert_eq!(
+            locations.find(&key(&[
+                a.clone(),
+                bc.clone(),
+                Segment::Index(1),
+                Segment::Key("d".to_owned())
+            ])),
+            at(2, 23)
+        );
+        assert_eq!(
+            locations.find(&key(&[Segment::Key("e".to_owned())])),
+            at(3, 3)
+        );
+    }
+
+    #[test]
+    fn missing_keys_use_their_parent() {
+        let locations = scan("{ \"a\": {} }");
+        let missing = key(&[Segment::Key("a".to_owned()), Segment::Key("b".to_owned())]);
+        assert_eq!(locations.find(&missing), at(1, 3));
+        assert_eq!(locations.find(&key(&[Segment::Key("c".to_owned())])), None);
+    }
+
+    #[test]
+    fn documents_keep_their_layout() {
+        let original = "{\n    \"b\": [\n        1\n    ],\n    \"a\": 2\n}\n";
+        let (mut value, _) = parse(original).unwrap();
+        value["a"] = 3.into();
+        assert_eq!(
+            serialize_like(original, &value).unwrap(),
+            original.replace('2', "3")
+        );
+    }
+
+    #[test]
+    fn duplicate_keys_are_found() {
+        let locations = scan("{ \"a\": 1,\n\"a\": 2, \"b\": 3 }");
+        assert_eq!(
+            locations.duplicates,
+            vec![(
+                key(&[Segment::Key("a".to_owned())]),
+                Position { line: 2, column: 1 }
+            )]
+        );
+    }
+}
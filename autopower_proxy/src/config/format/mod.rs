@@ -0,0 +1,189 @@
+mod json;
+mod toml;
+mod yaml;
+
+use super::validation::{KeyPath, Locations, Problem, Segment};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+type Result<T> = crate::Result<T>;
+
+/// The file formats the config can be written in, picked by the file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub const ALL: [Self; 3] = [Self::Json, Self::Toml, Self::Yaml];
+
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Self::Json => &["json"],
+            Self::Toml => &["toml"],
+            Self::Yaml => &["yaml", "yml"],
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|x| x.extensions().contains(&extension.as_str()))
+    }
+
+    /// Parses the document into a JSON value, so migrations and validation work the same for
+    /// every format. Also returns where each key is, for reporting problems.
+    pub fn parse(self, text: &str) -> std::result::Result<(Value, Locations), Problem> {
+        match self {
+            Self::Json => json::parse(text),
+            Self::Toml => toml::parse(text),
+            Self::Yaml => yaml::parse(text),
+        }
+    }
+
+    pub fn serialize(self, value: &impl Serialize) -> Result<String> {
+        Ok(match self {
+            Self::Json => serde_json::to_string_pretty(value)?,
+            Self::Toml => ::toml::to_string_pretty(value)?,
+            Self::Yaml => yaml::serialize(serde_json::to_value(value)?)?,
+        })
+    }
+
+    /// Sets one key in the document, and keeps the order and layout of the others where the
+    /// format allows it. TOML keeps its comments, while YAML comments can not be kept, so
+    /// documents with them are left for editing by hand.
+    pub fn edit(self, text: &str, key: &KeyPath, value: Value) -> Result<String> {
+        match self {
+            Self::Toml => return toml::edit(text, key, &value),
+            Self::Yaml if yaml::has_comments(text) => {
+                return Err("Its comments would be lost, so change it by hand instead!".into())
+            }
+            Self::Json | Self::Yaml => (),
+        }
+        // The keys keep the order they were read in, so only the changed one moves.
+        let (mut document, _) = self.parse(text).map_err(|e| e.message)?;
+        set_value(&mut document, key, value)?;
+        match self {
+            Self::Json => json::serialize_like(text, &document),
+            _ => self.serialize(&document),
+        }
+    }
+}
+
+/// Sets the value at the key, adding the objects leading to it if needed.
+pub fn set_value(root: &mut Value, key: &KeyPath, value: Value) -> Result<()> {
+    let mut target = root;
+    for (depth, segment) in key.segments().iter().enumerate() {
+        let parent = || key.segments()[..depth].iter().cloned().collect::<KeyPath>();
+        target = match segment {
+            Segment::Key(name) => {
+                if target.is_null() {
+                    *target = Value::Object(Map::new());
+                }
+                target
+                    .as_object_mut()
+                    .ok_or_else(|| format!("{} is not an object!", parent()))?
+                    .entry(name)
+                    .or_insert(Value::Null)
+            }
+            Segment::Index(index) => target
+                .as_array_mut()
+                .and_then(|x| x.get_mut(*index))
+                .ok_or_else(|| format!("{} has no element {}!", parent(), index))?,
+        };
+    }
+    *target = value;
+    Ok(())
+}
+
+fn candidates<'a>(dir: &'a Path, name: &'a str) -> impl Iterator<Item = PathBuf> + 'a {
+    ConfigFormat::ALL
+        .into_iter()
+        .flat_map(ConfigFormat::extensions)
+        .map(move |x| dir.join(format!("{}.{}", name, x)))
+}
+
+/// Returns the config file called `name` in the directory, which may be in any format.
+/// Without one, this is where a new JSON config goes.
+pub fn find_config(dir: &Path, name: &str) -> Result<PathBuf> {
+    let found: Vec<_> = candidates(dir, name).filter(|x| x.exists()).collect();
+    match found.as_slice() {
+        [] => Ok(dir.join(format!("{}.json", name))),
+        [path] => Ok(path.clone()),
+        _ => Err(format!(
+            "Found more than one config file, remove all but one of them!\n{}",
+            found
+                .iter()
+                .map(|x| x.display().to_string())
+                .collect::<Vec<_>>()
+                .join("\n")
+        )
+        .into()),
+    }
+}
+
+/// Whether the file name is one [`find_config`] looks for.
+pub fn is_config_name(file_name: &OsStr, name: &str) -> bool {
+    candidates(Path::new(""), name).any(|x| x.as_os_str() == file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PowerConfig;
+    use std::fs;
+
+    #[test]
+    fn formats_are_detected_by_extension() {
+        let format = |x| ConfigFormat::from_path(Path::new(x));
+        assert_eq!(format("config.json"), Some(ConfigFormat::Json));
+        assert_eq!(format("config.TOML"), Some(ConfigFormat::Toml));
+        assert_eq!(format("config.yml"), Some(ConfigFormat::Yaml));
+        assert_eq!(format("config.ini"), None);
+        assert_eq!(format("config"), None);
+    }
+
+    #[test]
+    fn only_one_config_may_exist() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            find_config(dir.path(), "config").unwrap(),
+            dir.path().join("config.json")
+        );
+
+        fs::write(dir.path().join("config.yml"), "").unwrap();
+        assert_eq!(
+            find_config(dir.path(), "config").unwrap(),
+            dir.path().join("config.yml")
+        );
+
+        fs::write(dir.path().join("config.toml"), "").unwrap();
+        let error = find_config(dir.path(), "config").unwrap_err().to_string();
+        assert!(error.contains("config.toml") && error.contains("config.yml"));
+    }
+
+    #[test]
+    fn config_names_are_recognized() {
+        assert!(is_config_name(OsStr::new("config.yaml"), "config"));
+        assert!(!is_config_name(OsStr::new("config.json.tmp"), "config"));
+        assert!(!is_config_name(OsStr::new("settings.json"), "config"));
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let config = PowerConfig::default();
+        let json = serde_json::to_value(&config).unwrap();
+        for format in ConfigFormat::ALL {
+            let text = format.serialize(&config).unwrap();
+            let (value, _) = format.parse(&text).unwrap();
+            assert_eq!(value, json, "{:?}:\n{}", format, text);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use crate::config::validation::{KeyPath, Locations, Position, Problem, Segment};
+use ::toml::de::{DeTable, DeValue, Error};
+use serde_json::Value;
+use toml_edit::{Array, DocumentMut, InlineTable, Item};
+
+type Result<T> = crate::Result<T>;
+
+pub fn parse(text: &str) -> std::result::Result<(Value, Locations), Problem> {
+    let syntax = |e: Error| {
+        let offset = e.span().map_or(0, |x| x.start);
+        Problem::syntax(Position::from_offset(text, offset), e.message().to_owned())
+    };
+    let value = ::toml::from_str(text).map_err(syntax)?;
+    let table = DeTable::parse(text).map_err(syntax)?;
+
+    let mut locations = Locations::default();
+    locate_table(
+        text,
+        table.get_ref(),
+        &mut KeyPath::default(),
+        &mut locations,
+    );
+    Ok((value, locations))
+}
+
+fn locate_table(text: &str, table: &DeTable, path: &mut KeyPath, locations: &mut Locations) {
+    for (key, value) in table.iter() {
+        path.push(Segment::Key(key.get_ref().to_string()));
+        locations.record(path.clone(), Position::from_offset(text, key.span().start));
+        locate_value(text, value.get_ref(), path, locations);
+        path.pop();
+    }
+}
+
+fn locate_value(text: &str, value: &DeValue, path: &mut KeyPath, locations: &mut Locations) {
+    match value {
+        DeValue::Table(table) => locate_table(text, table, path, locations),
+        DeValue::Array(array) => {
+            for (i, item) in array.iter().enumerate() {
+                path.push(Segment::Index(i));
+                locations.record(path.clone(), Position::from_offset(text, item.span().start));
+                locate_value(text, item.get_ref(), path, locations);
+                path.pop();
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Changes one key in the document itself, so its comments and layout are kept.
+pub fn edit(text: &str, key: &KeyPath, value: &Value) -> Result<String> {
+    let mut document: DocumentMut = text.parse()?;
+    let mut target = document.as_item_mut();
+    for (depth, segment) in key.segments().iter().enumerate() {
+        let parent = || key.segments()[..depth].iter().cloned().collect::<KeyPath>();
+        // Missing keys are added, and missing tables are added inline.
+        target = match segment {
+            Segment::Key(name) => target
+                .get_mut(name.as_str())
+                .ok_or_else(|| format!("{} is not a table!", parent()))?,
+            Segment::Index(index) => target
+                .get_mut(*index)
+                .ok_or_else(|| format!("{} has no element {}!", parent(), index))?,
+        };
+    }
+    let mut value = to_toml(value)?;
+    // Keeps a comment after the old value.
+    if let Some(old) = target.as_value() {
+        *value.decor_mut() = old.decor().clone();
+    }
+    *target = Item::Value(value);
+    Ok(document.to_string())
+}
+
+fn to_toml(value: &Value) -> Result<toml_edit::Value> {
+    Ok(match value {
+        Value::Null => return Err("TOML has no null values!".into()),
+        Value::Bool(x) => (*x).into(),
+        Value::Number(x) => match (x.as_i64(), x.as_f64()) {
+            (Some(x), _) => x.into(),
+            (None, Some(x)) => x.into(),
+            (None, None) => return Err(format!("{} is too large for TOML!", x).into()),
+        },
+        Value::String(x) => x.as_str().into(),
+        Value::Array(x) => x.iter().map(to_toml).collect::<Result<Array>>()?.into(),
+        Value::Object(x) => x
+            .iter()
+            .map(|(key, value)| Ok((key.as_str(), to_toml(value)?)))
+            .collect::<Result<InlineTable>>()?
+            .into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_located() {
+        let (_, locations) = parse(
+            "# Comments are fine.\nwired_profile = \"Wired\"\n\n[profiles.Wired]\npower_scheme = \"Balanced\"\n\n[[battery_tiers]]\nmin_percent = 20\n",
+        )
+        .unwrap();
+        let key = |x: &[Segment]| x.iter().cloned().collect::<KeyPath>();
+        let find = |x: &[Segment]| locations.find(&key(x)).unwrap();
+
+        assert_eq!(
+            find(&[Segment::Key("wired_profile".to_owned())]),
+            Position { line: 2, column: 1 }
+        );
+        assert_eq!(
+            find(&[
+                Segment::Key("profiles".to_owned()),
+                Segment::Key("Wired".to_owned()),
+                Segment::Key("power_scheme".to_owned())
+            ]),
+            Position { line: 5, column: 1 }
+        );
+        assert_eq!(
+            find(&[
+                Segment::Key("battery_tiers".to_owned()),
+                Segment::Index(0),
+                Segment::Key("min_percent".to_owned())
+            ]),
+            Position { line: 8, column: 1 }
+        );
+    }
+
+    #[test]
+    fn edits_keep_the_rest_of_the_document() {
+        let text = "# Team settings.\nwired_profile = \"Wired\" # Docked.\n\n[profiles.Battery]\n# Saves power.\nscreen_refresh_rate = \"Min\"\n";
+        let key = |x: &str| x.parse::<KeyPath>().unwrap();
+
+        let edited = edit(text, &key("wired_profile"), &Value::from("Battery")).unwrap();
+        assert_eq!(edited, text.replace("\"Wired\"", "\"Battery\""));
+
+        let edited = edit(
+            text,
+            &key("profiles.Battery.screen_refresh_rate"),
+            &serde_json::json!({ "Value": 75 }),
+        )
+        .unwrap();
+        assert!(edited.starts_with("# Team settings.\nwired_profile = \"Wired\" # Docked.\n"));
+        assert!(edited.contains("# Saves power.\nscreen_refresh_rate = { Value = 75 }\n"));
+
+        let edited = edit(
+            text,
+            &key("profiles.Battery.send_notification"),
+            &false.into(),
+        );
+        assert!(edited
+            .unwrap()
+            .ends_with("screen_refresh_rate = \"Min\"\nsend_notification = false\n"));
+        assert!(edit(text, &key("wired_profile.name"), &false.into()).is_err());
+    }
+
+    #[test]
+    fn syntax_errors_have_a_position() {
+        let problem = parse("wired_profile = \"Wired\"\nbattery_profile = \n").unwrap_err();
+        assert_eq!(problem.position.unwrap().line, 2);
+    }
+}
@@ -0,0 +1,257 @@
+use crate::config::validation::{KeyPath, Locations, Position, Problem, Segment};
+use serde_json::{Number, Value};
+use yaml_rust2::{
+    parser::{Event, Parser},
+    scanner::Marker,
+    Yaml, YamlEmitter, YamlLoader,
+};
+
+type Result<T> = crate::Result<T>;
+
+fn position(marker: &Marker) -> Position {
+    Position {
+        line: marker.line(),
+        column: marker.col() + 1,
+    }
+}
+
+pub fn parse(text: &str) -> std::result::Result<(Value, Locations), Problem> {
+    let documents = YamlLoader::load_from_str(text)
+        .map_err(|e| Problem::syntax(position(e.marker()), e.info().to_owned()))?;
+    let value = match documents.into_iter().next() {
+        Some(document) => to_json(document).map_err(|message| Problem {
+            path: None,
+            key: KeyPath::default(),
+            position: None,
+            message,
+        })?,
+        None => Value::Null,
+    };
+    Ok((value, locate(text)))
+}
+
+pub fn serialize(value: Value) -> Result<String> {
+    let mut text = String::new();
+    YamlEmitter::new(&mut text)
+        .dump(&from_json(value))
+        .map_err(|e| format!("Could not write YAML!\n{:?}", e))?;
+    text.push('\n');
+    Ok(text)
+}
+
+/// Whether the document has comments, which writing it again would lose. Errs on the side of
+/// finding one, such as a `#` in a block scalar.
+pub fn has_comments(text: &str) -> bool {
+    text.lines().any(|line| {
+        let mut quote = None;
+        let mut previous = ' ';
+        for next in line.chars() {
+            match (quote, next) {
+                (None, '#') if previous.is_whitespace() => return true,
+                (None, '"' | '\'') if previous.is_whitespace() || "[{,:".contains(previous) => {
+                    quote = Some(next)
+                }
+                (Some(x), _) if x == next => quote = None,
+                _ => (),
+            }
+            previous = next;
+        }
+        false
+    })
+}
+
+fn to_json(yaml: Yaml) -> std::result::Result<Value, String> {
+    Ok(match yaml {
+        Yaml::Real(x) => x
+            .parse()
+            .ok()
+            .and_then(Number::from_f64)
+            .map(Value::Number)
+            .ok_or_else(|| format!("{} is not a valid number!", x))?,
+        Yaml::Integer(x) => x.into(),
+        Yaml::String(x) => Value::String(x),
+        Yaml::Boolean(x) => Value::Bool(x),
+        Yaml::Null => Value::Null,
+        Yaml::Array(x) => Value::Array(
+            x.into_iter()
+                .map(to_json)
+                .collect::<std::result::Result<_, _>>()?,
+        ),
+        Yaml::Hash(x) => Value::Object(
+            x.into_iter()
+                .map(|(key, value)| Ok((to_key(key)?, to_json(value)?)))
+                .collect::<std::result::Result<_, String>>()?,
+        ),
+        Yaml::Alias(_) | Yaml::BadValue => return Err("Aliases are not supported!".to_owned()),
+    })
+}
+
+fn to_key(yaml: Yaml) -> std::result::Result<String, String> {
+    match yaml {
+        Yaml::String(x) | Yaml::Real(x) => Ok(x),
+        Yaml::Integer(x) => Ok(x.to_string()),
+        Yaml::Boolean(x) => Ok(x.to_string()),
+        x => Err(format!("{:?} can not be used as a key!", x)),
+    }
+}
+
+fn from_json(value: Value) -> Yaml {
+    match value {
+        Value::Null => Yaml::Null,
+        Value::Bool(x) => Yaml::Boolean(x),
+        Value::Number(x) => x
+            .as_i64()
+            .map_or_else(|| Yaml::Real(x.to_string()), Yaml::Integer),
+        Value::String(x) => Yaml::String(x),
+        Value::Array(x) => Yaml::Array(x.into_iter().map(from_json).collect()),
+        Value::Object(x) => Yaml::Hash(
+            x.into_iter()
+                .map(|(key, value)| (Yaml::String(key), from_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+enum Frame {
+    /// Mappings alternate between keys and values.
+    Mapping {
+        in_value: bool,
+    },
+    Sequence {
+        next: usize,
+    },
+}
+
+/// Follows the parser events to find where each key and sequence item starts.
+#[derive(Default)]
+struct Locator {
+    frames: Vec<Frame>,
+    path: KeyPath,
+    locations: Locations,
+}
+
+impl Locator {
+    /// Returns whether the node is a mapping key rather than a value.
+    fn start_node(&mut self, key: Option<String>, position: Position) -> bool {
+        let segment = match self.frames.last_mut() {
+            Some(Frame::Mapping { in_value }) if !*in_value => {
+                *in_value = true;
+                Segment::Key(key.unwrap_or_default())
+            }
+            Some(Frame::Sequence { next }) => {
+                *next += 1;
+                Segment::Index(*next - 1)
+            }
+            _ => return false,
+        };
+        let is_key = matches!(segment, Segment::Key(_));
+        self.path.push(segment);
+        self.locations.record(self.path.clone(), position);
+        is_key
+    }
+
+    /// A scalar or alias is a complete value unless it is a mapping key.
+    fn leaf(&mut self, key: Option<String>, position: Position) {
+        if !self.start_node(key, position) {
+            self.end_value();
+        }
+    }
+
+    fn end_value(&mut self) {
+        match self.frames.last_mut() {
+            Some(Frame::Mapping { in_value }) => {
+                *in_value = false;
+                self.path.pop();
+            }
+            Some(Frame::Sequence { .. }) => self.path.pop(),
+            None => (),
+        }
+    }
+}
+
+fn locate(text: &str) -> Locations {
+    let mut parser = Parser::new_from_str(text);
+    let mut locator = Locator::default();
+    while let Ok((event, marker)) = parser.next_token() {
+        let position = position(&marker);
+        match event {
+            Event::Scalar(value, ..) => locator.leaf(Some(value), position),
+            Event::Alias(_) => locator.leaf(None, position),
+            Event::MappingStart(..) => {
+                locator.start_node(None, position);
+                locator.frames.push(Frame::Mapping { in_value: false });
+            }
+            Event::SequenceStart(..) => {
+                locator.start_node(None, position);
+                locator.frames.push(Frame::Sequence { next: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                locator.frames.pop();
+                locator.end_value();
+            }
+            Event::StreamEnd => break,
+            _ => (),
+        }
+    }
+    locator.locations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(segments: &[Segment]) -> KeyPath {
+        segments.iter().cloned().collect()
+    }
+
+    #[test]
+    fn keys_and_items_are_located() {
+        let (value, locations) = parse(
+            "# Comments are fine.\nwired_profile: Wired\nprofiles:\n  Wired:\n    power_scheme: Balanced\nbattery_tiers:\n  - min_percent: 20\n    profile: Low\n",
+        )
+        .unwrap();
+        assert_eq!(value["battery_tiers"][0]["min_percent"], 20);
+
+        let find = |x: &[Segment]| locations.find(&key(x)).unwrap();
+        assert_eq!(
+            find(&[Segment::Key("wired_profile".to_owned())]),
+            Position { line: 2, column: 1 }
+        );
+        assert_eq!(
+            find(&[
+                Segment::Key("profiles".to_owned()),
+                Segment::Key("Wired".to_owned()),
+                Segment::Key("power_scheme".to_owned())
+            ]),
+            Position { line: 5, column: 5 }
+        );
+        assert_eq!(
+            find(&[
+                Segment::Key("battery_tiers".to_owned()),
+                Segment::Index(0),
+                Segment::Key("profile".to_owned())
+            ]),
+            Position { line: 8, column: 5 }
+        );
+    }
+
+    #[test]
+    fn comments_are_found() {
+        assert!(has_comments("# Why.\na: 1\n"));
+        assert!(has_comments("a: 1 # Why.\n"));
+        assert!(has_comments("a: 'it''s' # Why.\n"));
+        assert!(!has_comments("a: \"#1\"\nb: '# 2'\nc: C#\n"));
+    }
+
+    #[test]
+    fn duplicate_keys_are_rejected() {
+        let problem = parse("a: 1\nb: 2\na: 3\n").unwrap_err();
+        assert_eq!(problem.position.unwrap().line, 3);
+    }
+
+    #[test]
+    fn syntax_errors_have_a_position() {
+        let problem = parse("a: [1, 2\nb: 3\n").unwrap_err();
+        assert!(problem.position.is_some());
+    }
+}
@@ -0,0 +1,410 @@
+use super::{
+    format, migration,
+    validation::{self, KeyPath, Locations, Segment},
+    PowerConfig, CONFIG_NAME,
+};
+use autopower_shared::util::get_process_exe_path;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+type Result<T> = crate::Result<T>;
+
+/// The directories config files are read from, from the lowest to the highest priority.
+/// Both are layered over the built-in defaults.
+#[derive(Debug, Clone)]
+pub struct ConfigDirs {
+    /// Next to the executable, shared by every user on the machine.
+    pub machine: PathBuf,
+    /// In the user's profile, so each user can override the machine config without admin rights.
+    pub user: Option<PathBuf>,
+}
+
+impl ConfigDirs {
+    pub fn platform() -> Result<Self> {
+        let exe = get_process_exe_path()
+            .map_err(|e| format!("Could not get the executable path!\n{}", e))?;
+        Ok(Self {
+            machine: exe.with_file_name(""),
+            user: get_user_config_dir(),
+        })
+    }
+
+    pub fn get(&self, scope: Scope) -> Result<&Path> {
+        match scope {
+            Scope::Machine => Ok(&self.machine),
+            Scope::User => self
+                .user
+                .as_deref()
+                .ok_or_else(|| "Could not find the user's config directory!".into()),
+        }
+    }
+
+    /// Where the proxy keeps its own files. Only the user's directory is writable for it.
+    pub fn state(&self) -> Result<&Path> {
+        self.user.as_deref().ok_or_else(|| {
+            "Could not find the user's config directory to keep the proxy's files in!".into()
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.machine.as_path()).chain(self.user.as_deref())
+    }
+}
+
+/// Which config file a change is written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Machine,
+    User,
+}
+
+#[cfg(windows)]
+fn get_user_config_dir() -> Option<PathBuf> {
+    let app_data = std::env::var_os("APPDATA")?;
+    Some(PathBuf::from(app_data).join("AutoPower"))
+}
+
+#[cfg(not(windows))]
+fn get_user_config_dir() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".config")))?;
+    Some(config_home.join("autopower"))
+}
+
+/// Where a value of the effective config was set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+}
+
+impl Source {
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Default => None,
+            Self::File(path) => Some(path),
+        }
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Default => f.write_str("built-in default"),
+            Self::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// One config document, migrated to the current version. It is only validated once merged,
+/// since a layer usually only sets some of the keys.
+#[derive(Debug)]
+pub struct Layer {
+    pub source: Source,
+    pub value: Value,
+    pub locations: Locations,
+    /// Whether the file was written for an older version, and should be saved again.
+    pub migrated: bool,
+}
+
+impl Layer {
+    pub fn defaults() -> Self {
+        Self {
+            source: Source::Default,
+            value: serde_json::to_value(PowerConfig::default())
+                .expect("the default config is always serializable"),
+            locations: Locations::default(),
+            migrated: false,
+        }
+    }
+
+    /// A file that does not exist yet, which changes nothing.
+    pub fn empty(path: PathBuf) -> Self {
+        Self {
+            source: Source::File(path),
+            value: json!({ "version": migration::CURRENT_VERSION }),
+            locations: Locations::default(),
+            migrated: false,
+        }
+    }
+
+    /// Returns [`None`] if there is no config file in the directory.
+    pub fn read(dir: &Path, scope: Scope) -> Result<Option<Self>> {
+        let path = format::find_config(dir, CONFIG_NAME)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        Self::read_file(&path, scope).map(Some)
+    }
+
+    pub fn read_file(path: &Path, scope: Scope) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read the config at {}!\n{}", path.display(), e))?;
+        Ok(validation::parse_layer(
+            path,
+            PowerConfig::get_format(path)?,
+            &text,
+            scope,
+        )?)
+    }
+}
+
+/// Which layer set each key of the merged document.
+/// Keys that are not recorded come from the same layer as their closest parent.
+#[derive(Debug, Default)]
+pub struct Origins(HashMap<KeyPath, usize>);
+
+impl Origins {
+    fn set(&mut self, key: &KeyPath, layer: usize) {
+        // The whole value was replaced, so nothing below it comes from an older layer anymore.
+        self.0.retain(|x, _| !x.starts_with(key));
+        self.0.insert(key.clone(), layer);
+    }
+
+    pub fn find(&self, key: &KeyPath) -> usize {
+        key.ancestors()
+            .find_map(|x| self.0.get(&x))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Keys whose values are enums. Their objects name a variant, and are replaced as a whole,
+/// since merging two variants would make an invalid value.
+const ENUM_KEYS: [&str; 4] = [
+    "power_scheme",
+    "screen_refresh_rate",
+    "refresh_rate",
+    "resolution",
+];
+
+/// Merges the layers from the lowest to the highest priority. Objects are merged key by key,
+/// and any other value replaces the one below it, including arrays and enums.
+pub fn merge(layers: &[Layer]) -> (Value, Origins) {
+    let mut merged = Value::Object(Default::default());
+    let mut origins = Origins::default();
+    for (index, layer) in layers.iter().enumerate() {
+        merge_value(
+            &mut merged,
+            &layer.value,
+            index,
+            &mut KeyPath::default(),
+            &mut origins,
+        );
+    }
+    (merged, origins)
+}
+
+fn merge_value(
+    target: &mut Value,
+    layer: &Value,
+    index: usize,
+    key: &mut KeyPath,
+    origins: &mut Origins,
+) {
+    match (target, layer) {
+        (Value::Object(target), Value::Object(layer)) if !is_enum(key) => {
+            for (name, value) in layer {
+                key.push(Segment::Key(name.clone()));
+                match target.get_mut(name) {
+                    Some(existing) => merge_value(existing, value, index, key, origins),
+                    None => {
+                        target.insert(name.clone(), value.clone());
+                        origins.set(key, index);
+                    }
+                }
+                key.pop();
+            }
+        }
+        (target, layer) => {
+            *target = layer.clone();
+            origins.set(key, index);
+        }
+    }
+}
+
+fn is_enum(key: &KeyPath) -> bool {
+    matches!(key.segments().last(), Some(Segment::Key(name)) if ENUM_KEYS.contains(&name.as_str()))
+}
+
+/// The config after merging every layer, which remembers where each value came from.
+#[derive(Debug)]
+pub struct Effective {
+    pub config: PowerConfig,
+    value: Value,
+    origins: Origins,
+    sources: Vec<Source>,
+}
+
+impl Effective {
+    pub fn new(config: PowerConfig, value: Value, origins: Origins, layers: &[Layer]) -> Self {
+        Self {
+            config,
+            value,
+            origins,
+            sources: layers.iter().map(|x| x.source.clone()).collect(),
+        }
+    }
+
+    /// Reads every layer without migrating or creating any file.
+    pub fn read(dirs: &ConfigDirs) -> Result<Self> {
+        let layers = read_layers(dirs)?;
+        Ok(validation::build(&layers)?)
+    }
+
+    /// Every value that is not an object, with the layer it was set in.
+    pub fn entries(&self) -> Vec<(KeyPath, &Value, &Source)> {
+        let mut entries = vec![];
+        self.collect_entries(&self.value, &mut KeyPath::default(), &mut entries);
+        entries
+    }
+
+    fn collect_entries<'a>(
+        &'a self,
+        value: &'a Value,
+        key: &mut KeyPath,
+        entries: &mut Vec<(KeyPath, &'a Value, &'a Source)>,
+    ) {
+        match value {
+            Value::Object(object) if !object.is_empty() => {
+                for (name, value) in object {
+                    key.push(Segment::Key(name.clone()));
+                    self.collect_entries(value, key, entries);
+                    key.pop();
+                }
+            }
+            _ => entries.push((key.clone(), value, &self.sources[self.origins.find(key)])),
+        }
+    }
+}
+
+/// The built-in defaults followed by every config file that exists.
+pub fn read_layers(dirs: &ConfigDirs) -> Result<Vec<Layer>> {
+    let mut layers = vec![Layer::defaults()];
+    layers.extend(Layer::read(&dirs.machine, Scope::Machine)?);
+    if let Some(user) = &dirs.user {
+        layers.extend(Layer::read(user, Scope::User)?);
+    }
+    Ok(layers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(path: &str, value: Value) -> Layer {
+        Layer {
+            source: Source::File(PathBuf::from(path)),
+            value,
+            locations: Locations::default(),
+            migrated: false,
+        }
+    }
+
+    #[test]
+    fn objects_merge_deeply_and_other_values_are_replaced() {
+        let layers = [
+            layer(
+                "machine",
+                json!({ "profiles": { "A": { "x": 1, "y": 2 } }, "tiers": [1, 2] }),
+            ),
+            layer(
+                "user",
+                json!({ "profiles": { "A": { "y": 3 }, "B": { "x": 4 } }, "tiers": [3] }),
+            ),
+        ];
+        let (merged, origins) = merge(&layers);
+        assert_eq!(
+            merged,
+            json!({
+                "profiles": { "A": { "x": 1, "y": 3 }, "B": { "x": 4 } },
+                "tiers": [3],
+            })
+        );
+
+        let origin = |x: KeyPath| origins.find(&x);
+        let a = KeyPath::default().key("profiles").key("A");
+        assert_eq!(origin(a.clone().key("x")), 0);
+        assert_eq!(origin(a.key("y")), 1);
+        assert_eq!(
+            origin(KeyPath::default().key("profiles").key("B").key("x")),
+            1
+        );
+        assert_eq!(origin(KeyPath::default().key("tiers").index(0)), 1);
+    }
+
+    #[test]
+    fn replaced_objects_forget_older_origins() {
+        let layers = [
+            layer("machine", json!({ "rules": { "a": 1 } })),
+            layer("user", json!({ "rules": [] })),
+            layer("other", json!({ "rules": { "b": 2 } })),
+        ];
+        let (merged, origins) = merge(&layers);
+        assert_eq!(merged, json!({ "rules": { "b": 2 } }));
+        assert_eq!(origins.find(&KeyPath::default().key("rules").key("a")), 2);
+    }
+
+    #[test]
+    fn enums_are_replaced_as_a_whole() {
+        let layers = [
+            layer(
+                "machine",
+                json!({ "screen_refresh_rate": { "Value": 60 }, "other": { "Value": 60 } }),
+            ),
+            layer(
+                "user",
+                json!({ "screen_refresh_rate": { "Ratio": 0.5 }, "other": { "Ratio": 0.5 } }),
+            ),
+        ];
+        let (merged, _) = merge(&layers);
+        assert_eq!(
+            merged,
+            json!({
+                "screen_refresh_rate": { "Ratio": 0.5 },
+                "other": { "Value": 60, "Ratio": 0.5 },
+            })
+        );
+    }
+
+    #[test]
+    fn entries_name_their_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = ConfigDirs {
+            machine: dir.path().join("machine"),
+            user: Some(dir.path().join("user")),
+        };
+        for dir in dirs.iter() {
+            fs::create_dir(dir).unwrap();
+        }
+        let user_path = dirs.user.as_ref().unwrap().join("config.toml");
+        fs::write(&user_path, "version = 2\nwired_profile = \"Battery\"\n").unwrap();
+
+        let effective = Effective::read(&dirs).unwrap();
+        assert_eq!(effective.config.wired_profile, "Battery");
+        let entries = effective.entries();
+        let source = |key: &KeyPath| {
+            entries
+                .iter()
+                .find(|(x, ..)| x == key)
+                .map(|(_, _, source)| (*source).clone())
+                .unwrap()
+        };
+        assert_eq!(
+            source(&KeyPath::default().key("wired_profile")),
+            Source::File(user_path)
+        );
+        assert_eq!(
+            source(&KeyPath::default().key("battery_profile")),
+            Source::Default
+        );
+        // Reading does not create a machine config.
+        assert!(fs::read_dir(&dirs.machine).unwrap().next().is_none());
+    }
+}
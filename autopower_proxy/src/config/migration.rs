@@ -0,0 +1,200 @@
+use serde_json::{Map, Value};
+
+type Result<T> = crate::Result<T>;
+
+/// The layout of `config.json` that [`super::PowerConfig`] reads and writes.
+pub const CURRENT_VERSION: u64 = 2;
+/// Machine configs written before versioning was added.
+pub const LEGACY_VERSION: u64 = 1;
+
+type Migration = fn(Map<String, Value>) -> Result<Map<String, Value>>;
+
+/// Upgrades a config of the version at the index by one version.
+const MIGRATIONS: [Migration; 1] = [migrate_v1];
+
+/// Returns the version of the config, which is `unversioned` if it does not name one.
+fn get_version(value: &Value, unversioned: u64) -> Result<u64> {
+    let config = value.as_object().ok_or("The config is not an object!")?;
+    match config.get("version") {
+        None => Ok(unversioned),
+        Some(version) => version
+            .as_u64()
+            .filter(|x| *x >= 1)
+            .ok_or_else(|| format!("{} is not a valid config version!", version).into()),
+    }
+}
+
+/// Runs every migration from the config's version up to the current one.
+pub fn migrate(value: Value, unversioned: u64) -> Result<Value> {
+    let version = get_version(&value, unversioned)?;
+    if version > CURRENT_VERSION {
+        return Err(format!(
+            "Config version {} is newer than the supported version {}!",
+            version, CURRENT_VERSION
+        )
+        .into());
+    }
+
+    let Value::Object(mut config) = value else {
+        unreachable!("get_version only accepts objects");
+    };
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        config = migration(config)
+            .map_err(|e| format!("Could not migrate config version {}!\n{}", from + 1, e))?;
+    }
+    config.insert("version".to_owned(), CURRENT_VERSION.into());
+    Ok(Value::Object(config))
+}
+
+/// Whether migrating changed more than the version, so the file is worth writing again.
+pub fn changes_content(original: &Value, migrated: &Value) -> bool {
+    let without_version = |value: &Value| {
+        let mut value = value.clone();
+        if let Some(x) = value.as_object_mut() {
+            x.remove("version");
+        }
+        value
+    };
+    without_version(original) != without_version(migrated)
+}
+
+/// Version 1 is the layout of 2.x and 3.0, with the wired and battery configs inline.
+/// Version 2 moves them into named profiles.
+fn migrate_v1(mut config: Map<String, Value>) -> Result<Map<String, Value>> {
+    if !config.contains_key("wired_config") && !config.contains_key("battery_config") {
+        return Ok(config);
+    }
+    let mut profiles = match config.remove("profiles") {
+        Some(Value::Object(x)) => x,
+        Some(_) => return Err("profiles is not an object!".into()),
+        None => Map::new(),
+    };
+
+    // Without an inline config, the binding is left to the built-in defaults.
+    let mut legacy_names: Vec<(&str, String)> = vec![];
+    for (inline, binding) in [
+        ("wired_config", "wired_profile"),
+        ("battery_config", "battery_profile"),
+    ] {
+        if let Some(value) = config.remove(inline) {
+            let name = add_profile(&mut profiles, value)?;
+            // Both would become the same profile, silently losing one of them.
+            if let Some((other, _)) = legacy_names.iter().find(|(_, x)| *x == name) {
+                return Err(format!(
+                    "{} and {} are both named {}! Rename one of them.",
+                    other, inline, name
+                )
+                .into());
+            }
+            config.entry(binding).or_insert(name.clone().into());
+            legacy_names.push((inline, name));
+        }
+    }
+
+    config.insert("profiles".to_owned(), Value::Object(profiles));
+    Ok(config)
+}
+
+/// Adds an inline config as a profile named after its `state_name`, and returns the name.
+fn add_profile(profiles: &mut Map<String, Value>, inline: Value) -> Result<String> {
+    let name = inline
+        .get("state_name")
+        .and_then(Value::as_str)
+        .ok_or("An inline config has no state_name!")?
+        .to_owned();
+    profiles.entry(name.clone()).or_insert(inline);
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn unversioned_configs_get_the_given_version() {
+        assert_eq!(get_version(&json!({}), LEGACY_VERSION).unwrap(), 1);
+        assert_eq!(get_version(&json!({}), CURRENT_VERSION).unwrap(), 2);
+        assert_eq!(
+            get_version(&json!({ "version": 2 }), LEGACY_VERSION).unwrap(),
+            2
+        );
+        for invalid in [
+            json!({ "version": "2" }),
+            json!({ "version": 0 }),
+            json!([]),
+        ] {
+            assert!(
+                get_version(&invalid, LEGACY_VERSION).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn inline_configs_become_profiles() {
+        let wired = json!({ "state_name": "Plugged in", "power_scheme": "HighPerformance" });
+        let battery = json!({ "state_name": "Unplugged", "power_scheme": "PowerSaver" });
+        let original = json!({ "wired_config": wired, "battery_config": battery });
+        let migrated = migrate(original.clone(), LEGACY_VERSION).unwrap();
+
+        assert_eq!(
+            migrated,
+            json!({
+                "version": CURRENT_VERSION,
+                "profiles": { "Plugged in": wired, "Unplugged": battery },
+                "wired_profile": "Plugged in",
+                "battery_profile": "Unplugged",
+            })
+        );
+        assert!(changes_content(&original, &migrated));
+    }
+
+    #[test]
+    fn configs_without_inline_configs_only_get_a_version() {
+        let original = json!({ "battery_hysteresis": 5 });
+        let migrated = migrate(original.clone(), LEGACY_VERSION).unwrap();
+        assert_eq!(
+            migrated,
+            json!({ "version": CURRENT_VERSION, "battery_hysteresis": 5 })
+        );
+        assert!(!changes_content(&original, &migrated));
+    }
+
+    #[test]
+    fn current_configs_are_unchanged() {
+        let config = json!({
+            "version": CURRENT_VERSION,
+            "profiles": {},
+            "wired_profile": "Wired",
+            "battery_profile": "Battery",
+        });
+        assert_eq!(migrate(config.clone(), LEGACY_VERSION).unwrap(), config);
+    }
+
+    #[test]
+    fn newer_configs_are_rejected() {
+        assert!(migrate(json!({ "version": CURRENT_VERSION + 1 }), LEGACY_VERSION).is_err());
+    }
+
+    #[test]
+    fn inline_configs_sharing_a_name_are_rejected() {
+        let config = json!({
+            "wired_config": { "state_name": "Default", "power_scheme": "HighPerformance" },
+            "battery_config": { "state_name": "Default", "power_scheme": "PowerSaver" },
+        });
+        let error = migrate(config, LEGACY_VERSION).unwrap_err().to_string();
+        assert!(
+            error.contains("wired_config and battery_config are both named Default!"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn inline_configs_need_a_name() {
+        let config = json!({ "wired_config": { "power_scheme": "Balanced" } });
+        assert!(migrate(config, LEGACY_VERSION).is_err());
+    }
+}
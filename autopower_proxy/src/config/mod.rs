@@ -1,82 +1,850 @@
+mod battery_tier;
+pub mod edit;
+mod format;
+mod layers;
+mod migration;
+mod plan;
 mod power_scheme;
 mod state_config;
+mod store;
+mod validation;
 
+pub use battery_tier::Selection;
+use battery_tier::{select_tier, BatteryTier};
+use format::ConfigFormat;
+pub use layers::{ConfigDirs, Effective, Scope, Source};
+pub use plan::{DisplayChange, DisplayPlan, Notification, Plan, PlannedScheme};
 pub use power_scheme::PowerScheme;
 use state_config::StateConfig;
+pub use store::ConfigStore;
 
-use crate::display::RefreshRateMode;
-use autopower_shared::{logging::Logger, util::get_process_exe_path};
+use crate::{
+    backend::Backends,
+    display::RefreshRateMode,
+    rules::{evaluate, Rule, SystemSnapshot},
+};
+use autopower_shared::{
+    logging::Logger,
+    proxy_command::{PowerConfigSelection, PowerState},
+};
+use schemars::{generate::SchemaSettings, JsonSchema};
 use serde::{Deserialize, Serialize};
 use std::{
-    fs::File,
-    io::{BufReader, BufWriter, Write},
+    collections::BTreeMap,
+    fs, io,
     path::{Path, PathBuf},
-    sync::LazyLock,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 static LOGGER: Logger = Logger::new("power_config", "autopower_proxy");
 
 type Result<T> = crate::Result<T>;
 
-static CACHED_CONFIG_PATH: LazyLock<PathBuf> = LazyLock::new(|| {
-    const CONFIG_FILE: &str = "config.json";
-    get_process_exe_path().unwrap().with_file_name(CONFIG_FILE)
-});
+/// The config file name without its extension, which picks the format.
+const CONFIG_NAME: &str = "config";
+/// Written next to the machine config, which refers to it with `$schema`.
+const SCHEMA_NAME: &str = "config.schema.json";
+
+const DEFAULT_BATTERY_HYSTERESIS: u8 = 3;
+const DEFAULT_WIRED_PROFILE: &str = "Wired";
+const DEFAULT_BATTERY_PROFILE: &str = "Battery";
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Older layouts are migrated by [`migration::migrate`] before deserializing.
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+// Unknown keys are reported by validation, so only editors reject them.
+#[schemars(
+    title = "AutoPower config",
+    description = "The profiles AutoPower switches between, and when each of them is used.",
+    deny_unknown_fields
+)]
 pub struct PowerConfig {
-    wired_config: StateConfig,
-    battery_config: StateConfig,
+    /// The JSON Schema editors use to complete and check the config.
+    #[serde(rename = "$schema", default, skip_serializing_if = "Option::is_none")]
+    schema: Option<String>,
+    version: u64,
+    profiles: BTreeMap<String, StateConfig>,
+    /// The profile used on wired power.
+    wired_profile: String,
+    /// Used on battery power when no tier matches, or the battery percentage is unknown.
+    battery_profile: String,
+    /// Ordered from the highest to the lowest `min_percent`.
+    #[serde(default)]
+    battery_tiers: Vec<BatteryTier>,
+    #[serde(default = "default_battery_hysteresis")]
+    battery_hysteresis: u8,
+    /// Checked before the power source. The first rule that matches picks the profile.
+    #[serde(default)]
+    rules: Vec<Rule>,
+}
+
+fn default_battery_hysteresis() -> u8 {
+    DEFAULT_BATTERY_HYSTERESIS
 }
 
 impl Default for PowerConfig {
     fn default() -> Self {
-        Self {
-            wired_config: StateConfig {
-                state_name: "Wired".to_owned(),
+        let profiles = [
+            StateConfig {
+                state_name: DEFAULT_WIRED_PROFILE.to_owned(),
                 power_scheme: PowerScheme::HighPerformance,
                 change_refresh_rate: true,
                 screen_refresh_rate: RefreshRateMode::Max,
+                resolution: None,
+                monitors: vec![],
                 send_notification: true,
             },
-            battery_config: StateConfig {
-                state_name: "Battery".to_owned(),
+            StateConfig {
+                state_name: DEFAULT_BATTERY_PROFILE.to_owned(),
                 power_scheme: PowerScheme::Balanced,
                 change_refresh_rate: true,
                 screen_refresh_rate: RefreshRateMode::Value(60),
+                resolution: None,
+                monitors: vec![],
                 send_notification: true,
             },
+        ];
+        Self {
+            schema: None,
+            version: migration::CURRENT_VERSION,
+            profiles: profiles
+                .into_iter()
+                .map(|x| (x.state_name.clone(), x))
+                .collect(),
+            wired_profile: DEFAULT_WIRED_PROFILE.to_owned(),
+            battery_profile: DEFAULT_BATTERY_PROFILE.to_owned(),
+            battery_tiers: vec![],
+            battery_hysteresis: DEFAULT_BATTERY_HYSTERESIS,
+            rules: vec![],
         }
     }
 }
 
 impl PowerConfig {
-    fn get(path: &Path) -> Result<Self> {
-        LOGGER.debug(format!("Reading power config at {}", path.display()));
-        let fs = File::open(path)?;
-        let buf = BufReader::new(fs);
-        serde_json::from_reader(buf).map_err(|e| e.into())
+    fn get_format(path: &Path) -> Result<ConfigFormat> {
+        ConfigFormat::from_path(path)
+            .ok_or_else(|| format!("{} is not a known config format!", path.display()).into())
+    }
+
+    /// The value may be a whole config or only the keys of one layer.
+    fn save(path: &Path, value: &impl Serialize) -> Result<()> {
+        let text = Self::get_format(path)?.serialize(value)?;
+        Self::write(path, &text)
+    }
+
+    /// Writes to a temporary file first, so the config is never seen half-written.
+    fn write(path: &Path, text: &str) -> Result<()> {
+        let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+        fs::write(&temp_path, text)?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Copies the config next to itself before it gets overwritten, and returns the copy's path.
+    /// Backups made within the same second are numbered, so none replaces another.
+    fn backup(path: &Path) -> Result<PathBuf> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut source = fs::File::open(path)
+            .map_err(|e| format!("Could not read {}!\n{}", path.display(), e))?;
+        for counter in 0.. {
+            let mut name = path.file_name().unwrap_or_default().to_owned();
+            match counter {
+                0 => name.push(format!(".{}.bak", timestamp)),
+                _ => name.push(format!(".{}.{}.bak", timestamp, counter)),
+            }
+            let backup_path = path.with_file_name(name);
+            let mut backup = match fs::File::create_new(&backup_path) {
+                Ok(x) => x,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(Self::backup_error(&backup_path, e)),
+            };
+            io::copy(&mut source, &mut backup).map_err(|e| Self::backup_error(&backup_path, e))?;
+            return Ok(backup_path);
+        }
+        unreachable!("one of the names is free")
+    }
+
+    fn backup_error(backup_path: &Path, error: io::Error) -> Box<dyn std::error::Error> {
+        format!(
+            "Could not back up the config to {}!\n{}",
+            backup_path.display(),
+            error
+        )
+        .into()
+    }
+
+    /// The JSON Schema of a complete config.
+    pub fn schema() -> String {
+        let schema = SchemaSettings::draft07()
+            .into_generator()
+            .into_root_schema_for::<Self>();
+        serde_json::to_string_pretty(&schema).expect("schemas are always serializable")
     }
 
-    fn new(path: &Path) -> Result<Self> {
+    /// Keeps the schema next to the machine config up to date with this version.
+    fn write_schema(dir: &Path) {
+        let path = dir.join(SCHEMA_NAME);
+        let schema = Self::schema();
+        if fs::read_to_string(&path).is_ok_and(|x| x == schema) {
+            return;
+        }
+        if let Err(e) = fs::write(&path, schema) {
+            LOGGER.error(format!(
+                "Could not write the config schema to {}!\n{}",
+                path.display(),
+                e
+            ));
+        }
+    }
+
+    /// Writes the defaults to the file. JSON configs also refer to the schema.
+    fn write_defaults(path: &Path) -> Result<()> {
+        let format = Self::get_format(path)?;
+        let config = Self {
+            schema: (format == ConfigFormat::Json).then(|| SCHEMA_NAME.to_owned()),
+            ..Self::default()
+        };
+        Self::save(path, &config)
+    }
+
+    /// Writes the defaults as the machine config, so there is a file to edit.
+    /// Without permission to write it, the defaults are still used.
+    fn create(path: &Path) {
         LOGGER.debug(format!("Writing new power config at {}", path.display()));
-        let new_config = PowerConfig::default();
-        let fs = File::create(path)?;
-        let mut buf = BufWriter::new(fs);
-        serde_json::to_writer_pretty(&mut buf, &new_config)?;
-        buf.flush()?;
-        Ok(new_config)
+        if let Err(e) = Self::write_defaults(path) {
+            LOGGER.error(format!(
+                "Could not create the config at {}!\n{}",
+                path.display(),
+                e
+            ));
+        }
     }
 
-    pub fn get_or_create() -> Result<Self> {
-        Self::get(&CACHED_CONFIG_PATH).or_else(|_| Self::new(&CACHED_CONFIG_PATH))
+    /// Reads and validates every layer, migrating older files after backing up the original.
+    /// Invalid configs are never overwritten.
+    fn load(dirs: &ConfigDirs) -> Result<Self> {
+        let machine_path = format::find_config(&dirs.machine, CONFIG_NAME)?;
+        if !machine_path.exists() {
+            Self::create(&machine_path);
+        }
+        Self::write_schema(&dirs.machine);
+
+        let layers = layers::read_layers(dirs)?;
+        let effective = validation::build(&layers)?;
+        for layer in layers.iter().filter(|x| x.migrated) {
+            let Some(path) = layer.source.path() else {
+                continue;
+            };
+            let backup_path = Self::backup(path)?;
+            Self::save(path, &layer.value)?;
+            LOGGER.debug(format!(
+                "Migrated {} to version {}, the original is at {}",
+                path.display(),
+                migration::CURRENT_VERSION,
+                backup_path.display()
+            ));
+        }
+        Ok(effective.config)
+    }
+
+    pub fn get_profile(&self, name: &str) -> Result<&StateConfig> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| format!("Profile {} does not exist!", name).into())
+    }
+
+    /// Whether the selection can change without a power event, see [`Session::recheck_rules`].
+    ///
+    /// [`Session::recheck_rules`]: crate::session::Session::recheck_rules
+    pub fn has_changing_rules(&self) -> bool {
+        self.rules.iter().any(|x| x.when.changes_over_time())
+    }
+
+    /// Takes a snapshot of what the rules check, for [`select`](Self::select).
+    pub fn collect_snapshot(&self, state: PowerState, backends: &Backends) -> SystemSnapshot {
+        SystemSnapshot::collect(state, &self.rules, backends.system.as_ref())
     }
 
-    pub fn get_wired_config(&self) -> &StateConfig {
-        &self.wired_config
+    /// Picks the profile for the snapshot, using the rules first and the power state otherwise.
+    /// `current` is the selection that is active now, which is kept while the battery hovers
+    /// around a tier boundary.
+    pub fn select(&self, snapshot: &SystemSnapshot, current: Option<&Selection>) -> Selection {
+        if let Some(profile) = evaluate(&self.rules, snapshot) {
+            if self.profiles.contains_key(profile) {
+                return Selection::profile(profile);
+            }
+            LOGGER.error(format!("Rule refers to unknown profile {}!", profile));
+        }
+        self.select_by_power(&snapshot.power, current)
     }
 
-    pub fn get_battery_config(&self) -> &StateConfig {
-        &self.battery_config
+    fn select_by_power(&self, state: &PowerState, current: Option<&Selection>) -> Selection {
+        if state.source == PowerConfigSelection::Wired {
+            return Selection::profile(&self.wired_profile);
+        }
+        let Some(percent) = state.battery_percent else {
+            return Selection::profile(&self.battery_profile);
+        };
+        let tier = select_tier(
+            &self.battery_tiers,
+            self.battery_hysteresis,
+            percent,
+            current.and_then(|x| x.tier),
+        );
+        match tier {
+            Some(tier) => Selection {
+                profile: self.battery_tiers[tier].profile.clone(),
+                tier: Some(tier),
+            },
+            None => Selection::profile(&self.battery_profile),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{BackendCall, Backends, FakeSystemInfo, RecordingBackend},
+        display::{ModeInfo, Monitor},
+        rules::{Conditions, TimeOfDay},
+    };
+
+    fn parse(text: &str) -> (PowerConfig, bool) {
+        let layer = validation::parse_layer(
+            Path::new("config.json"),
+            ConfigFormat::Json,
+            text,
+            Scope::Machine,
+        )
+        .unwrap();
+        let migrated = layer.migrated;
+        let effective = validation::build(&[layers::Layer::defaults(), layer]).unwrap();
+        (effective.config, migrated)
+    }
+
+    fn machine(dir: &Path) -> ConfigDirs {
+        ConfigDirs {
+            machine: dir.to_owned(),
+            user: None,
+        }
+    }
+
+    fn switching_to(name: &str) -> BackendCall {
+        BackendCall::SendNotification {
+            title: "AutoPower".to_owned(),
+            description: format!("Switching to {}", name),
+        }
+    }
+
+    fn set_mode(monitor: &str, width: u32, height: u32, frequency: u32) -> BackendCall {
+        BackendCall::SetMode {
+            monitor: monitor.to_owned(),
+            mode: ModeInfo {
+                width,
+                height,
+                bits_per_pixel: 32,
+                frequency,
+            },
+        }
+    }
+
+    fn set_refresh_rate(frequency: u32) -> BackendCall {
+        set_mode(r"\\.\DISPLAY1", 1920, 1080, frequency)
+    }
+
+    #[test]
+    fn wired_to_battery_transition() {
+        let config = PowerConfig::default();
+        let recorder = RecordingBackend::default();
+        let backends = Backends::new(recorder.clone());
+
+        config
+            .get_profile("Wired")
+            .unwrap()
+            .change_to(&backends, None)
+            .unwrap();
+        config
+            .get_profile("Battery")
+            .unwrap()
+            .change_to(&backends, None)
+            .unwrap();
+
+        assert_eq!(
+            recorder.calls(),
+            vec![
+                switching_to("Wired"),
+                BackendCall::SetActiveScheme(PowerScheme::HighPerformance),
+                set_refresh_rate(144),
+                switching_to("Battery"),
+                BackendCall::SetActiveScheme(PowerScheme::Balanced),
+                set_refresh_rate(60),
+            ]
+        );
+    }
+
+    #[test]
+    fn monitor_rules_leave_other_displays_alone() {
+        let (config, _) = parse(
+            r#"{
+                "version": 2,
+                "profiles": {
+                    "Battery": {
+                        "state_name": "Battery",
+                        "power_scheme": "Balanced",
+                        "change_refresh_rate": true,
+                        "screen_refresh_rate": "Min",
+                        "send_notification": false,
+                        "monitors": [
+                            { "monitor": "GSM5B7F", "resolution": { "Size": { "width": 2560, "height": 1440 } } },
+                            { "monitor": "internal", "refresh_rate": { "Value": 60 } }
+                        ]
+                    }
+                }
+            }"#,
+        );
+        let recorder = RecordingBackend::default();
+        let monitor = |name: &str, id: &str, internal| Monitor {
+            name: name.to_owned(),
+            id: id.to_owned(),
+            internal,
+            primary: !internal,
+        };
+        recorder.set_system_info(FakeSystemInfo {
+            monitors: vec![
+                monitor(r"\\.\DISPLAY1", "BOE0868", true),
+                monitor(r"\\.\DISPLAY2", "GSM5B7F", false),
+                monitor(r"\\.\DISPLAY3", "DEL4123", false),
+            ],
+            supported_modes: [(1920, 1080, 144), (2560, 1440, 60), (1920, 1080, 60)]
+                .into_iter()
+                .map(|(width, height, frequency)| ModeInfo {
+                    width,
+                    height,
+                    bits_per_pixel: 32,
+                    frequency,
+                })
+                .collect(),
+            ..Default::default()
+        });
+
+        config
+            .get_profile("Battery")
+            .unwrap()
+            .change_to(&Backends::new(recorder.clone()), None)
+            .unwrap();
+
+        assert_eq!(
+            recorder.calls(),
+            vec![
+                BackendCall::SetActiveScheme(PowerScheme::Balanced),
+                set_refresh_rate(60),
+                set_mode(r"\\.\DISPLAY2", 2560, 1440, 60),
+            ]
+        );
+    }
+
+    const TIERS: [&str; 3] = ["Battery above 50%", "Battery 20-50%", "Battery below 20%"];
+
+    fn tiered() -> PowerConfig {
+        let mut config = PowerConfig::default();
+        for (name, min_percent) in TIERS.into_iter().zip([50, 20, 0]) {
+            config.profiles.insert(
+                name.to_owned(),
+                StateConfig {
+                    state_name: name.to_owned(),
+                    power_scheme: PowerScheme::PowerSaver,
+                    change_refresh_rate: false,
+                    screen_refresh_rate: RefreshRateMode::Max,
+                    resolution: None,
+                    monitors: vec![],
+                    send_notification: true,
+                },
+            );
+            config.battery_tiers.push(BatteryTier {
+                min_percent,
+                profile: name.to_owned(),
+            });
+        }
+        config
+    }
+
+    fn tier(tier: usize) -> Selection {
+        Selection {
+            profile: TIERS[tier].to_owned(),
+            tier: Some(tier),
+        }
+    }
+
+    fn snapshot(power: PowerState) -> SystemSnapshot {
+        SystemSnapshot {
+            power,
+            time: Some(TimeOfDay::new(12, 0).unwrap()),
+            external_display: Some(false),
+            processes: Some(vec![]),
+        }
+    }
+
+    fn on_battery(percent: u8) -> SystemSnapshot {
+        snapshot(PowerState {
+            source: PowerConfigSelection::Battery,
+            battery_percent: Some(percent),
+        })
+    }
+
+    #[test]
+    fn battery_tiers_follow_the_percentage() {
+        let config = tiered();
+        let mut current = None;
+        let mut selected = vec![];
+        for percent in [80, 50, 49, 21, 20, 19, 5] {
+            current = Some(config.select(&on_battery(percent), current.as_ref()));
+            selected.push(current.clone().unwrap());
+        }
+        assert_eq!(selected, [0, 0, 1, 1, 1, 2, 2].map(tier).to_vec());
+    }
+
+    #[test]
+    fn battery_tiers_do_not_flap_at_boundaries() {
+        let config = tiered();
+        let mut current = config.select(&on_battery(49), None);
+        for percent in [50, 49, 51, 52, 50] {
+            current = config.select(&on_battery(percent), Some(&current));
+            assert_eq!(current, tier(1));
+        }
+        current = config.select(&on_battery(53), Some(&current));
+        assert_eq!(current, tier(0));
+        current = config.select(&on_battery(52), Some(&current));
+        assert_eq!(current, tier(0));
+
+        // Without a previous tier there is nothing to hold on to.
+        assert_eq!(
+            config.select(&on_battery(50), Some(&Selection::profile("Wired"))),
+            tier(0)
+        );
+    }
+
+    #[test]
+    fn battery_config_is_the_fallback() {
+        let config = tiered();
+        let unknown = PowerState {
+            source: PowerConfigSelection::Battery,
+            battery_percent: None,
+        };
+        assert_eq!(
+            config.select(&snapshot(unknown), None),
+            Selection::profile("Battery")
+        );
+
+        let wired = PowerState {
+            source: PowerConfigSelection::Wired,
+            battery_percent: Some(10),
+        };
+        assert_eq!(
+            config.select(&snapshot(wired), None),
+            Selection::profile("Wired")
+        );
+
+        // Without tiers, any percentage uses the battery config.
+        let config = PowerConfig::default();
+        assert_eq!(
+            config.select(&on_battery(10), None),
+            Selection::profile("Battery")
+        );
+    }
+
+    #[test]
+    fn rules_override_the_power_source() {
+        let mut config = tiered();
+        config.rules = vec![
+            Rule {
+                when: Conditions {
+                    process_running: Some("game.exe".to_owned()),
+                    ..Default::default()
+                },
+                profile: "Wired".to_owned(),
+            },
+            Rule {
+                when: Conditions {
+                    external_display: Some(true),
+                    ..Default::default()
+                },
+                profile: "Battery 20-50%".to_owned(),
+            },
+        ];
+
+        let mut gaming = on_battery(10);
+        gaming.processes = Some(vec!["game.exe".to_owned()]);
+        gaming.external_display = Some(true);
+        assert_eq!(config.select(&gaming, None), Selection::profile("Wired"));
+
+        let mut docked = on_battery(90);
+        docked.external_display = Some(true);
+        assert_eq!(
+            config.select(&docked, None),
+            Selection::profile("Battery 20-50%")
+        );
+
+        assert_eq!(config.select(&on_battery(90), None), tier(0));
+    }
+
+    #[test]
+    fn unknown_rule_profiles_fall_back_to_the_power_source() {
+        let config = PowerConfig {
+            rules: vec![Rule {
+                when: Conditions::default(),
+                profile: "Gaming".to_owned(),
+            }],
+            ..PowerConfig::default()
+        };
+        assert_eq!(
+            config.select(&on_battery(90), None),
+            Selection::profile("Battery")
+        );
+    }
+
+    #[test]
+    fn configs_round_trip() {
+        let json = serde_json::to_string(&tiered()).unwrap();
+        let config: PowerConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&config).unwrap(), json);
+    }
+
+    #[test]
+    fn inline_wired_and_battery_configs_still_load() {
+        let (config, migrated) = parse(
+            r#"{
+                "wired_config": {
+                    "state_name": "Plugged in",
+                    "power_scheme": "HighPerformance",
+                    "change_refresh_rate": true,
+                    "screen_refresh_rate": "Max",
+                    "send_notification": true
+                },
+                "battery_config": {
+                    "state_name": "Unplugged",
+                    "power_scheme": "PowerSaver",
+                    "change_refresh_rate": false,
+                    "screen_refresh_rate": { "Value": 60 },
+                    "send_notification": false
+                }
+            }"#,
+        );
+        assert!(migrated);
+        assert_eq!(config.wired_profile, "Plugged in");
+        assert_eq!(config.battery_profile, "Unplugged");
+        assert_eq!(
+            config.get_profile("Unplugged").unwrap().power_scheme,
+            PowerScheme::PowerSaver
+        );
+        assert!(config.battery_tiers.is_empty());
+        assert_eq!(config.battery_hysteresis, DEFAULT_BATTERY_HYSTERESIS);
+        assert!(config.rules.is_empty());
+    }
+
+    fn backups(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|x| x.unwrap().path())
+            .filter(|x| x.extension().is_some_and(|x| x == "bak"))
+            .collect()
+    }
+
+    #[test]
+    fn missing_configs_are_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+
+        PowerConfig::load(&machine(dir.path())).unwrap();
+        let (config, migrated) = parse(&fs::read_to_string(&path).unwrap());
+        assert!(!migrated);
+        assert_eq!(config.version, migration::CURRENT_VERSION);
+        assert_eq!(config.schema.as_deref(), Some(SCHEMA_NAME));
+        assert_eq!(
+            fs::read_to_string(dir.path().join(SCHEMA_NAME)).unwrap(),
+            PowerConfig::schema()
+        );
+        assert!(backups(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn schema_describes_every_key() {
+        let schema: serde_json::Value = serde_json::from_str(&PowerConfig::schema()).unwrap();
+        let config = PowerConfig {
+            schema: Some(SCHEMA_NAME.to_owned()),
+            ..tiered()
+        };
+        let serde_json::Value::Object(config) = serde_json::to_value(config).unwrap() else {
+            panic!("configs are objects");
+        };
+        for key in config.keys() {
+            assert!(schema["properties"].get(key).is_some(), "{}", key);
+        }
+        assert_eq!(schema["additionalProperties"], false);
+
+        let custom = &schema["definitions"]["PowerScheme"]["oneOf"][1];
+        assert_eq!(custom["properties"]["Custom"]["type"], "string");
+    }
+
+    #[test]
+    fn migrated_configs_are_backed_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let original = r#"{
+            "wired_config": { "state_name": "Wired", "power_scheme": "Balanced",
+                "change_refresh_rate": false, "screen_refresh_rate": "Max", "send_notification": false },
+            "battery_config": { "state_name": "Battery", "power_scheme": "PowerSaver",
+                "change_refresh_rate": false, "screen_refresh_rate": "Min", "send_notification": false }
+        }"#;
+        fs::write(&path, original).unwrap();
+
+        let config = PowerConfig::load(&machine(dir.path())).unwrap();
+        assert_eq!(
+            config.get_profile("Wired").unwrap().power_scheme,
+            PowerScheme::Balanced
+        );
+        assert_eq!(
+            fs::read_to_string(&backups(dir.path())[0]).unwrap(),
+            original
+        );
+        let (_, migrated) = parse(&fs::read_to_string(&path).unwrap());
+        assert!(!migrated);
+
+        // Loading it again has nothing left to migrate.
+        PowerConfig::load(&machine(dir.path())).unwrap();
+        assert_eq!(backups(dir.path()).len(), 1);
+    }
+
+    #[test]
+    fn configs_that_only_lack_a_version_are_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let original = "{\n    \"battery_hysteresis\": 5\n}";
+        fs::write(&path, original).unwrap();
+
+        let config = PowerConfig::load(&machine(dir.path())).unwrap();
+        assert_eq!(config.battery_hysteresis, 5);
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+        assert!(backups(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn unversioned_user_configs_are_current() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = ConfigDirs {
+            machine: dir.path().join("machine"),
+            user: Some(dir.path().join("user")),
+        };
+        for dir in dirs.iter() {
+            fs::create_dir(dir).unwrap();
+        }
+        let user = dirs.user.as_ref().unwrap();
+        let original = "# Mine.\nbattery_hysteresis: 7\n";
+        fs::write(user.join("config.yaml"), original).unwrap();
+
+        let config = PowerConfig::load(&dirs).unwrap();
+        assert_eq!(config.battery_hysteresis, 7);
+        assert_eq!(
+            fs::read_to_string(user.join("config.yaml")).unwrap(),
+            original
+        );
+        assert!(backups(user).is_empty());
+    }
+
+    #[test]
+    fn backups_do_not_replace_each_other() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let mut paths = vec![];
+        for version in ["first", "second", "third"] {
+            fs::write(&path, version).unwrap();
+            paths.push(PowerConfig::backup(&path).unwrap());
+        }
+
+        let contents: Vec<_> = paths
+            .iter()
+            .map(|x| fs::read_to_string(x).unwrap())
+            .collect();
+        assert_eq!(contents, ["first", "second", "third"]);
+        assert_eq!(backups(dir.path()).len(), 3);
+    }
+
+    #[test]
+    fn invalid_configs_are_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(&path, "{ \"profiles\": ").unwrap();
+
+        let error = PowerConfig::load(&machine(dir.path())).unwrap_err();
+        assert!(error
+            .downcast_ref::<validation::ValidationError>()
+            .is_some());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{ \"profiles\": ");
+        assert!(backups(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn newer_configs_are_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let newer = format!("{{ \"version\": {} }}", migration::CURRENT_VERSION + 1);
+        fs::write(&path, &newer).unwrap();
+
+        assert!(PowerConfig::load(&machine(dir.path())).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), newer);
+        assert!(backups(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn user_configs_override_the_machine_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = ConfigDirs {
+            machine: dir.path().join("machine"),
+            user: Some(dir.path().join("user")),
+        };
+        for dir in dirs.iter() {
+            fs::create_dir(dir).unwrap();
+        }
+        let user_path = dirs.user.as_ref().unwrap().join("config.yaml");
+        fs::write(
+            &user_path,
+            "version: 2\nprofiles:\n  Battery:\n    power_scheme: PowerSaver\n",
+        )
+        .unwrap();
+
+        let config = PowerConfig::load(&dirs).unwrap();
+        assert!(dirs.machine.join("config.json").exists());
+        let battery = config.get_profile("Battery").unwrap();
+        assert_eq!(battery.power_scheme, PowerScheme::PowerSaver);
+        assert_eq!(battery.screen_refresh_rate, RefreshRateMode::Value(60));
+
+        fs::write(&user_path, "version: 2\nwired_profile: Gaming\n").unwrap();
+        let error = PowerConfig::load(&dirs).unwrap_err().to_string();
+        let expected = format!(
+            "{}:2:1: wired_profile: Profile Gaming does not exist!",
+            user_path.display()
+        );
+        assert!(error.contains(&expected), "{}", error);
+    }
+
+    #[test]
+    fn disabled_steps_are_skipped() {
+        let mut config = PowerConfig::default();
+        let battery = config.profiles.get_mut("Battery").unwrap();
+        battery.send_notification = false;
+        battery.change_refresh_rate = false;
+        let recorder = RecordingBackend::default();
+
+        config
+            .get_profile("Battery")
+            .unwrap()
+            .change_to(&Backends::new(recorder.clone()), None)
+            .unwrap();
+
+        assert_eq!(
+            recorder.calls(),
+            vec![BackendCall::SetActiveScheme(PowerScheme::Balanced)]
+        );
     }
 }
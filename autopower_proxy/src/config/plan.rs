@@ -0,0 +1,418 @@
+use super::{PowerConfig, PowerScheme, StateConfig};
+use crate::{
+    backend::{Backends, DisplayBackend},
+    display::{
+        choose_mode, select_display_modes, DisplayMode, ModeInfo, Monitor, MonitorModes,
+        RefreshRateMode, SavedMode, SavedModes,
+    },
+};
+use autopower_shared::{logging::Logger, proxy_command::PowerState};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+type Result<T> = crate::Result<T>;
+
+static LOGGER: Logger = Logger::new("plan", "autopower_proxy");
+
+/// Everything switching to a profile changes, worked out without changing anything.
+/// [`Plan::apply`] makes exactly these changes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub profile: String,
+    pub notification: Option<Notification>,
+    pub power_scheme: PlannedScheme,
+    /// The monitors the profile changes, which is none if it does not change displays.
+    pub displays: Vec<DisplayPlan>,
+    /// The monitors whose change could not be worked out, which do not keep the others, or the
+    /// power scheme, from changing.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub title: String,
+    pub description: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PlannedScheme {
+    pub scheme: PowerScheme,
+    pub guid: String,
+    /// The GUID of the active scheme, if it could be read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DisplayPlan {
+    pub monitor: Monitor,
+    pub current: ModeInfo,
+    pub change: DisplayChange,
+    /// Why the new mode is not exactly what the profile asks for.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DisplayChange {
+    /// Switches to a mode the monitor supports.
+    Set(ModeInfo),
+    /// Brings back the mode the monitor had when the profile was last left.
+    Restore(SavedMode),
+    /// The profile restores the mode, but none was saved yet.
+    Keep,
+}
+
+impl StateConfig {
+    /// Works out what [`change_to`](Self::change_to) would do with the system as it is now.
+    pub fn plan(&self, backends: &Backends, saved: Option<&MonitorModes>) -> Result<Plan> {
+        let display = backends.display.as_ref();
+        // Only shown, so the switch does not depend on it.
+        let current = backends
+            .power
+            .get_active_scheme()
+            .map_err(|e| LOGGER.error(format!("Could not get the active power scheme!\n{}", e)))
+            .ok();
+        let mut plan = Plan {
+            profile: self.state_name.clone(),
+            notification: self.send_notification.then(|| Notification {
+                title: "AutoPower".to_owned(),
+                description: format!("Switching to {}", self.state_name),
+            }),
+            power_scheme: PlannedScheme {
+                scheme: self.power_scheme.clone(),
+                guid: self.power_scheme.guid(),
+                current,
+            },
+            displays: vec![],
+            errors: vec![],
+        };
+        if !self.change_refresh_rate {
+            return Ok(plan);
+        }
+
+        // Like a single monitor failing, this still leaves the power scheme to change.
+        let monitors = match display.get_monitors() {
+            Ok(x) => x,
+            Err(e) => {
+                plan.errors
+                    .push(format!("Could not list the monitors: {}", e));
+                return Ok(plan);
+            }
+        };
+        let fallback = DisplayMode {
+            refresh_rate: Some(self.screen_refresh_rate.clone()),
+            resolution: self.resolution,
+        };
+        for (monitor, mode) in select_display_modes(&self.monitors, fallback, &monitors) {
+            match Self::plan_display(display, monitor, mode, saved) {
+                Ok(x) => plan.displays.push(x),
+                Err(e) => plan.errors.push(format!("{}: {}", monitor.name, e)),
+            }
+        }
+        Ok(plan)
+    }
+
+    fn plan_display(
+        display: &dyn DisplayBackend,
+        monitor: &Monitor,
+        mode: DisplayMode,
+        saved: Option<&MonitorModes>,
+    ) -> Result<DisplayPlan> {
+        let (modes, current) = display.get_modes(monitor)?;
+        let (change, notes) = match mode.refresh_rate {
+            Some(RefreshRateMode::Restore) => match saved.and_then(|x| x.get(&monitor.name)) {
+                Some(saved) => (DisplayChange::Restore(saved.clone()), vec![]),
+                None => (DisplayChange::Keep, vec![]),
+            },
+            _ => {
+                let (chosen, notes) = choose_mode(&modes, current, mode);
+                (DisplayChange::Set(chosen), notes)
+            }
+        };
+        Ok(DisplayPlan {
+            monitor: monitor.clone(),
+            current,
+            change,
+            notes,
+        })
+    }
+}
+
+impl Plan {
+    pub fn apply(&self, backends: &Backends) -> Result<()> {
+        if let Some(notification) = &self.notification {
+            backends
+                .notification
+                .send_notification(&notification.title, &notification.description)?;
+        }
+
+        backends
+            .power
+            .set_active_scheme(&self.power_scheme.scheme)?;
+
+        // One monitor failing should not keep the others from changing.
+        let mut errors = self.errors.clone();
+        for display in &self.displays {
+            let monitor = &display.monitor;
+            for note in &display.notes {
+                LOGGER.error(format!("{}: {}", monitor.name, note));
+            }
+            let result = match &display.change {
+                DisplayChange::Set(mode) => backends.display.set_mode(monitor, mode),
+                DisplayChange::Restore(saved) => backends.display.restore_mode(monitor, saved),
+                DisplayChange::Keep => {
+                    LOGGER.debug(format!(
+                        "No mode of {} was saved yet, leaving it alone.",
+                        monitor.name
+                    ));
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                errors.push(format!("{}: {}", monitor.name, e));
+            }
+        }
+        if !errors.is_empty() {
+            return Err(
+                format!("Could not change the display mode!\n{}", errors.join("\n")).into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// One line per change, in the order they are made.
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Profile:      {}", self.profile)?;
+        match &self.notification {
+            Some(x) => writeln!(f, "Notification: {}: {}", x.title, x.description)?,
+            None => writeln!(f, "Notification: none")?,
+        }
+        let scheme = &self.power_scheme;
+        let target = match &scheme.scheme {
+            PowerScheme::Custom(_) => scheme.guid.clone(),
+            x => format!("{:?} ({})", x, scheme.guid),
+        };
+        match &scheme.current {
+            Some(current) if *current == scheme.guid => {
+                writeln!(f, "Power scheme: {} (unchanged)", target)?
+            }
+            Some(current) => writeln!(f, "Power scheme: {} (from {})", target, current)?,
+            None => writeln!(f, "Power scheme: {}", target)?,
+        }
+        if self.displays.is_empty() && self.errors.is_empty() {
+            writeln!(f, "Displays:     unchanged")?;
+        }
+        for display in &self.displays {
+            let target = match &display.change {
+                DisplayChange::Set(mode) if *mode == display.current => {
+                    format!("{} (unchanged)", mode)
+                }
+                DisplayChange::Set(mode) => format!("{} (from {})", mode, display.current),
+                DisplayChange::Restore(_) => {
+                    format!("the saved mode (from {})", display.current)
+                }
+                DisplayChange::Keep => format!("{} (no mode was saved yet)", display.current),
+            };
+            writeln!(f, "Display:      {} {}", display.monitor.name, target)?;
+            for note in &display.notes {
+                writeln!(f, "              {}", note)?;
+            }
+        }
+        for error in &self.errors {
+            writeln!(f, "Error:        {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl PowerConfig {
+    /// Plans the switch to the profile the power state selects now, matching rules against the
+    /// running system.
+    pub fn plan_for_state(
+        &self,
+        state: PowerState,
+        backends: &Backends,
+        saved: &SavedModes,
+    ) -> Result<Plan> {
+        let snapshot = self.collect_snapshot(state, backends);
+        let selection = self.select(&snapshot, None);
+        self.plan_profile(&selection.profile, backends, saved)
+    }
+
+    pub fn plan_profile(
+        &self,
+        name: &str,
+        backends: &Backends,
+        saved: &SavedModes,
+    ) -> Result<Plan> {
+        self.get_profile(name)?.plan(backends, saved.get(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{BackendCall, FakeSystemInfo, RecordingBackend},
+        display::Resolution,
+    };
+    use autopower_shared::proxy_command::PowerConfigSelection;
+
+    fn mode(width: u32, height: u32, frequency: u32) -> ModeInfo {
+        ModeInfo {
+            width,
+            height,
+            bits_per_pixel: 32,
+            frequency,
+        }
+    }
+
+    fn battery_plan(recorder: &RecordingBackend) -> Plan {
+        let mut config = PowerConfig::default();
+        config.profiles.get_mut("Battery").unwrap().resolution = Some(Resolution::Half);
+        let state = PowerState {
+            source: PowerConfigSelection::Battery,
+            battery_percent: Some(50),
+        };
+        config
+            .plan_for_state(
+                state,
+                &Backends::new(recorder.clone()),
+                &SavedModes::default(),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn planning_changes_nothing() {
+        let recorder = RecordingBackend::default();
+        recorder.set_system_info(FakeSystemInfo {
+            supported_modes: vec![mode(1920, 1080, 144), mode(960, 540, 75)],
+            ..Default::default()
+        });
+
+        let plan = battery_plan(&recorder);
+
+        assert!(recorder.calls().is_empty());
+        assert_eq!(
+            plan.notification,
+            Some(Notification {
+                title: "AutoPower".to_owned(),
+                description: "Switching to Battery".to_owned(),
+            })
+        );
+        assert_eq!(
+            plan.power_scheme.guid,
+            "381b4222-f694-41f0-9685-ff5bb260df2e"
+        );
+        let display = &plan.displays[0];
+        assert_eq!(display.current, mode(1920, 1080, 144));
+        assert_eq!(display.change, DisplayChange::Set(mode(960, 540, 75)));
+        assert_eq!(
+            display.notes,
+            vec![
+                "At 960x540, 60 Hz is not supported, using the closest refresh rate 75 Hz instead."
+            ]
+        );
+    }
+
+    #[test]
+    fn applying_a_plan_makes_exactly_its_changes() {
+        let recorder = RecordingBackend::default();
+        let plan = battery_plan(&recorder);
+        let json = serde_json::to_string(&plan).unwrap();
+        let plan: Plan = serde_json::from_str(&json).unwrap();
+
+        plan.apply(&Backends::new(recorder.clone())).unwrap();
+
+        let DisplayChange::Set(new_mode) = plan.displays[0].change else {
+            panic!("Expected a new mode, got {:?}", plan.displays[0].change);
+        };
+        assert_eq!(
+            recorder.calls(),
+            vec![
+                BackendCall::SendNotification {
+                    title: "AutoPower".to_owned(),
+                    description: "Switching to Battery".to_owned(),
+                },
+                BackendCall::SetActiveScheme(PowerScheme::Balanced),
+                BackendCall::SetMode {
+                    monitor: r"\\.\DISPLAY1".to_owned(),
+                    mode: new_mode,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn the_scheme_changes_when_monitors_cannot_be_listed() {
+        let recorder = RecordingBackend::default();
+        recorder.set_system_info(FakeSystemInfo {
+            monitor_error: Some("The display driver is not responding!".to_owned()),
+            ..Default::default()
+        });
+        let backends = Backends::new(recorder.clone());
+        let mut config = serde_json::to_value(PowerConfig::default()).unwrap();
+        config["rules"] = serde_json::json!([
+            { "when": { "external_display": true }, "profile": "Wired" }
+        ]);
+        let config: PowerConfig = serde_json::from_value(config).unwrap();
+        let state = PowerState {
+            source: PowerConfigSelection::Battery,
+            battery_percent: Some(50),
+        };
+
+        // The rule cannot be checked, so it does not match.
+        let plan = config
+            .plan_for_state(state, &backends, &SavedModes::default())
+            .unwrap();
+        assert_eq!(plan.profile, "Battery");
+        assert_eq!(
+            plan.errors,
+            vec!["Could not list the monitors: The display driver is not responding!"]
+        );
+
+        assert!(plan.apply(&backends).is_err());
+        assert_eq!(
+            recorder.calls(),
+            vec![
+                BackendCall::SendNotification {
+                    title: "AutoPower".to_owned(),
+                    description: "Switching to Battery".to_owned(),
+                },
+                BackendCall::SetActiveScheme(PowerScheme::Balanced),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_active_scheme_is_compared() {
+        let recorder = RecordingBackend::default();
+        let plan = battery_plan(&recorder);
+        assert_eq!(plan.power_scheme.current, None);
+        assert!(plan
+            .to_string()
+            .contains("Power scheme: Balanced (381b4222-f694-41f0-9685-ff5bb260df2e)\n"));
+
+        recorder.set_system_info(FakeSystemInfo {
+            active_scheme: Some(PowerScheme::HighPerformance.guid()),
+            ..Default::default()
+        });
+        assert!(battery_plan(&recorder).to_string().contains(
+            "Power scheme: Balanced (381b4222-f694-41f0-9685-ff5bb260df2e) \
+             (from 8c5e7fda-e8bf-4a96-9a85-a6e23a8c635c)\n"
+        ));
+
+        recorder.set_system_info(FakeSystemInfo {
+            active_scheme: Some(PowerScheme::Balanced.guid()),
+            ..Default::default()
+        });
+        assert!(battery_plan(&recorder).to_string().contains(
+            "Power scheme: Balanced (381b4222-f694-41f0-9685-ff5bb260df2e) (unchanged)\n"
+        ));
+    }
+}
@@ -1,18 +1,34 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+#[cfg(windows)]
 use windows::core::GUID;
+#[cfg(windows)]
 use windows::Win32::System::SystemServices::{
     GUID_MAX_POWER_SAVINGS, GUID_MIN_POWER_SAVINGS, GUID_TYPICAL_POWER_SAVINGS,
 };
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
 pub enum PowerScheme {
     HighPerformance,
     Balanced,
     PowerSaver,
-    Custom(String),
+    /// The GUID of any other power scheme, such as `"381b4222-f694-41f0-9685-ff5bb260df2e"`.
+    /// `powercfg /list` shows the GUIDs of every scheme.
+    Custom(#[schemars(pattern(r"^[0-9a-fA-F]{8}(-[0-9a-fA-F]{4}){3}-[0-9a-fA-F]{12}$"))] String),
 }
 
 impl PowerScheme {
+    /// The GUID `powercfg` lists the scheme under.
+    pub fn guid(&self) -> String {
+        match self {
+            Self::HighPerformance => "8c5e7fda-e8bf-4a96-9a85-a6e23a8c635c".to_owned(),
+            Self::Balanced => "381b4222-f694-41f0-9685-ff5bb260df2e".to_owned(),
+            Self::PowerSaver => "a1841308-3541-4fab-bc81-f71556f20b4a".to_owned(),
+            Self::Custom(val) => val.to_ascii_lowercase(),
+        }
+    }
+
+    #[cfg(windows)]
     pub fn to_guid(&self) -> GUID {
         match self {
             Self::HighPerformance => GUID_MIN_POWER_SAVINGS,
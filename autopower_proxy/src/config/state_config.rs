@@ -1,35 +1,33 @@
 use super::PowerScheme;
 use crate::{
-    display::{set_display_refresh_rate, RefreshRateMode},
-    toast::Toast,
+    backend::Backends,
+    display::{MonitorModes, MonitorRule, RefreshRateMode, Resolution},
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use windows::Win32::System::Power::PowerSetActiveScheme;
 
 type Result<T> = crate::Result<T>;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[schemars(deny_unknown_fields)]
 pub struct StateConfig {
     pub(super) state_name: String,
     pub(super) power_scheme: PowerScheme,
     pub(super) change_refresh_rate: bool,
     pub(super) screen_refresh_rate: RefreshRateMode,
+    /// Keeps the current resolution when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) resolution: Option<Resolution>,
+    /// How each monitor is changed. When empty, `screen_refresh_rate` and `resolution` apply to
+    /// the primary monitor.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) monitors: Vec<MonitorRule>,
     pub(super) send_notification: bool,
 }
 
 impl StateConfig {
-    pub fn change_to(&self) -> Result<()> {
-        if self.send_notification {
-            Toast::new("AutoPower", format!("Switching to {}", self.state_name)).send()?;
-        }
-
-        unsafe {
-            PowerSetActiveScheme(None, Some(&self.power_scheme.to_guid())).ok()?;
-        }
-
-        if self.change_refresh_rate {
-            set_display_refresh_rate(self.screen_refresh_rate)?;
-        }
-        Ok(())
+    /// Applies the profile. `saved` holds the modes the monitors had when it was last left.
+    pub fn change_to(&self, backends: &Backends, saved: Option<&MonitorModes>) -> Result<()> {
+        self.plan(backends, saved)?.apply(backends)
     }
 }
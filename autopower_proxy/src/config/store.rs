@@ -0,0 +1,266 @@
+use super::{format, validation::ValidationError, ConfigDirs, PowerConfig, CONFIG_NAME};
+use crate::backend::Backends;
+use autopower_shared::logging::Logger;
+use notify::{Event, RecursiveMode, Watcher};
+use std::{
+    error::Error,
+    fs,
+    sync::{mpsc, Arc, Mutex, RwLock},
+    thread,
+    time::Duration,
+};
+
+static LOGGER: Logger = Logger::new("config_store", "autopower_proxy");
+
+type Result<T> = crate::Result<T>;
+
+/// How long the file has to stay unchanged before it is read, so a save in progress is skipped.
+const SETTLE_TIME: Duration = Duration::from_millis(250);
+
+/// Holds the parsed config in memory, and swaps in a new one once the file changes and validates.
+/// Reading the config never waits on the disk.
+pub struct ConfigStore {
+    /// The directories the config files are looked up in, since their format may change while
+    /// running.
+    dirs: ConfigDirs,
+    current: RwLock<Arc<PowerConfig>>,
+    /// The last error the user was notified about, so an unchanged file is only reported once.
+    /// Also makes sure only one reload runs at a time.
+    reported: Mutex<Option<String>>,
+}
+
+impl ConfigStore {
+    /// Loads the config, and falls back to the defaults until the file is valid.
+    pub fn open(backends: &Backends) -> Result<Self> {
+        let store = Self::at(ConfigDirs::platform()?);
+        // Any error has already been reported.
+        let _ = store.reload(backends);
+        Ok(store)
+    }
+
+    fn at(dirs: ConfigDirs) -> Self {
+        Self {
+            dirs,
+            current: RwLock::new(Arc::new(PowerConfig::default())),
+            reported: Mutex::new(None),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_config(config: PowerConfig) -> Self {
+        let store = Self::at(ConfigDirs {
+            machine: Default::default(),
+            user: None,
+        });
+        *store.current.write().unwrap() = Arc::new(config);
+        store
+    }
+
+    pub fn get(&self) -> Arc<PowerConfig> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Reads the files again. If they are invalid, the error is reported and the current config
+    /// kept.
+    pub fn reload(&self, backends: &Backends) -> Result<()> {
+        let mut reported = self.reported.lock().unwrap();
+        match PowerConfig::load(&self.dirs) {
+            Ok(config) => {
+                *self.current.write().unwrap() = Arc::new(config);
+                *reported = None;
+                LOGGER.debug("Loaded config");
+                Ok(())
+            }
+            Err(e) => {
+                Self::report(&mut reported, backends, e.as_ref());
+                Err(e)
+            }
+        }
+    }
+
+    fn report(reported: &mut Option<String>, backends: &Backends, error: &(dyn Error + 'static)) {
+        let message = error.to_string();
+        if reported.as_ref() == Some(&message) {
+            return;
+        }
+        LOGGER.error(format!(
+            "Could not load the config, keeping the last good one!\n{}",
+            message
+        ));
+
+        let summary = match error.downcast_ref::<ValidationError>() {
+            Some(e) => format!(
+                "{} problem(s) in the config, keeping the last good one.\n{}",
+                e.problems.len(),
+                e.problems[0]
+            ),
+            None => format!(
+                "Could not load the config, keeping the last good one.\n{}",
+                message
+            ),
+        };
+        if let Err(e) = backends
+            .notification
+            .send_notification("AutoPower", &summary)
+        {
+            LOGGER.error(format!("Could not send config error notification!\n{}", e));
+        }
+        *reported = Some(message);
+    }
+
+    /// Reloads the config on a background thread whenever one of the files changes.
+    pub fn watch(self: &Arc<Self>, backends: Arc<Backends>) -> Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)
+            .map_err(|e| format!("Could not create file watcher!\n{}", e))?;
+        for dir in self.dirs.iter() {
+            // The user directory is created, so a config added to it later is picked up too.
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Could not create {}!\n{}", dir.display(), e))?;
+            // Editors often replace the file instead of writing to it, so watch the whole directory.
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .map_err(|e| format!("Could not watch {}!\n{}", dir.display(), e))?;
+        }
+
+        let store = self.clone();
+        thread::spawn(move || {
+            // The watcher stops once dropped.
+            let _watcher = watcher;
+            store.handle_events(&receiver, &backends);
+        });
+        Ok(())
+    }
+
+    /// Reloads once per burst of changes to a config file, until the watcher is gone.
+    fn handle_events(&self, receiver: &mpsc::Receiver<notify::Result<Event>>, backends: &Backends) {
+        while let Ok(event) = receiver.recv() {
+            if !self.is_change(event) {
+                continue;
+            }
+            while receiver.recv_timeout(SETTLE_TIME).is_ok() {}
+            LOGGER.debug("Config changed, reloading...");
+            // Any error has already been reported.
+            let _ = self.reload(backends);
+        }
+    }
+
+    fn is_change(&self, event: notify::Result<Event>) -> bool {
+        match event {
+            Ok(event) => {
+                !event.kind.is_access()
+                    && event.paths.iter().any(|x| {
+                        x.file_name()
+                            .is_some_and(|x| format::is_config_name(x, CONFIG_NAME))
+                    })
+            }
+            Err(e) => {
+                LOGGER.error(format!("Could not watch the config!\n{}", e));
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{BackendCall, RecordingBackend};
+    use notify::event::{AccessKind, EventKind, ModifyKind};
+    use std::path::Path;
+
+    fn notifications(recorder: &RecordingBackend) -> usize {
+        recorder
+            .calls()
+            .iter()
+            .filter(|x| matches!(x, BackendCall::SendNotification { .. }))
+            .count()
+    }
+
+    fn machine(dir: &Path) -> ConfigDirs {
+        ConfigDirs {
+            machine: dir.to_owned(),
+            user: None,
+        }
+    }
+
+    fn good() -> PowerConfig {
+        PowerConfig {
+            wired_profile: "Battery".to_owned(),
+            ..PowerConfig::default()
+        }
+    }
+
+    #[test]
+    fn last_good_config_is_kept_and_errors_reported_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let recorder = RecordingBackend::default();
+        let backends = Backends::new(recorder.clone());
+        let store = ConfigStore::at(machine(dir.path()));
+
+        PowerConfig::save(&path, &good()).unwrap();
+        store.reload(&backends).unwrap();
+        assert_eq!(store.get().wired_profile, "Battery");
+
+        fs::write(&path, "{ \"version\": 2, \"profile\": {} }").unwrap();
+        assert!(store.reload(&backends).is_err());
+        assert!(store.reload(&backends).is_err());
+        assert_eq!(store.get().wired_profile, "Battery");
+        assert_eq!(notifications(&recorder), 1);
+
+        PowerConfig::save(&path, &good()).unwrap();
+        store.reload(&backends).unwrap();
+        fs::write(&path, "{").unwrap();
+        assert!(store.reload(&backends).is_err());
+        assert_eq!(notifications(&recorder), 2);
+    }
+
+    #[test]
+    fn defaults_are_used_until_the_config_is_valid() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(&path, "{").unwrap();
+        let backends = Backends::new(RecordingBackend::default());
+
+        let store = ConfigStore::at(machine(dir.path()));
+        assert!(store.reload(&backends).is_err());
+        assert_eq!(store.get().wired_profile, "Wired");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{");
+    }
+
+    #[test]
+    fn changes_are_picked_up_by_the_watcher() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let backends = Backends::new(RecordingBackend::default());
+        let store = ConfigStore::at(machine(dir.path()));
+        store.reload(&backends).unwrap();
+        PowerConfig::save(&path, &good()).unwrap();
+
+        // Reading the file and changing another one are not changes to the config.
+        let (sender, receiver) = mpsc::channel();
+        sender
+            .send(Ok(
+                Event::new(EventKind::Access(AccessKind::Any)).add_path(path.clone())
+            ))
+            .unwrap();
+        sender
+            .send(Ok(Event::new(EventKind::Modify(ModifyKind::Any))
+                .add_path(dir.path().join("notes.txt"))))
+            .unwrap();
+        drop(sender);
+        store.handle_events(&receiver, &backends);
+        assert_eq!(store.get().wired_profile, "Wired");
+
+        let (sender, receiver) = mpsc::channel();
+        sender
+            .send(Ok(
+                Event::new(EventKind::Modify(ModifyKind::Any)).add_path(path)
+            ))
+            .unwrap();
+        drop(sender);
+        store.handle_events(&receiver, &backends);
+        assert_eq!(store.get().wired_profile, "Battery");
+    }
+}
@@ -0,0 +1,706 @@
+use super::{
+    format::ConfigFormat,
+    layers::{self, Effective, Layer, Scope, Source},
+    migration, PowerConfig, PowerScheme,
+};
+use crate::display::{RefreshRateMode, Resolution};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    error::Error,
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Refresh rates and resolutions outside of these are most likely typos.
+const REFRESH_RATE_RANGE: std::ops::RangeInclusive<u32> = 20..=1000;
+const RESOLUTION_RANGE: std::ops::RangeInclusive<u32> = 320..=16384;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// The keys leading to a value in the config, such as `profiles.Wired.power_scheme`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct KeyPath(Vec<Segment>);
+
+impl KeyPath {
+    pub fn key(mut self, key: &str) -> Self {
+        self.0.push(Segment::Key(key.to_owned()));
+        self
+    }
+
+    pub fn index(mut self, index: usize) -> Self {
+        self.0.push(Segment::Index(index));
+        self
+    }
+
+    pub fn push(&mut self, segment: Segment) {
+        self.0.push(segment);
+    }
+
+    pub fn pop(&mut self) {
+        self.0.pop();
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.0
+    }
+
+    pub fn starts_with(&self, other: &KeyPath) -> bool {
+        self.0.starts_with(&other.0)
+    }
+
+    /// The path itself, then every parent up to the root.
+    pub fn ancestors(&self) -> impl Iterator<Item = KeyPath> + '_ {
+        (0..=self.0.len())
+            .rev()
+            .map(|x| KeyPath(self.0[..x].to_vec()))
+    }
+
+    fn from_ignored(path: &serde_ignored::Path) -> Self {
+        use serde_ignored::Path;
+        match path {
+            Path::Root => Self::default(),
+            Path::Seq { parent, index } => Self::from_ignored(parent).index(*index),
+            Path::Map { parent, key } => Self::from_ignored(parent).key(key),
+            Path::Some { parent }
+            | Path::NewtypeStruct { parent }
+            | Path::NewtypeVariant { parent } => Self::from_ignored(parent),
+        }
+    }
+
+    fn from_tracked(path: &serde_path_to_error::Path) -> Self {
+        use serde_path_to_error::Segment;
+        path.iter()
+            .fold(Self::default(), |x, segment| match segment {
+                Segment::Seq { index } => x.index(*index),
+                Segment::Map { key } => x.key(key),
+                Segment::Enum { variant } => x.key(variant),
+                Segment::Unknown => x,
+            })
+    }
+}
+
+impl FromIterator<Segment> for KeyPath {
+    fn from_iter<T: IntoIterator<Item = Segment>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl Display for KeyPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("(root)");
+        }
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                Segment::Key(key) if key.chars().all(|x| x.is_alphanumeric() || x == '_') => {
+                    if i > 0 {
+                        f.write_str(".")?;
+                    }
+                    f.write_str(key)?;
+                }
+                Segment::Key(key) => write!(f, "[{:?}]", key)?,
+                Segment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads keys the way they are displayed, such as `battery_tiers[0].profile` or
+/// `profiles["Battery 20-50%"]`. Keys that are not quoted may contain anything but `.` and `[`.
+impl FromStr for KeyPath {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{} is not a valid key!", text);
+        let mut path = Self::default();
+        let mut rest = text;
+        while !rest.is_empty() {
+            if let Some(inner) = rest.strip_prefix("[\"") {
+                // Quoted keys are escaped like JSON strings.
+                let mut strings =
+                    serde_json::Deserializer::from_str(&rest[1..]).into_iter::<String>();
+                let key = strings.next().and_then(|x| x.ok()).ok_or_else(invalid)?;
+                let end = strings.byte_offset() - 1;
+                rest = inner[end..].strip_prefix(']').ok_or_else(invalid)?;
+                path = path.key(&key);
+            } else if let Some(inner) = rest.strip_prefix('[') {
+                let (index, after) = inner.split_once(']').ok_or_else(invalid)?;
+                path = path.index(index.parse().map_err(|_| invalid())?);
+                rest = after;
+            } else {
+                let key = match path.0.is_empty() {
+                    true => rest,
+                    false => rest.strip_prefix('.').ok_or_else(invalid)?,
+                };
+                let end = key.find(['.', '[']).unwrap_or(key.len());
+                if end == 0 {
+                    return Err(invalid());
+                }
+                path = path.key(&key[..end]);
+                rest = &key[end..];
+            }
+        }
+        match path.0.is_empty() {
+            true => Err(invalid()),
+            false => Ok(path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn from_offset(text: &str, offset: usize) -> Self {
+        let before = &text[..offset.min(text.len())];
+        let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+/// Where each key and array element of a document starts, and which keys appear twice.
+#[derive(Debug, Default)]
+pub struct Locations {
+    positions: HashMap<KeyPath, Position>,
+    pub duplicates: Vec<(KeyPath, Position)>,
+}
+
+impl Locations {
+    pub fn record(&mut self, key: KeyPath, position: Position) {
+        match self.positions.entry(key) {
+            Entry::Occupied(x) => self.duplicates.push((x.key().clone(), position)),
+            Entry::Vacant(x) => {
+                x.insert(position);
+            }
+        }
+    }
+
+    /// Returns the position of the key, or of the closest parent that is in the document.
+    pub fn find(&self, key: &KeyPath) -> Option<Position> {
+        key.ancestors()
+            .find_map(|x| self.positions.get(&x))
+            .copied()
+    }
+}
+
+/// Something wrong with the value at `key`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// The file the key was set in, or [`None`] for the built-in defaults.
+    pub path: Option<PathBuf>,
+    pub key: KeyPath,
+    /// Where the key is in the file. Keys that were moved by a migration point at their parent.
+    pub position: Option<Position>,
+    pub message: String,
+}
+
+impl Problem {
+    /// A file that could not be parsed at all.
+    pub fn syntax(position: Position, message: String) -> Self {
+        Self {
+            path: None,
+            key: KeyPath::default(),
+            position: Some(position),
+            message,
+        }
+    }
+
+    /// Looks up the key in the layer that set it.
+    fn locate(layer: &Layer, key: KeyPath, message: String) -> Self {
+        Self {
+            path: layer.source.path().map(Path::to_owned),
+            position: layer.locations.find(&key),
+            key,
+            message,
+        }
+    }
+}
+
+impl Display for Problem {
+    /// Formats the problem as `file:line:column: key: message`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}", path.display())?,
+            None => write!(f, "{}", Source::Default)?,
+        }
+        if let Some(position) = self.position {
+            write!(f, ":{}:{}", position.line, position.column)?;
+        }
+        write!(f, ": {}: {}", self.key, self.message)
+    }
+}
+
+/// Every problem found in the config files.
+#[derive(Debug)]
+pub struct ValidationError {
+    pub problems: Vec<Problem>,
+}
+
+impl ValidationError {
+    fn new(mut problems: Vec<Problem>) -> Self {
+        // Problems without a position go last.
+        problems.sort_by_key(|x| {
+            let position = x.position.map_or((usize::MAX, 0), |x| (x.line, x.column));
+            (x.path.clone(), position)
+        });
+        Self { problems }
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Found {} problem(s) in the config!", self.problems.len())?;
+        for problem in &self.problems {
+            write!(f, "\n{}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ValidationError {}
+
+/// Parses a config file of any known version, and migrates it to the current one.
+/// Only problems that stop the file from being merged are returned here, the rest are found by
+/// [`build`].
+pub fn parse_layer(
+    path: &Path,
+    format: ConfigFormat,
+    text: &str,
+    scope: Scope,
+) -> Result<Layer, ValidationError> {
+    let in_file = |problem: Problem| {
+        ValidationError::new(vec![Problem {
+            path: Some(path.to_owned()),
+            ..problem
+        }])
+    };
+
+    let (value, locations) = format.parse(text).map_err(in_file)?;
+    let version_problem = |e: Box<dyn Error>| {
+        let key = KeyPath::default().key("version");
+        in_file(Problem {
+            path: None,
+            position: locations.find(&key),
+            key,
+            message: e.to_string(),
+        })
+    };
+    // User configs came after versioning, so one without a version is written for this one.
+    let unversioned = match scope {
+        Scope::Machine => migration::LEGACY_VERSION,
+        Scope::User => migration::CURRENT_VERSION,
+    };
+    let migrated = migration::migrate(value.clone(), unversioned).map_err(version_problem)?;
+    Ok(Layer {
+        source: Source::File(path.to_owned()),
+        migrated: migration::changes_content(&value, &migrated),
+        value: migrated,
+        locations,
+    })
+}
+
+/// Merges the layers, from the lowest to the highest priority, and validates the result.
+/// Each problem points at the layer that set the key.
+pub fn build(layers: &[Layer]) -> Result<Effective, ValidationError> {
+    let (value, origins) = layers::merge(layers);
+    let problem =
+        |key: KeyPath, message: String| Problem::locate(&layers[origins.find(&key)], key, message);
+    let mut problems: Vec<_> = layers
+        .iter()
+        .flat_map(|layer| {
+            layer
+                .locations
+                .duplicates
+                .iter()
+                .map(|(key, position)| Problem {
+                    path: layer.source.path().map(Path::to_owned),
+                    key: key.clone(),
+                    position: Some(*position),
+                    message: "Is defined more than once!".to_owned(),
+                })
+        })
+        .collect();
+
+    let mut unknown = vec![];
+    let mut track_unknown = |path: serde_ignored::Path| {
+        unknown.push(KeyPath::from_ignored(&path));
+    };
+    let deserializer = serde_ignored::Deserializer::new(value.clone(), &mut track_unknown);
+    let result: Result<PowerConfig, _> = serde_path_to_error::deserialize(deserializer);
+    problems.extend(
+        unknown
+            .into_iter()
+            .map(|x| problem(x, "Is not a known setting!".to_owned())),
+    );
+    let config = match result {
+        Ok(config) => config,
+        Err(e) => {
+            problems.push(problem(
+                KeyPath::from_tracked(e.path()),
+                e.into_inner().to_string(),
+            ));
+            return Err(ValidationError::new(problems));
+        }
+    };
+
+    problems.extend(
+        config
+            .check()
+            .into_iter()
+            .map(|(key, message)| problem(key, message)),
+    );
+    if !problems.is_empty() {
+        return Err(ValidationError::new(problems));
+    }
+    Ok(Effective::new(config, value, origins, layers))
+}
+
+/// Whether the GUID looks like `"381b4222-f694-41f0-9685-ff5bb260df2e"`.
+fn is_valid_guid(guid: &str) -> bool {
+    let groups: Vec<_> = guid.split('-').map(str::len).collect();
+    groups == [8, 4, 4, 4, 12] && guid.chars().all(|x| x == '-' || x.is_ascii_hexdigit())
+}
+
+/// Returns the problems of a refresh rate that deserializing alone does not catch.
+fn check_refresh_rate(key: &KeyPath, mode: &RefreshRateMode) -> Vec<(KeyPath, String)> {
+    let out_of_range = |key: KeyPath, rate: u32| {
+        let message = format!(
+            "{} Hz is outside of {} to {} Hz!",
+            rate,
+            REFRESH_RATE_RANGE.start(),
+            REFRESH_RATE_RANGE.end()
+        );
+        (!REFRESH_RATE_RANGE.contains(&rate)).then_some((key, message))
+    };
+    match mode {
+        RefreshRateMode::Value(rate) => out_of_range(key.clone().key("Value"), *rate)
+            .into_iter()
+            .collect(),
+        RefreshRateMode::AtMost(rate) => out_of_range(key.clone().key("AtMost"), *rate)
+            .into_iter()
+            .collect(),
+        RefreshRateMode::AtLeast(rate) => out_of_range(key.clone().key("AtLeast"), *rate)
+            .into_iter()
+            .collect(),
+        RefreshRateMode::Preferred(rates) if rates.is_empty() => vec![(
+            key.clone().key("Preferred"),
+            "Expected at least one refresh rate!".to_owned(),
+        )],
+        RefreshRateMode::Preferred(rates) => rates
+            .iter()
+            .enumerate()
+            .filter_map(|(i, rate)| out_of_range(key.clone().key("Preferred").index(i), *rate))
+            .collect(),
+        RefreshRateMode::Ratio(ratio) if !(*ratio > 0.0 && *ratio <= 1.0) => vec![(
+            key.clone().key("Ratio"),
+            format!("{} is outside of 0 to 1!", ratio),
+        )],
+        _ => vec![],
+    }
+}
+
+impl PowerConfig {
+    /// Returns the problems that deserializing alone does not catch.
+    fn check(&self) -> Vec<(KeyPath, String)> {
+        let mut problems = vec![];
+        let profiles = KeyPath::default().key("profiles");
+        for (name, profile) in &self.profiles {
+            let key = profiles.clone().key(name);
+            if let PowerScheme::Custom(guid) = &profile.power_scheme {
+                if !is_valid_guid(guid) {
+                    problems.push((
+                        key.clone().key("power_scheme").key("Custom"),
+                        format!("\"{}\" is not a valid GUID!", guid),
+                    ));
+                }
+            }
+            // The display settings of the profile, followed by those of each monitor rule.
+            let modes = std::iter::once((
+                key.clone().key("screen_refresh_rate"),
+                Some(&profile.screen_refresh_rate),
+                key.clone().key("resolution"),
+                profile.resolution,
+            ))
+            .chain(profile.monitors.iter().enumerate().map(|(i, x)| {
+                let key = key.clone().key("monitors").index(i);
+                (
+                    key.clone().key("refresh_rate"),
+                    x.refresh_rate.as_ref(),
+                    key.key("resolution"),
+                    x.resolution,
+                )
+            }));
+            for (rate_key, rate, resolution_key, resolution) in modes {
+                if let Some(rate) = rate {
+                    problems.extend(check_refresh_rate(&rate_key, rate));
+                }
+                if let Some(Resolution::Size { width, height }) = resolution {
+                    if !RESOLUTION_RANGE.contains(&width) || !RESOLUTION_RANGE.contains(&height) {
+                        problems.push((
+                            resolution_key.key("Size"),
+                            format!(
+                                "{}x{} is outside of {} to {} pixels!",
+                                width,
+                                height,
+                                RESOLUTION_RANGE.start(),
+                                RESOLUTION_RANGE.end()
+                            ),
+                        ));
+                    }
+                }
+            }
+            for (i, rule) in profile.monitors.iter().enumerate() {
+                if rule.monitor.trim().is_empty() {
+                    problems.push((
+                        key.clone().key("monitors").index(i).key("monitor"),
+                        "Expected internal, external, a device name or an EDID id!".to_owned(),
+                    ));
+                }
+            }
+        }
+
+        let references = [
+            (KeyPath::default().key("wired_profile"), &self.wired_profile),
+            (
+                KeyPath::default().key("battery_profile"),
+                &self.battery_profile,
+            ),
+        ]
+        .into_iter()
+        .chain(self.battery_tiers.iter().enumerate().map(|(i, x)| {
+            let key = KeyPath::default().key("battery_tiers").index(i);
+            (key.key("profile"), &x.profile)
+        }))
+        .chain(self.rules.iter().enumerate().map(|(i, x)| {
+            let key = KeyPath::default().key("rules").index(i);
+            (key.key("profile"), &x.profile)
+        }));
+        for (key, name) in references {
+            if !self.profiles.contains_key(name) {
+                problems.push((key, format!("Profile {} does not exist!", name)));
+            }
+        }
+
+        for (i, rule) in self.rules.iter().enumerate() {
+            if let Some(range) = rule.when.battery_percent.filter(|x| x.min > x.max) {
+                let key = KeyPath::default().key("rules").index(i);
+                problems.push((
+                    key.key("when").key("battery_percent"),
+                    format!(
+                        "min ({}) must not be greater than max ({})!",
+                        range.min, range.max
+                    ),
+                ));
+            }
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(text: &str) -> Vec<String> {
+        let error = parse_layer(
+            Path::new("config.json"),
+            ConfigFormat::Json,
+            text,
+            Scope::Machine,
+        )
+        .and_then(|layer| build(&[Layer::defaults(), layer]))
+        .unwrap_err();
+        error.problems.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let text = r#"{
+  "version": 2,
+  "profiles": {
+    "Wired": {
+      "state_name": "Wired",
+      "power_scheme": { "Custom": "not-a-guid" },
+      "change_refresh_rate": true,
+      "screen_refresh_rate": "Max",
+      "send_notification": true,
+      "sendnotification": false
+    },
+    "Battery": {},
+    "Battery": {
+      "state_name": "Battery",
+      "power_scheme": "Balanced",
+      "change_refresh_rate": true,
+      "screen_refresh_rate": { "Value": 1200 },
+      "send_notification": true
+    }
+  },
+  "wired_profile": "Wired",
+  "battery_profile": "Battery",
+  "battery_tiers": [{ "min_percent": 20, "profile": "Low", "max_percent": 50 }]
+}"#;
+        assert_eq!(
+            problems(text),
+            vec![
+                "config.json:6:25: profiles.Wired.power_scheme.Custom: \"not-a-guid\" is not a valid GUID!",
+                "config.json:10:7: profiles.Wired.sendnotification: Is not a known setting!",
+                "config.json:13:5: profiles.Battery: Is defined more than once!",
+                "config.json:17:32: profiles.Battery.screen_refresh_rate.Value: 1200 Hz is outside of 20 to 1000 Hz!",
+                "config.json:23:42: battery_tiers[0].profile: Profile Low does not exist!",
+                "config.json:23:60: battery_tiers[0].max_percent: Is not a known setting!",
+            ]
+        );
+    }
+
+    #[test]
+    fn monitor_rules_are_checked() {
+        let text = r#"{
+  "version": 2,
+  "profiles": {
+    "Battery": {
+      "state_name": "Battery",
+      "power_scheme": "Balanced",
+      "change_refresh_rate": true,
+      "screen_refresh_rate": "Min",
+      "send_notification": true,
+      "monitors": [
+        { "monitor": "external", "refresh_rate": { "Value": 5 } },
+        { "monitor": " ", "resolution": { "Size": { "width": 2560, "height": 14 } } }
+      ]
+    }
+  }
+}"#;
+        assert_eq!(
+            problems(text),
+            vec![
+                "config.json:11:52: profiles.Battery.monitors[0].refresh_rate.Value: 5 Hz is outside of 20 to 1000 Hz!",
+                "config.json:12:11: profiles.Battery.monitors[1].monitor: Expected internal, external, a device name or an EDID id!",
+                "config.json:12:43: profiles.Battery.monitors[1].resolution.Size: 2560x14 is outside of 320 to 16384 pixels!",
+            ]
+        );
+    }
+
+    #[test]
+    fn refresh_rate_policies_are_checked() {
+        let text = r#"{
+  "version": 2,
+  "profiles": {
+    "Battery": {
+      "state_name": "Battery",
+      "power_scheme": "Balanced",
+      "change_refresh_rate": true,
+      "screen_refresh_rate": { "Ratio": 1.5 },
+      "send_notification": true,
+      "monitors": [
+        { "monitor": "external", "refresh_rate": { "Preferred": [] } },
+        { "monitor": "internal", "refresh_rate": { "Preferred": [120, 2000] } },
+        { "monitor": "GSM5B7F", "refresh_rate": { "AtMost": 10 } }
+      ]
+    }
+  }
+}"#;
+        assert_eq!(
+            problems(text),
+            vec![
+                "config.json:8:32: profiles.Battery.screen_refresh_rate.Ratio: 1.5 is outside of 0 to 1!",
+                "config.json:11:52: profiles.Battery.monitors[0].refresh_rate.Preferred: Expected at least one refresh rate!",
+                "config.json:12:71: profiles.Battery.monitors[1].refresh_rate.Preferred[1]: 2000 Hz is outside of 20 to 1000 Hz!",
+                "config.json:13:51: profiles.Battery.monitors[2].refresh_rate.AtMost: 10 Hz is outside of 20 to 1000 Hz!",
+            ]
+        );
+    }
+
+    #[test]
+    fn percent_ranges_are_checked() {
+        let text = r#"{
+  "version": 2,
+  "rules": [
+    { "when": { "battery_percent": { "min": 50, "max": 20 } }, "profile": "Battery" },
+    { "when": { "battery_percent": { "min": 20, "max": 20 } }, "profile": "Battery" }
+  ]
+}"#;
+        assert_eq!(
+            problems(text),
+            vec!["config.json:4:17: rules[0].when.battery_percent: min (50) must not be greater than max (20)!"]
+        );
+    }
+
+    #[test]
+    fn custom_guids_are_checked() {
+        assert!(is_valid_guid("381b4222-f694-41f0-9685-ff5bb260df2e"));
+        for invalid in [
+            "not-a-guid",
+            "{381b4222-f694-41f0-9685-ff5bb260df2e}",
+            "381b4222f69441f09685ff5bb260df2e",
+            "381b4222-f694-41f0-9685-ff5bb260df2g",
+        ] {
+            assert!(!is_valid_guid(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn syntax_errors_have_a_position() {
+        assert_eq!(
+            problems("{\n  \"profiles\": {,\n}"),
+            vec!["config.json:2:16: (root): key must be a string"]
+        );
+    }
+
+    #[test]
+    fn type_errors_point_at_their_key() {
+        let text = r#"{
+  "version": 2,
+  "profiles": {},
+  "wired_profile": "Wired",
+  "battery_profile": "Battery",
+  "battery_hysteresis": "three"
+}"#;
+        assert_eq!(
+            problems(text),
+            vec![
+                "config.json:6:3: battery_hysteresis: invalid type: string \"three\", expected u8"
+            ]
+        );
+    }
+
+    #[test]
+    fn keys_are_parsed_the_way_they_are_displayed() {
+        for key in [
+            KeyPath::default().key("wired_profile"),
+            KeyPath::default()
+                .key("battery_tiers")
+                .index(1)
+                .key("profile"),
+            KeyPath::default()
+                .key("profiles")
+                .key("Battery \"20-50%\"")
+                .key("screen_refresh_rate"),
+        ] {
+            assert_eq!(key.to_string().parse::<KeyPath>().unwrap(), key);
+        }
+        for invalid in ["", "a..b", "a[x]", "a[\"b\"", ".a", "a.[0]"] {
+            assert!(invalid.parse::<KeyPath>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn quoted_keys_are_displayed_with_brackets() {
+        let key = KeyPath::default()
+            .key("profiles")
+            .key("Battery 20-50%")
+            .key("power_scheme");
+        assert_eq!(key.to_string(), "profiles[\"Battery 20-50%\"].power_scheme");
+    }
+}
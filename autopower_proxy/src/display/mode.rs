@@ -0,0 +1,408 @@
+use super::{DisplayMode, RefreshRateMode, Resolution};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Reverse, fmt};
+
+/// A mode a display supports.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModeInfo {
+    pub width: u32,
+    pub height: u32,
+    pub bits_per_pixel: u32,
+    pub frequency: u32,
+}
+
+impl ModeInfo {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+impl fmt::Display for ModeInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}x{} at {} Hz, {} bpp",
+            self.width, self.height, self.frequency, self.bits_per_pixel
+        )
+    }
+}
+
+fn area((width, height): (u32, u32)) -> u64 {
+    width as u64 * height as u64
+}
+
+/// Picks the supported mode closest to the target, keeping the current color depth.
+/// Also returns an explanation for each part of the target that could not be met exactly.
+pub fn choose_mode(
+    modes: &[ModeInfo],
+    current: ModeInfo,
+    target: DisplayMode,
+) -> (ModeInfo, Vec<String>) {
+    let mut notes = vec![];
+    let modes: Vec<_> = modes
+        .iter()
+        .copied()
+        .filter(|x| x.bits_per_pixel == current.bits_per_pixel)
+        .chain(std::iter::once(current))
+        .collect();
+
+    let size = match target.resolution {
+        None => current.size(),
+        Some(resolution) => {
+            let native = modes
+                .iter()
+                .map(ModeInfo::size)
+                .max_by_key(|&x| (area(x), x))
+                .unwrap_or(current.size());
+            let wanted = match resolution {
+                Resolution::Native => native,
+                Resolution::Half => (native.0 / 2, native.1 / 2),
+                Resolution::Size { width, height } => (width, height),
+            };
+            // Ties go to the larger resolution, so text does not get smaller than asked for.
+            let size = modes
+                .iter()
+                .map(ModeInfo::size)
+                .min_by_key(|&(width, height)| {
+                    (
+                        width.abs_diff(wanted.0) + height.abs_diff(wanted.1),
+                        Reverse((width, height)),
+                    )
+                })
+                .unwrap_or(current.size());
+            if size != wanted {
+                notes.push(format!(
+                    "{}x{} is not supported, using the closest resolution {}x{} instead.",
+                    wanted.0, wanted.1, size.0, size.1
+                ));
+            }
+            size
+        }
+    };
+
+    let sized: Vec<_> = modes.into_iter().filter(|x| x.size() == size).collect();
+    let mut rates: Vec<_> = sized.iter().map(|x| x.frequency).collect();
+    rates.sort_unstable();
+    rates.dedup();
+    let (rate, note) = choose_refresh_rate(&rates, target.refresh_rate.as_ref(), current.frequency);
+    if let Some(note) = note {
+        notes.push(format!("At {}x{}, {}", size.0, size.1, note));
+    }
+    let mode = sized
+        .into_iter()
+        .find(|x| x.frequency == rate)
+        .unwrap_or(current);
+    (mode, notes)
+}
+
+/// Picks one of the refresh rates supported at a resolution, which are sorted and unique.
+/// Also returns an explanation if the rate is not what was asked for.
+fn choose_refresh_rate(
+    rates: &[u32],
+    mode: Option<&RefreshRateMode>,
+    current: u32,
+) -> (u32, Option<String>) {
+    let (Some(&lowest), Some(&highest)) = (rates.first(), rates.last()) else {
+        return (current, None);
+    };
+    // Ties go to the lower rate, which saves power.
+    let closest = |rate: u32| {
+        let closest = rates
+            .iter()
+            .copied()
+            .min_by_key(|x| (x.abs_diff(rate), *x))
+            .unwrap_or(current);
+        let note = (closest != rate).then(|| {
+            format!(
+                "{} Hz is not supported, using the closest refresh rate {} Hz instead.",
+                rate, closest
+            )
+        });
+        (closest, note)
+    };
+    let at_most = |rate: u32| rates.iter().rev().copied().find(|x| *x <= rate);
+    let lowest_instead = |what: String| {
+        let note = format!("{} is supported, using the lowest, {} Hz.", what, lowest);
+        (lowest, Some(note))
+    };
+
+    match mode {
+        Some(RefreshRateMode::Max) => (highest, None),
+        Some(RefreshRateMode::Min) => (lowest, None),
+        Some(RefreshRateMode::Value(rate)) => closest(*rate),
+        None | Some(RefreshRateMode::Restore) => closest(current),
+        Some(RefreshRateMode::Preferred(preferred)) => {
+            if let Some(rate) = preferred.iter().copied().find(|x| rates.contains(x)) {
+                return (rate, None);
+            }
+            let first = preferred.first().copied().unwrap_or(highest);
+            let listed: Vec<_> = preferred.iter().map(ToString::to_string).collect();
+            let what = format!("none of {} Hz", listed.join(", "));
+            match at_most(first) {
+                Some(rate) => (
+                    rate,
+                    Some(format!("{} is supported, using {} Hz instead.", what, rate)),
+                ),
+                None => lowest_instead(what),
+            }
+        }
+        Some(RefreshRateMode::AtMost(cap)) => match at_most(*cap) {
+            Some(rate) => (rate, None),
+            None => lowest_instead(format!("no refresh rate up to {} Hz", cap)),
+        },
+        Some(RefreshRateMode::AtLeast(floor)) => match rates.iter().copied().find(|x| x >= floor) {
+            Some(rate) => (rate, None),
+            None => (
+                highest,
+                Some(format!(
+                    "no refresh rate of at least {} Hz is supported, using the highest, {} Hz.",
+                    floor, highest
+                )),
+            ),
+        },
+        Some(RefreshRateMode::Ratio(ratio)) => {
+            let cap = (highest as f64 * ratio).round() as u32;
+            match at_most(cap) {
+                Some(rate) => (rate, None),
+                None => lowest_instead(format!(
+                    "no refresh rate up to {} Hz, {} of {} Hz,",
+                    cap, ratio, highest
+                )),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(width: u32, height: u32, frequency: u32) -> ModeInfo {
+        ModeInfo {
+            width,
+            height,
+            bits_per_pixel: 32,
+            frequency,
+        }
+    }
+
+    /// A 4K display that supports 1440p, but not 1080p at 144 Hz.
+    fn modes() -> Vec<ModeInfo> {
+        vec![
+            mode(3840, 2160, 60),
+            mode(3840, 2160, 144),
+            mode(2560, 1440, 60),
+            mode(2560, 1440, 120),
+            mode(1920, 1080, 60),
+            ModeInfo {
+                bits_per_pixel: 16,
+                ..mode(1920, 1080, 144)
+            },
+        ]
+    }
+
+    fn target(
+        refresh_rate: Option<RefreshRateMode>,
+        resolution: Option<Resolution>,
+    ) -> DisplayMode {
+        DisplayMode {
+            refresh_rate,
+            resolution,
+        }
+    }
+
+    #[test]
+    fn the_resolution_is_kept_without_one() {
+        let current = mode(3840, 2160, 60);
+        assert_eq!(
+            choose_mode(&modes(), current, target(Some(RefreshRateMode::Max), None)),
+            (mode(3840, 2160, 144), vec![])
+        );
+        assert_eq!(
+            choose_mode(&modes(), current, target(Some(RefreshRateMode::Min), None)),
+            (current, vec![])
+        );
+    }
+
+    #[test]
+    fn presets_are_relative_to_the_native_resolution() {
+        let current = mode(2560, 1440, 60);
+        assert_eq!(
+            choose_mode(
+                &modes(),
+                current,
+                target(Some(RefreshRateMode::Max), Some(Resolution::Native))
+            ),
+            (mode(3840, 2160, 144), vec![])
+        );
+        assert_eq!(
+            choose_mode(&modes(), current, target(None, Some(Resolution::Half))),
+            (mode(1920, 1080, 60), vec![])
+        );
+    }
+
+    #[test]
+    fn unsupported_targets_fall_back_to_the_closest_mode() {
+        let current = mode(3840, 2160, 144);
+        let (chosen, notes) = choose_mode(
+            &modes(),
+            current,
+            target(
+                Some(RefreshRateMode::Value(144)),
+                Some(Resolution::Size {
+                    width: 2500,
+                    height: 1400,
+                }),
+            ),
+        );
+        assert_eq!(chosen, mode(2560, 1440, 120));
+        assert_eq!(
+            notes,
+            vec![
+                "2500x1400 is not supported, using the closest resolution 2560x1440 instead.",
+                "At 2560x1440, 144 Hz is not supported, using the closest refresh rate 120 Hz instead.",
+            ]
+        );
+
+        // Other color depths are never picked.
+        let (chosen, notes) = choose_mode(
+            &modes(),
+            current,
+            target(
+                None,
+                Some(Resolution::Size {
+                    width: 1920,
+                    height: 1080,
+                }),
+            ),
+        );
+        assert_eq!(chosen, mode(1920, 1080, 60));
+        assert_eq!(
+            notes,
+            vec!["At 1920x1080, 144 Hz is not supported, using the closest refresh rate 60 Hz instead."]
+        );
+    }
+
+    const RATES: [u32; 6] = [48, 60, 90, 120, 144, 165];
+
+    fn rate(rates: &[u32], mode: RefreshRateMode) -> u32 {
+        choose_refresh_rate(rates, Some(&mode), 60).0
+    }
+
+    #[test]
+    fn policies_pick_the_expected_rate() {
+        let rates = [60, 90, 120, 144];
+        let cases = [
+            (RefreshRateMode::Max, 144),
+            (RefreshRateMode::Min, 60),
+            (RefreshRateMode::Value(100), 90),
+            // Ties go to the lower rate.
+            (RefreshRateMode::Value(105), 90),
+            (RefreshRateMode::Preferred(vec![165, 120, 60]), 120),
+            (RefreshRateMode::Preferred(vec![100, 75]), 90),
+            (RefreshRateMode::Preferred(vec![50]), 60),
+            (RefreshRateMode::AtMost(100), 90),
+            (RefreshRateMode::AtMost(30), 60),
+            (RefreshRateMode::AtLeast(100), 120),
+            (RefreshRateMode::AtLeast(200), 144),
+            (RefreshRateMode::Ratio(0.5), 60),
+            (RefreshRateMode::Ratio(0.85), 120),
+            (RefreshRateMode::Ratio(1.0), 144),
+        ];
+        for (mode, expected) in cases {
+            assert_eq!(rate(&rates, mode.clone()), expected, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn fallbacks_are_explained() {
+        let rates = [60, 90];
+        let note = |mode| choose_refresh_rate(&rates, Some(&mode), 60).1;
+        assert_eq!(note(RefreshRateMode::AtMost(100)), None);
+        assert_eq!(
+            note(RefreshRateMode::Preferred(vec![120, 75])),
+            Some("none of 120, 75 Hz is supported, using 90 Hz instead.".to_owned())
+        );
+        assert_eq!(
+            note(RefreshRateMode::AtLeast(120)),
+            Some(
+                "no refresh rate of at least 120 Hz is supported, using the highest, 90 Hz."
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            note(RefreshRateMode::AtMost(50)),
+            Some("no refresh rate up to 50 Hz is supported, using the lowest, 60 Hz.".to_owned())
+        );
+    }
+
+    /// Checks every policy against every set of supported rates.
+    #[test]
+    fn policies_hold_for_every_set_of_rates() {
+        let subsets = (1..1u32 << RATES.len()).map(|bits| {
+            RATES
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| bits & (1 << i) != 0)
+                .map(|(_, x)| *x)
+                .collect::<Vec<_>>()
+        });
+        for rates in subsets {
+            let (lowest, highest) = (rates[0], rates[rates.len() - 1]);
+            for asked in (40..=180).step_by(5) {
+                let closest = rate(&rates, RefreshRateMode::Value(asked));
+                assert!(rates.iter().all(|x| {
+                    let (a, b) = (x.abs_diff(asked), closest.abs_diff(asked));
+                    a > b || (a == b && *x >= closest)
+                }));
+
+                let capped = rate(&rates, RefreshRateMode::AtMost(asked));
+                match rates.iter().rev().find(|x| **x <= asked) {
+                    Some(expected) => assert_eq!(capped, *expected),
+                    None => assert_eq!(capped, lowest),
+                }
+
+                let floored = rate(&rates, RefreshRateMode::AtLeast(asked));
+                match rates.iter().find(|x| **x >= asked) {
+                    Some(expected) => assert_eq!(floored, *expected),
+                    None => assert_eq!(floored, highest),
+                }
+
+                let preferred = rate(&rates, RefreshRateMode::Preferred(vec![asked, 60]));
+                if rates.contains(&asked) {
+                    assert_eq!(preferred, asked);
+                } else if rates.contains(&60) {
+                    assert_eq!(preferred, 60);
+                } else {
+                    assert!(preferred <= asked || preferred == lowest);
+                }
+            }
+            for tenths in 1..=10 {
+                let ratio = tenths as f64 / 10.0;
+                let chosen = rate(&rates, RefreshRateMode::Ratio(ratio));
+                assert!(chosen as f64 <= (highest as f64 * ratio).round() || chosen == lowest);
+            }
+            assert_eq!(rate(&rates, RefreshRateMode::Max), highest);
+            assert_eq!(rate(&rates, RefreshRateMode::Min), lowest);
+        }
+    }
+
+    #[test]
+    fn ties_are_broken_the_same_whatever_the_order() {
+        // Tied on the resolution and refresh rate, but only one has the current color depth.
+        let deeper = ModeInfo {
+            bits_per_pixel: 32,
+            ..mode(1920, 1080, 60)
+        };
+        let shallower = ModeInfo {
+            bits_per_pixel: 16,
+            ..deeper
+        };
+        let current = mode(1920, 1080, 90);
+        for modes in [[shallower, deeper, current], [current, deeper, shallower]] {
+            let (chosen, _) =
+                choose_mode(&modes, current, target(Some(RefreshRateMode::Min), None));
+            assert_eq!(chosen, deeper);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use super::{RefreshRateMode, Resolution};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A display attached to the desktop.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Monitor {
+    /// The device name, like `\\.\DISPLAY1`.
+    pub name: String,
+    /// The manufacturer and product code from the EDID, like `GSM5B7F`.
+    pub id: String,
+    /// Whether the display is a panel built into the machine.
+    pub internal: bool,
+    pub primary: bool,
+}
+
+/// What a profile changes about one display. Anything that is [`None`] stays as close to the
+/// current mode as the display allows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayMode {
+    pub refresh_rate: Option<RefreshRateMode>,
+    pub resolution: Option<Resolution>,
+}
+
+/// How the monitors a rule matches are changed.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct MonitorRule {
+    /// `internal` or `external`, or the device name (`\\.\DISPLAY2`) or EDID id (`GSM5B7F`) of
+    /// one monitor.
+    pub monitor: String,
+    /// Keeps the current refresh rate when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_rate: Option<RefreshRateMode>,
+    /// Keeps the current resolution when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<Resolution>,
+}
+
+impl MonitorRule {
+    pub fn matches(&self, monitor: &Monitor) -> bool {
+        match self.monitor.to_ascii_lowercase().as_str() {
+            "internal" => monitor.internal,
+            "external" => !monitor.internal,
+            name => {
+                monitor.name.eq_ignore_ascii_case(name) || monitor.id.eq_ignore_ascii_case(name)
+            }
+        }
+    }
+}
+
+/// Picks the mode of each monitor. Without any rules, `fallback` applies to the primary monitor
+/// only. Otherwise the first rule that matches a monitor applies, and monitors without a rule
+/// are left untouched.
+pub fn select_display_modes<'a>(
+    rules: &[MonitorRule],
+    fallback: DisplayMode,
+    monitors: &'a [Monitor],
+) -> Vec<(&'a Monitor, DisplayMode)> {
+    monitors
+        .iter()
+        .filter_map(|monitor| match rules.is_empty() {
+            true => monitor.primary.then(|| (monitor, fallback.clone())),
+            false => rules.iter().find(|x| x.matches(monitor)).map(|x| {
+                let mode = DisplayMode {
+                    refresh_rate: x.refresh_rate.clone(),
+                    resolution: x.resolution,
+                };
+                (monitor, mode)
+            }),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitors() -> Vec<Monitor> {
+        vec![
+            Monitor {
+                name: r"\\.\DISPLAY1".to_owned(),
+                id: "GSM5B7F".to_owned(),
+                internal: false,
+                primary: true,
+            },
+            Monitor {
+                name: r"\\.\DISPLAY2".to_owned(),
+                id: "BOE0868".to_owned(),
+                internal: true,
+                primary: false,
+            },
+            Monitor {
+                name: r"\\.\DISPLAY3".to_owned(),
+                id: "DEL4123".to_owned(),
+                internal: false,
+                primary: false,
+            },
+        ]
+    }
+
+    fn rule(monitor: &str, refresh_rate: RefreshRateMode) -> MonitorRule {
+        MonitorRule {
+            monitor: monitor.to_owned(),
+            refresh_rate: Some(refresh_rate),
+            resolution: None,
+        }
+    }
+
+    fn select(rules: &[MonitorRule], fallback: RefreshRateMode) -> Vec<(String, DisplayMode)> {
+        let fallback = DisplayMode {
+            refresh_rate: Some(fallback),
+            resolution: Some(Resolution::Native),
+        };
+        select_display_modes(rules, fallback, &monitors())
+            .into_iter()
+            .map(|(monitor, mode)| (monitor.name.clone(), mode))
+            .collect()
+    }
+
+    fn changed(name: &str, refresh_rate: RefreshRateMode) -> (String, DisplayMode) {
+        let mode = DisplayMode {
+            refresh_rate: Some(refresh_rate),
+            resolution: None,
+        };
+        (name.to_owned(), mode)
+    }
+
+    #[test]
+    fn only_the_primary_monitor_changes_without_rules() {
+        let mode = DisplayMode {
+            refresh_rate: Some(RefreshRateMode::Min),
+            resolution: Some(Resolution::Native),
+        };
+        assert_eq!(
+            select(&[], RefreshRateMode::Min),
+            vec![(r"\\.\DISPLAY1".to_owned(), mode)]
+        );
+    }
+
+    #[test]
+    fn the_first_matching_rule_applies() {
+        let rules = [
+            rule("gsm5b7f", RefreshRateMode::Value(144)),
+            rule("internal", RefreshRateMode::Min),
+            rule("external", RefreshRateMode::Max),
+        ];
+        assert_eq!(
+            select(&rules, RefreshRateMode::Min),
+            vec![
+                changed(r"\\.\DISPLAY1", RefreshRateMode::Value(144)),
+                changed(r"\\.\DISPLAY2", RefreshRateMode::Min),
+                changed(r"\\.\DISPLAY3", RefreshRateMode::Max),
+            ]
+        );
+    }
+
+    #[test]
+    fn monitors_without_a_rule_are_untouched() {
+        let rules = [rule(r"\\.\display3", RefreshRateMode::Value(60))];
+        assert_eq!(
+            select(&rules, RefreshRateMode::Max),
+            vec![changed(r"\\.\DISPLAY3", RefreshRateMode::Value(60))]
+        );
+    }
+}
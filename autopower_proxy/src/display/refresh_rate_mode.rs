@@ -1,8 +1,26 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
 pub enum RefreshRateMode {
+    /// The highest refresh rate the display supports at its current resolution.
     Max,
+    /// A refresh rate in Hz. The closest one the display supports is used, or the lower one
+    /// when two are just as close.
     Value(u32),
+    /// The lowest refresh rate the display supports at its current resolution.
     Min,
+    /// The first of these refresh rates in Hz that the display supports. Otherwise the highest
+    /// one below the first, or the lowest supported one.
+    Preferred(Vec<u32>),
+    /// The highest refresh rate up to this many Hz, or the lowest supported one.
+    AtMost(u32),
+    /// The lowest refresh rate of at least this many Hz, or the highest supported one.
+    AtLeast(u32),
+    /// The highest refresh rate up to this share of the highest supported one, like `0.5` for
+    /// 72 Hz on a 144 Hz display. Otherwise the lowest supported one.
+    Ratio(f64),
+    /// The exact mode the display had when this profile was last left, including its
+    /// resolution. The display is left alone until then.
+    Restore,
 }
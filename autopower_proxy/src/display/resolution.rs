@@ -0,0 +1,12 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    /// The largest resolution the display supports.
+    Native,
+    /// Half the width and height of the native resolution.
+    Half,
+    /// A resolution in pixels. The closest one the display supports is used.
+    Size { width: u32, height: u32 },
+}
@@ -0,0 +1,175 @@
+use crate::backend::DisplayBackend;
+use autopower_shared::logging::Logger;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+type Result<T> = crate::Result<T>;
+
+static LOGGER: Logger = Logger::new("saved_modes", "autopower_proxy");
+
+/// A display mode exactly as the system reported it. Only the backend that saved it can read it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct SavedMode(pub String);
+
+/// The saved mode of each monitor, by device name.
+pub type MonitorModes = BTreeMap<String, SavedMode>;
+
+/// The mode of every monitor when each profile was last left, so
+/// [`RefreshRateMode::Restore`](super::RefreshRateMode::Restore) can bring it back.
+/// Kept on disk, so it survives restarts of the proxy.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SavedModes {
+    /// The profile that is left on the next change.
+    active: Option<String>,
+    profiles: BTreeMap<String, MonitorModes>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl SavedModes {
+    pub const FILE_NAME: &str = "saved_display_modes.json";
+
+    /// Reads the saved modes from the directory, starting over if there are none yet.
+    pub fn open(dir: &Path) -> Self {
+        let path = dir.join(Self::FILE_NAME);
+        let saved = match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+                LOGGER.error(format!(
+                    "Could not read the saved display modes at {}, starting over!\n{}",
+                    path.display(),
+                    e
+                ));
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        };
+        Self {
+            path: Some(path),
+            ..saved
+        }
+    }
+
+    pub fn get(&self, profile: &str) -> Option<&MonitorModes> {
+        self.profiles.get(profile)
+    }
+
+    /// Reads the mode of every monitor, to save for the active profile once it has been left for
+    /// `next`. There is nothing to read if it is not left.
+    pub fn read(&self, next: &str, display: &dyn DisplayBackend) -> Result<Option<MonitorModes>> {
+        if self.active.as_deref().is_none_or(|x| x == next) {
+            return Ok(None);
+        }
+        let mut modes = MonitorModes::new();
+        for monitor in display.get_monitors()? {
+            let mode = display.save_mode(&monitor)?;
+            modes.insert(monitor.name, mode);
+        }
+        Ok(Some(modes))
+    }
+
+    /// Saves the modes [`read`](Self::read) for the profile that was left for `next`, once the
+    /// change succeeded. Without them, the profile keeps the modes it was left with before.
+    pub fn leave(&mut self, next: &str, modes: Option<MonitorModes>) -> Result<()> {
+        if let (Some(active), Some(modes)) = (self.active.take(), modes) {
+            LOGGER.debug(format!("Saved display modes of {}: {:?}", active, modes));
+            self.profiles.insert(active, modes);
+        }
+        self.active = Some(next.to_owned());
+        self.write()
+    }
+
+    fn write(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Could not create {}!\n{}", dir.display(), e))?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?).map_err(|e| {
+            format!(
+                "Could not write the saved display modes to {}!\n{}",
+                path.display(),
+                e
+            )
+            .into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{FakeSystemInfo, RecordingBackend},
+        display::ModeInfo,
+    };
+
+    #[test]
+    fn modes_are_saved_for_the_profile_that_is_left() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = RecordingBackend::default();
+        let tuned = ModeInfo {
+            width: 1280,
+            height: 720,
+            bits_per_pixel: 32,
+            frequency: 50,
+        };
+        backend.set_system_info(FakeSystemInfo {
+            modes: [(r"\\.\DISPLAY1".to_owned(), tuned)].into(),
+            ..Default::default()
+        });
+
+        let leave = |saved: &mut SavedModes, next| {
+            let modes = saved.read(next, &backend).unwrap();
+            saved.leave(next, modes).unwrap();
+        };
+        let mut saved = SavedModes::open(dir.path());
+        leave(&mut saved, "Wired");
+        assert_eq!(saved.get("Wired"), None);
+        leave(&mut saved, "Battery");
+
+        // A restarted proxy knows which profile it is leaving.
+        let mut saved = SavedModes::open(dir.path());
+        leave(&mut saved, "Wired");
+        let modes = saved.get("Battery").unwrap();
+        let monitor = &backend.get_monitors().unwrap()[0];
+        let other = ModeInfo {
+            frequency: 60,
+            ..tuned
+        };
+        backend.set_mode(monitor, &other).unwrap();
+        backend
+            .restore_mode(monitor, &modes[&monitor.name])
+            .unwrap();
+        assert_eq!(backend.get_modes(monitor).unwrap().1, tuned);
+        assert!(saved.get("Wired").is_some());
+    }
+
+    #[test]
+    fn modes_that_could_not_be_read_are_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = RecordingBackend::default();
+        let mut saved = SavedModes::open(dir.path());
+        saved.leave("Wired", None).unwrap();
+
+        backend.set_system_info(FakeSystemInfo {
+            monitor_error: Some("The display driver is not responding!".to_owned()),
+            ..Default::default()
+        });
+        assert!(saved.read("Battery", &backend).is_err());
+        saved.leave("Battery", None).unwrap();
+        assert_eq!(saved.get("Wired"), None);
+
+        backend.set_system_info(FakeSystemInfo::default());
+        let modes = saved.read("Wired", &backend).unwrap();
+        assert!(modes.is_some());
+        saved.leave("Wired", modes).unwrap();
+        assert!(saved.get("Battery").is_some());
+    }
+}
@@ -0,0 +1,260 @@
+use super::{ModeInfo, Monitor, SavedMode};
+use crate::Result;
+use autopower_shared::{logging::Logger, winstr::Win32String};
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        Devices::Display::{
+            DisplayConfigGetDeviceInfo, GetDisplayConfigBufferSizes, QueryDisplayConfig,
+            DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME, DISPLAYCONFIG_MODE_INFO,
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_DISPLAYPORT_EMBEDDED,
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INTERNAL, DISPLAYCONFIG_OUTPUT_TECHNOLOGY_LVDS,
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_UDI_EMBEDDED, DISPLAYCONFIG_PATH_INFO,
+            DISPLAYCONFIG_SOURCE_DEVICE_NAME, QDC_ONLY_ACTIVE_PATHS,
+        },
+        Foundation::HWND,
+        Graphics::Gdi::{
+            ChangeDisplaySettingsExW, EnumDisplayDevicesW, EnumDisplaySettingsW, CDS_TYPE,
+            DEVMODEW, DISPLAY_DEVICEW, DISPLAY_DEVICE_ATTACHED_TO_DESKTOP,
+            DISPLAY_DEVICE_PRIMARY_DEVICE, DISP_CHANGE_BADDUALVIEW, DISP_CHANGE_BADFLAGS,
+            DISP_CHANGE_BADMODE, DISP_CHANGE_BADPARAM, DISP_CHANGE_FAILED, DISP_CHANGE_NOTUPDATED,
+            DISP_CHANGE_RESTART, DISP_CHANGE_SUCCESSFUL, ENUM_CURRENT_SETTINGS,
+            ENUM_DISPLAY_SETTINGS_MODE,
+        },
+    },
+};
+
+static LOGGER: Logger = Logger::new("display", "autopower_proxy");
+
+/// The names of the displays connected through a built-in panel output, like `\\.\DISPLAY1`.
+fn get_internal_display_names() -> Result<Vec<String>> {
+    let mut path_count = 0;
+    let mut mode_count = 0;
+    unsafe {
+        GetDisplayConfigBufferSizes(QDC_ONLY_ACTIVE_PATHS, &mut path_count, &mut mode_count).ok()?
+    };
+    let mut paths = vec![DISPLAYCONFIG_PATH_INFO::default(); path_count as usize];
+    let mut modes = vec![DISPLAYCONFIG_MODE_INFO::default(); mode_count as usize];
+    unsafe {
+        QueryDisplayConfig(
+            QDC_ONLY_ACTIVE_PATHS,
+            &mut path_count,
+            paths.as_mut_ptr(),
+            &mut mode_count,
+            modes.as_mut_ptr(),
+            None,
+        )
+        .ok()?
+    };
+
+    let internal = [
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INTERNAL,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_DISPLAYPORT_EMBEDDED,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_UDI_EMBEDDED,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_LVDS,
+    ];
+    let mut names = vec![];
+    for path in &paths[..path_count as usize] {
+        if !internal.contains(&path.targetInfo.outputTechnology) {
+            continue;
+        }
+        let mut source = DISPLAYCONFIG_SOURCE_DEVICE_NAME::default();
+        source.header.r#type = DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME;
+        source.header.size = size_of::<DISPLAYCONFIG_SOURCE_DEVICE_NAME>() as u32;
+        source.header.adapterId = path.sourceInfo.adapterId;
+        source.header.id = path.sourceInfo.id;
+        let code = unsafe { DisplayConfigGetDeviceInfo(&mut source.header) };
+        if code != 0 {
+            return Err(format!("Could not get the display source name! (code {})", code).into());
+        }
+        names.push(from_wide(&source.viewGdiDeviceName));
+    }
+    Ok(names)
+}
+
+fn from_wide(buf: &[u16]) -> String {
+    let len = buf.iter().position(|x| *x == 0).unwrap_or(buf.len());
+    String::from_utf16_lossy(&buf[..len])
+}
+
+/// Every display attached to the desktop.
+pub fn get_monitors() -> Result<Vec<Monitor>> {
+    let internal = get_internal_display_names()?;
+    let mut display_adapter = DISPLAY_DEVICEW::default();
+    display_adapter.cb = size_of::<DISPLAY_DEVICEW>() as u32;
+
+    let mut monitors = vec![];
+    for i in 0.. {
+        unsafe {
+            if !EnumDisplayDevicesW(None, i, &mut display_adapter, 0).as_bool() {
+                break;
+            }
+        }
+
+        if (display_adapter.StateFlags & DISPLAY_DEVICE_ATTACHED_TO_DESKTOP) == 0 {
+            continue;
+        }
+
+        // Asking for the devices of an adapter returns its monitor, whose device id looks like
+        // `MONITOR\GSM5B7F\{...}\0001`.
+        let display_adapter_name = PCWSTR::from_raw(display_adapter.DeviceName.as_ptr());
+        let mut display = DISPLAY_DEVICEW::default();
+        display.cb = size_of::<DISPLAY_DEVICEW>() as u32;
+        let id = match unsafe { EnumDisplayDevicesW(display_adapter_name, 0, &mut display, 0) }
+            .as_bool()
+        {
+            true => from_wide(&display.DeviceID)
+                .split('\\')
+                .nth(1)
+                .unwrap_or_default()
+                .to_owned(),
+            false => String::new(),
+        };
+
+        let name = from_wide(&display_adapter.DeviceName);
+        let monitor = Monitor {
+            internal: internal.contains(&name),
+            primary: (display_adapter.StateFlags & DISPLAY_DEVICE_PRIMARY_DEVICE) != 0,
+            name,
+            id,
+        };
+        LOGGER.debug(format!("Got monitor: {:?}", monitor));
+        monitors.push(monitor);
+    }
+    Ok(monitors)
+}
+
+fn get_current_display_mode(monitor_name: PCWSTR) -> Result<DEVMODEW> {
+    LOGGER.debug(format!("Getting current display mode for {}", unsafe {
+        monitor_name.display()
+    }));
+    let mut devmode = DEVMODEW::default();
+    devmode.dmSize = size_of::<DEVMODEW>() as u16;
+    unsafe {
+        EnumDisplaySettingsW(Some(&monitor_name), ENUM_CURRENT_SETTINGS, &mut devmode).ok()?;
+    }
+    Ok(devmode)
+}
+
+fn get_display_modes(monitor_name: PCWSTR) -> Result<(Vec<DEVMODEW>, DEVMODEW)> {
+    LOGGER.debug("Getting all display modes...");
+
+    let current_mode = get_current_display_mode(monitor_name)?;
+
+    let mut devmode = DEVMODEW::default();
+    devmode.dmSize = size_of::<DEVMODEW>() as u16;
+
+    let mut buf = vec![];
+    for i in 0.. {
+        unsafe {
+            if !EnumDisplaySettingsW(
+                Some(&monitor_name),
+                ENUM_DISPLAY_SETTINGS_MODE(i),
+                &mut devmode,
+            )
+            .as_bool()
+            {
+                break;
+            }
+        }
+        buf.push(devmode);
+    }
+    LOGGER.debug(format!("Got {} display modes", buf.len()));
+    Ok((buf, current_mode))
+}
+
+fn get_mode_info(devmode: &DEVMODEW) -> ModeInfo {
+    ModeInfo {
+        width: devmode.dmPelsWidth,
+        height: devmode.dmPelsHeight,
+        bits_per_pixel: devmode.dmBitsPerPel,
+        frequency: devmode.dmDisplayFrequency,
+    }
+}
+
+/// Every mode the monitor supports, and the one it is in now.
+pub fn get_supported_modes(monitor: &Monitor) -> Result<(Vec<ModeInfo>, ModeInfo)> {
+    let name = Win32String::from_str(&monitor.name);
+    let (devmodes, current_mode) = get_display_modes(name.get_const())?;
+    Ok((
+        devmodes.iter().map(get_mode_info).collect(),
+        get_mode_info(&current_mode),
+    ))
+}
+
+fn apply_display_mode(monitor: &Monitor, new_mode: &DEVMODEW) -> Result<()> {
+    let name = Win32String::from_str(&monitor.name);
+    unsafe {
+        let flags = ChangeDisplaySettingsExW(
+            name.get_const(),
+            Some(new_mode),
+            HWND::default(),
+            CDS_TYPE(0),
+            None,
+        );
+        if flags != DISP_CHANGE_SUCCESSFUL {
+            let msg = match flags {
+                DISP_CHANGE_BADDUALVIEW => "Could not change display settings! (BADDUALVIEW)",
+                DISP_CHANGE_BADFLAGS => "Could not change display settings! (BADFLAGS)",
+                DISP_CHANGE_BADMODE => "Could not change display settings! (BADMODE)",
+                DISP_CHANGE_BADPARAM => "Could not change display settings! (BADPARAM)",
+                DISP_CHANGE_FAILED => "Could not change display settings! (FAILED)",
+                DISP_CHANGE_NOTUPDATED => "Could not change display settings! (NOTUPDATED)",
+                DISP_CHANGE_RESTART => "Could not change display settings! (RESTART)",
+                _ => "Could not change display settings! (unknown code)",
+            };
+            LOGGER.error(msg);
+            return Err(msg.into());
+        }
+    }
+    Ok(())
+}
+
+/// Switches to one of the modes from [`get_supported_modes`].
+pub fn set_display_mode(monitor: &Monitor, mode: &ModeInfo) -> Result<()> {
+    LOGGER.debug(format!(
+        "Setting display mode of {} to {:?}...",
+        monitor.name, mode
+    ));
+    let name = Win32String::from_str(&monitor.name);
+    let (devmodes, _) = get_display_modes(name.get_const())?;
+    let new_mode = devmodes
+        .iter()
+        .find(|x| get_mode_info(x) == *mode)
+        .ok_or_else(|| format!("{} does not support {:?}!", monitor.name, mode))?;
+    apply_display_mode(monitor, new_mode)
+}
+
+/// Saves the whole `DEVMODEW` as hex, so it is restored exactly.
+pub fn save_display_mode(monitor: &Monitor) -> Result<SavedMode> {
+    let name = Win32String::from_str(&monitor.name);
+    let devmode = get_current_display_mode(name.get_const())?;
+    let bytes = unsafe {
+        std::slice::from_raw_parts(
+            &devmode as *const DEVMODEW as *const u8,
+            size_of::<DEVMODEW>(),
+        )
+    };
+    Ok(SavedMode(
+        bytes.iter().map(|x| format!("{:02x}", x)).collect(),
+    ))
+}
+
+pub fn restore_display_mode(monitor: &Monitor, mode: &SavedMode) -> Result<()> {
+    LOGGER.debug(format!(
+        "Restoring the saved display mode of {}...",
+        monitor.name
+    ));
+    let bytes = (0..mode.0.len())
+        .step_by(2)
+        .map(|i| {
+            mode.0
+                .get(i..i + 2)
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+        })
+        .collect::<Option<Vec<_>>>()
+        .filter(|x| x.len() == size_of::<DEVMODEW>())
+        .ok_or_else(|| format!("Could not read the saved display mode of {}!", monitor.name))?;
+    let devmode = unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const DEVMODEW) };
+    apply_display_mode(monitor, &devmode)
+}
@@ -0,0 +1,11 @@
+//! The parts of the proxy that the `autopower` CLI shares, so both read the config the same way.
+
+pub mod backend;
+pub mod config;
+pub mod display;
+mod rules;
+pub mod session;
+#[cfg(windows)]
+mod toast;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
@@ -1,57 +1,156 @@
 #![windows_subsystem = "windows"]
 
-mod config;
-mod display;
-mod toast;
-
-use config::PowerConfig;
-
+use autopower_proxy::{
+    backend::Backends,
+    config::{ConfigDirs, ConfigStore},
+    display::SavedModes,
+    session::{Session, RECHECK_INTERVAL},
+};
 use autopower_shared::{
     logging::Logger,
-    pipe::{Client, Pipe, PIPE_NAME},
-    proxy_command::{PowerConfigSelection, ProxyCommand},
-    stream::Read,
+    pipe::{security::PeerPolicy, Client, Pipe, PIPE_NAME},
+    proxy_command::{ProxyCommand, ProxyReply},
+    stream::Duplex,
+};
+use std::{
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
+#[cfg(windows)]
 use windows::Win32::System::Com::CoInitialize;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
 static LOGGER: Logger = Logger::new("main", "autopower_proxy");
 
-fn change_power_config(selection: PowerConfigSelection) -> Result<()> {
-    let config = PowerConfig::get_or_create()?;
-    match selection {
-        PowerConfigSelection::Wired => config.get_wired_config().change_to(),
-        PowerConfigSelection::Battery => config.get_battery_config().change_to(),
-    }
-}
+/// How long to wait after the first failed connection. Doubles with every further one, up to
+/// [`MAX_RECONNECT_DELAY`].
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
 
-fn execute_command(command: ProxyCommand) -> Result<()> {
-    match command {
-        ProxyCommand::ChangePowerConfig(selection) => change_power_config(selection),
+/// Returns [`None`] if the command could not be understood.
+fn read_command(input: &mut Pipe<Client, Duplex>) -> Result<Option<ProxyCommand>> {
+    LOGGER.debug("Waiting for input...");
+    let message = input.read_message()?;
+    match bincode::deserialize(&message) {
+        Ok(object) => {
+            LOGGER.debug(format!("Input object:\n{:?}", object));
+            Ok(Some(object))
+        }
+        Err(e) => {
+            LOGGER.error(format!("Could not deserialize command!\n{}", e));
+            Ok(None)
+        }
     }
 }
 
-fn read_command(input: &mut Pipe<Client, Read>) -> Result<ProxyCommand> {
-    LOGGER.debug(format!("Waiting for input..."));
-    let object = input.read_to()?;
-    LOGGER.debug(format!("Input object:\n{:?}", object));
-    Ok(object)
+fn connect() -> Result<Pipe<Client, Duplex>> {
+    let mut pipe = Pipe::create_client(PIPE_NAME)
+        .map_err(|e| format!("Could not create client pipe!\n{}", e))?;
+    let peer = pipe
+        .get_peer()
+        .map_err(|e| format!("Could not identify the service!\n{}", e))?;
+    PeerPolicy::for_service()
+        .and_then(|x| x.validate(&peer))
+        .map_err(|e| format!("Refusing to take commands from {:?}!\n{}", peer, e))?;
+    pipe.handshake()
+        .map_err(|e| format!("Could not connect to the service!\n{}", e))?;
+    Ok(pipe)
 }
 
-fn input_loop() -> Result<()> {
-    let mut input = Pipe::create_client_retrying(PIPE_NAME)
-        .map_err(|e| format!("Could not create client pipe!\n{}", e))?;
+/// Executes commands until the connection to the service breaks.
+fn serve(
+    backends: &Backends,
+    store: &ConfigStore,
+    saved: &mut SavedModes,
+    pipe: &mut Pipe<Client, Duplex>,
+) -> Result<()> {
     LOGGER.debug("Entering input loop...");
+    let session = Mutex::new(Session::new(backends, store, saved));
+    let (stop, stopped) = mpsc::channel::<()>();
+    std::thread::scope(|scope| {
+        scope.spawn(|| recheck_loop(&session, stopped));
+        let result = execute_commands(&session, pipe);
+        drop(stop);
+        result
+    })
+}
+
+/// Checks the rules again every [`RECHECK_INTERVAL`] until `stopped` is dropped.
+fn recheck_loop(session: &Mutex<Session>, stopped: mpsc::Receiver<()>) {
+    while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(RECHECK_INTERVAL) {
+        if let Err(e) = session.lock().unwrap().recheck_rules() {
+            LOGGER.error(format!("Could not check the rules again!\n{}", e));
+        }
+    }
+}
+
+fn execute_commands(session: &Mutex<Session>, pipe: &mut Pipe<Client, Duplex>) -> Result<()> {
     loop {
-        let command = match read_command(&mut input) {
+        let command = match read_command(pipe) {
             Ok(x) => x,
             Err(e) => {
                 LOGGER.error(format!("Could not read command!\n{}", e));
                 return Err(e);
             }
         };
-        execute_command(command).map_err(|e| format!("Could not execute command!\n{}", e))?;
+
+        let reply = match command {
+            Some(command) => {
+                let result = session.lock().unwrap().execute(command);
+                if let Err(e) = &result {
+                    LOGGER.error(format!("Could not execute command!\n{}", e));
+                }
+                ProxyReply::from_result(&result)
+            }
+            None => ProxyReply::Unsupported,
+        };
+        LOGGER.debug(format!("Replying with: {}", reply));
+        pipe.write_as(reply)
+            .map_err(|e| format!("Could not send reply!\n{}", e))?;
+    }
+}
+
+/// Keeps trying to connect until it works, however long the service takes to come back.
+/// The proxy only ends with the session.
+fn connect_retrying() -> Pipe<Client, Duplex> {
+    let mut delay = RECONNECT_DELAY;
+    loop {
+        match connect() {
+            Ok(x) => return x,
+            Err(e) => LOGGER.error(format!(
+                "Could not connect to the service, retrying in {} s...\n{}",
+                delay.as_secs(),
+                e
+            )),
+        }
+        std::thread::sleep(delay);
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Keeps reconnecting as long as the session lasts, for example across service restarts.
+fn input_loop() -> Result<()> {
+    let backends = Arc::new(Backends::platform());
+    let store = Arc::new(ConfigStore::open(&backends)?);
+    if let Err(e) = store.watch(backends.clone()) {
+        LOGGER.error(format!(
+            "Could not watch the config, changes are only picked up after a restart or a reload by the service!\n{}",
+            e
+        ));
+    }
+    let mut saved = SavedModes::open(ConfigDirs::platform()?.state()?);
+    loop {
+        let mut pipe = connect_retrying();
+        if let Err(e) = serve(&backends, &store, &mut saved, &mut pipe) {
+            LOGGER.error(format!(
+                "Lost connection to the service, reconnecting...\n{}",
+                e
+            ));
+        }
     }
 }
 
@@ -59,6 +158,7 @@ fn main() -> Result<()> {
     LOGGER.debug("Starting proxy...");
     Logger::set_panic_hook(&LOGGER);
 
+    #[cfg(windows)]
     unsafe {
         CoInitialize(None)
             .ok()
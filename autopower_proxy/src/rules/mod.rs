@@ -0,0 +1,326 @@
+mod snapshot;
+
+pub use snapshot::{SystemSnapshot, TimeOfDay};
+
+use autopower_shared::proxy_command::PowerConfigSelection;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// An inclusive range of battery percentages.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[schemars(deny_unknown_fields)]
+pub struct PercentRange {
+    #[serde(default)]
+    pub min: u8,
+    #[serde(default = "PercentRange::default_max")]
+    pub max: u8,
+}
+
+impl PercentRange {
+    fn default_max() -> u8 {
+        100
+    }
+
+    fn contains(&self, percent: u8) -> bool {
+        (self.min..=self.max).contains(&percent)
+    }
+}
+
+/// A window of local time from `start` up to, but excluding, `end`.
+/// Windows where `end` is before `start` wrap around midnight.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[schemars(deny_unknown_fields)]
+pub struct TimeWindow {
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+}
+
+impl TimeWindow {
+    fn contains(&self, time: TimeOfDay) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+/// All conditions that are set must hold for a rule to match. A rule without any always matches.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+#[schemars(deny_unknown_fields)]
+pub struct Conditions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power_source: Option<PowerConfigSelection>,
+    /// Never matches if there is no battery.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery_percent: Option<PercentRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<TimeWindow>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_display: Option<bool>,
+    /// The executable name of a process, such as `game.exe`. Case insensitive.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub process_running: Option<String>,
+}
+
+impl Conditions {
+    /// Whether the conditions can start or stop holding without a power event, so they need to
+    /// be checked again from time to time.
+    pub fn changes_over_time(&self) -> bool {
+        self.time.is_some() || self.process_running.is_some() || self.external_display.is_some()
+    }
+
+    pub fn matches(&self, snapshot: &SystemSnapshot) -> bool {
+        self.power_source.is_none_or(|x| x == snapshot.power.source)
+            && self.battery_percent.is_none_or(|range| {
+                snapshot
+                    .power
+                    .battery_percent
+                    .is_some_and(|x| range.contains(x))
+            })
+            && self
+                .time
+                .is_none_or(|x| snapshot.time.is_some_and(|time| x.contains(time)))
+            && self
+                .external_display
+                .is_none_or(|x| snapshot.external_display == Some(x))
+            && self.process_running.as_ref().is_none_or(|name| {
+                snapshot
+                    .processes
+                    .iter()
+                    .flatten()
+                    .any(|x| x.eq_ignore_ascii_case(name))
+            })
+    }
+}
+
+/// Switches to the profile when the conditions hold.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[schemars(deny_unknown_fields)]
+pub struct Rule {
+    #[serde(default)]
+    pub when: Conditions,
+    pub profile: String,
+}
+
+/// Returns the profile of the first rule that matches the snapshot.
+pub fn evaluate<'a>(rules: &'a [Rule], snapshot: &SystemSnapshot) -> Option<&'a str> {
+    rules
+        .iter()
+        .find(|x| x.when.matches(snapshot))
+        .map(|x| x.profile.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use autopower_shared::proxy_command::PowerState;
+
+    fn time(value: &str) -> TimeOfDay {
+        TimeOfDay::try_from(value.to_owned()).unwrap()
+    }
+
+    fn snapshot() -> SystemSnapshot {
+        SystemSnapshot {
+            power: PowerState {
+                source: PowerConfigSelection::Battery,
+                battery_percent: Some(60),
+            },
+            time: Some(time("12:00")),
+            external_display: Some(false),
+            processes: Some(vec!["explorer.exe".to_owned(), "Game.exe".to_owned()]),
+        }
+    }
+
+    fn rule(profile: &str, when: Conditions) -> Rule {
+        Rule {
+            when,
+            profile: profile.to_owned(),
+        }
+    }
+
+    fn on_battery() -> Conditions {
+        Conditions {
+            power_source: Some(PowerConfigSelection::Battery),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn no_rules_match_nothing() {
+        assert_eq!(evaluate(&[], &snapshot()), None);
+    }
+
+    #[test]
+    fn empty_conditions_always_match() {
+        let rules = [rule("Default", Conditions::default())];
+        assert_eq!(evaluate(&rules, &snapshot()), Some("Default"));
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let rules = [
+            rule(
+                "Wired",
+                Conditions {
+                    power_source: Some(PowerConfigSelection::Wired),
+                    ..Default::default()
+                },
+            ),
+            rule("Battery", on_battery()),
+            rule("Fallback", Conditions::default()),
+        ];
+        assert_eq!(evaluate(&rules, &snapshot()), Some("Battery"));
+    }
+
+    #[test]
+    fn all_conditions_must_hold() {
+        let rules = [rule(
+            "Travel",
+            Conditions {
+                external_display: Some(true),
+                ..on_battery()
+            },
+        )];
+        assert_eq!(evaluate(&rules, &snapshot()), None);
+
+        let mut docked = snapshot();
+        docked.external_display = Some(true);
+        assert_eq!(evaluate(&rules, &docked), Some("Travel"));
+    }
+
+    #[test]
+    fn unknown_facts_match_no_conditions() {
+        let unknown = SystemSnapshot::new(snapshot().power);
+        for when in [
+            Conditions {
+                time: Some(TimeWindow {
+                    start: time("00:00"),
+                    end: time("23:59"),
+                }),
+                ..Default::default()
+            },
+            Conditions {
+                external_display: Some(false),
+                ..Default::default()
+            },
+            Conditions {
+                process_running: Some("Game.exe".to_owned()),
+                ..Default::default()
+            },
+        ] {
+            assert_eq!(evaluate(&[rule("Any", when)], &unknown), None);
+        }
+        assert_eq!(
+            evaluate(&[rule("Battery", on_battery())], &unknown),
+            Some("Battery")
+        );
+    }
+
+    #[test]
+    fn battery_percent_range_is_inclusive() {
+        let rules = [rule(
+            "Low",
+            Conditions {
+                battery_percent: Some(PercentRange { min: 20, max: 50 }),
+                ..Default::default()
+            },
+        )];
+        let mut snapshot = snapshot();
+        for (percent, expected) in [(19, None), (20, Some("Low")), (50, Some("Low")), (51, None)] {
+            snapshot.power.battery_percent = Some(percent);
+            assert_eq!(evaluate(&rules, &snapshot), expected, "{}%", percent);
+        }
+    }
+
+    #[test]
+    fn battery_percent_never_matches_without_battery() {
+        let rules = [rule(
+            "Any",
+            Conditions {
+                battery_percent: Some(PercentRange { min: 0, max: 100 }),
+                ..Default::default()
+            },
+        )];
+        let mut snapshot = snapshot();
+        snapshot.power.battery_percent = None;
+        assert_eq!(evaluate(&rules, &snapshot), None);
+    }
+
+    #[test]
+    fn time_window_excludes_its_end() {
+        let window = TimeWindow {
+            start: time("09:00"),
+            end: time("17:30"),
+        };
+        assert!(!window.contains(time("08:59")));
+        assert!(window.contains(time("09:00")));
+        assert!(window.contains(time("17:29")));
+        assert!(!window.contains(time("17:30")));
+    }
+
+    #[test]
+    fn time_window_wraps_around_midnight() {
+        let night = TimeWindow {
+            start: time("22:00"),
+            end: time("06:00"),
+        };
+        assert!(night.contains(time("23:59")));
+        assert!(night.contains(time("00:00")));
+        assert!(night.contains(time("05:59")));
+        assert!(!night.contains(time("06:00")));
+        assert!(!night.contains(time("12:00")));
+        assert!(!night.contains(time("21:59")));
+    }
+
+    #[test]
+    fn processes_match_case_insensitively() {
+        let gaming = |name: &str| {
+            [rule(
+                "Gaming",
+                Conditions {
+                    process_running: Some(name.to_owned()),
+                    ..Default::default()
+                },
+            )]
+        };
+        assert_eq!(evaluate(&gaming("game.EXE"), &snapshot()), Some("Gaming"));
+        assert_eq!(evaluate(&gaming("game"), &snapshot()), None);
+    }
+
+    #[test]
+    fn times_are_parsed_and_validated() {
+        assert_eq!(time("7:05").to_string(), "07:05");
+        for invalid in ["24:00", "12:60", "12", "noon", "12:xx"] {
+            assert!(
+                TimeOfDay::try_from(invalid.to_owned()).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn rules_deserialize_from_config() {
+        let rules: Vec<Rule> = serde_json::from_str(
+            r#"[
+                { "when": { "process_running": "game.exe", "power_source": "Wired" }, "profile": "Gaming" },
+                { "when": { "time": { "start": "22:00", "end": "06:00" } }, "profile": "Night" },
+                { "when": { "battery_percent": { "max": 19 } }, "profile": "Low" },
+                { "profile": "Default" }
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            rules[0].when.power_source,
+            Some(PowerConfigSelection::Wired)
+        );
+        assert_eq!(
+            rules[2].when.battery_percent,
+            Some(PercentRange { min: 0, max: 19 })
+        );
+        assert_eq!(rules[3].when, Conditions::default());
+        assert_eq!(evaluate(&rules, &snapshot()), Some("Default"));
+    }
+}
@@ -0,0 +1,182 @@
+use super::Rule;
+use crate::backend::SystemInfoBackend;
+use autopower_shared::{logging::Logger, proxy_command::PowerState};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+type Result<T> = crate::Result<T>;
+
+static LOGGER: Logger = Logger::new("snapshot", "autopower_proxy");
+
+/// A local wall clock time, written as `"HH:MM"` in the config.
+#[derive(
+    Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay {
+    minutes: u16,
+}
+
+impl TimeOfDay {
+    pub fn new(hour: u8, minute: u8) -> Result<Self> {
+        if hour > 23 || minute > 59 {
+            return Err(format!("{:02}:{:02} is not a valid time of day!", hour, minute).into());
+        }
+        Ok(Self {
+            minutes: hour as u16 * 60 + minute as u16,
+        })
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        let invalid = || format!("Expected a time like \"22:30\", got \"{}\"!", value);
+        let (hour, minute) = value.split_once(':').ok_or_else(invalid)?;
+        let hour = hour.parse().map_err(|_| invalid())?;
+        let minute = minute.parse().map_err(|_| invalid())?;
+        Self::new(hour, minute).map_err(|e| e.to_string())
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(value: TimeOfDay) -> Self {
+        value.to_string()
+    }
+}
+
+impl Display for TimeOfDay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}:{:02}", self.minutes / 60, self.minutes % 60)
+    }
+}
+
+/// Everything rules can be matched against, taken once per event.
+/// Facts no rule asks about, or that could not be found out, are [`None`], and conditions on them
+/// do not hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemSnapshot {
+    pub power: PowerState,
+    pub time: Option<TimeOfDay>,
+    pub external_display: Option<bool>,
+    /// The executable names of all running processes, such as `game.exe`.
+    pub processes: Option<Vec<String>>,
+}
+
+impl SystemSnapshot {
+    /// Knows nothing but the power state.
+    pub fn new(power: PowerState) -> Self {
+        Self {
+            power,
+            time: None,
+            external_display: None,
+            processes: None,
+        }
+    }
+
+    /// Only asks the system for what the rules check. A fact that cannot be found out is logged,
+    /// and keeps only the rules that check it from matching, so the power source still switches.
+    pub fn collect(power: PowerState, rules: &[Rule], system: &dyn SystemInfoBackend) -> Self {
+        let uses = |condition: fn(&Rule) -> bool| rules.iter().any(condition);
+        Self {
+            time: uses(|x| x.when.time.is_some())
+                .then(|| Self::find_out("get the local time", system.get_local_time()))
+                .flatten(),
+            external_display: uses(|x| x.when.external_display.is_some())
+                .then(|| {
+                    Self::find_out("check for external displays", system.has_external_display())
+                })
+                .flatten(),
+            processes: uses(|x| x.when.process_running.is_some())
+                .then(|| Self::find_out("list running processes", system.get_running_processes()))
+                .flatten(),
+            ..Self::new(power)
+        }
+    }
+
+    fn find_out<T>(what: &str, result: Result<T>) -> Option<T> {
+        result
+            .map_err(|e| LOGGER.error(format!("Could not {}!\n{}", what, e)))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{FakeSystemInfo, RecordingBackend},
+        rules::Conditions,
+    };
+    use autopower_shared::proxy_command::PowerConfigSelection;
+
+    const POWER: PowerState = PowerState {
+        source: PowerConfigSelection::Wired,
+        battery_percent: None,
+    };
+
+    fn rule(when: Conditions) -> Rule {
+        Rule {
+            when,
+            profile: "Wired".to_owned(),
+        }
+    }
+
+    #[test]
+    fn snapshot_is_collected_from_the_backend() {
+        let backend = RecordingBackend::default();
+        backend.set_system_info(FakeSystemInfo {
+            time: TimeOfDay::new(22, 15).unwrap(),
+            external_display: true,
+            processes: vec!["game.exe".to_owned()],
+            ..Default::default()
+        });
+        let rules = [
+            rule(Conditions {
+                time: Some(
+                    serde_json::from_str(r#"{ "start": "22:00", "end": "06:00" }"#).unwrap(),
+                ),
+                external_display: Some(true),
+                ..Default::default()
+            }),
+            rule(Conditions {
+                process_running: Some("game.exe".to_owned()),
+                ..Default::default()
+            }),
+        ];
+
+        let snapshot = SystemSnapshot::collect(POWER, &rules, &backend);
+        assert_eq!(
+            snapshot,
+            SystemSnapshot {
+                power: POWER,
+                time: Some(TimeOfDay::new(22, 15).unwrap()),
+                external_display: Some(true),
+                processes: Some(vec!["game.exe".to_owned()]),
+            }
+        );
+    }
+
+    #[test]
+    fn only_facts_the_rules_check_are_collected() {
+        let backend = RecordingBackend::default();
+        backend.set_system_info(FakeSystemInfo {
+            monitor_error: Some("The display driver is not responding!".to_owned()),
+            ..Default::default()
+        });
+        let snapshot = SystemSnapshot::collect(POWER, &[], &backend);
+        assert_eq!(snapshot, SystemSnapshot::new(POWER));
+
+        // Failing to find out does not keep the others from being collected.
+        let rules = [rule(Conditions {
+            external_display: Some(false),
+            process_running: Some("game.exe".to_owned()),
+            ..Default::default()
+        })];
+        let snapshot = SystemSnapshot::collect(POWER, &rules, &backend);
+        assert_eq!(snapshot.external_display, None);
+        assert_eq!(snapshot.processes, Some(vec![]));
+    }
+}
@@ -0,0 +1,354 @@
+use crate::{
+    backend::Backends,
+    config::{ConfigStore, PowerConfig, Selection},
+    display::SavedModes,
+};
+use autopower_shared::{
+    logging::Logger,
+    proxy_command::{PowerState, ProxyCommand},
+};
+use std::time::Duration;
+
+type Result<T> = crate::Result<T>;
+
+/// How often [`Session::recheck_rules`] should run while connected.
+pub const RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+static LOGGER: Logger = Logger::new("session", "autopower_proxy");
+
+/// Applies commands from the service during one connection, remembering what is active.
+/// Every new connection applies its first profile, even if it did not change.
+pub struct Session<'a> {
+    backends: &'a Backends,
+    store: &'a ConfigStore,
+    /// Repeated events only apply a change once the selection names another profile.
+    active: Option<Selection>,
+    /// A profile applied by [`ProxyCommand::ApplyProfile`], kept until the power source changes.
+    pinned: Option<String>,
+    /// The last power state the service sent, which rules are checked against again.
+    state: Option<PowerState>,
+    /// Outlives the connection, like the config.
+    saved: &'a mut SavedModes,
+}
+
+impl<'a> Session<'a> {
+    pub fn new(backends: &'a Backends, store: &'a ConfigStore, saved: &'a mut SavedModes) -> Self {
+        Self {
+            backends,
+            store,
+            active: None,
+            pinned: None,
+            state: None,
+            saved,
+        }
+    }
+
+    pub fn execute(&mut self, command: ProxyCommand) -> Result<()> {
+        let config = &self.store.get();
+        match command {
+            // Older services only tell the power source, which is always applied.
+            ProxyCommand::ChangePowerConfig(source) => {
+                self.active = None;
+                self.pinned = None;
+                let state = PowerState {
+                    source,
+                    battery_percent: None,
+                };
+                self.change_power_state(config, state)
+            }
+            ProxyCommand::ChangePowerState(state) => self.change_power_state(config, state),
+            ProxyCommand::ApplyProfile(name) => self.apply_profile(config, name),
+            ProxyCommand::ReloadConfig => self.store.reload(self.backends),
+        }
+    }
+
+    fn change_power_state(&mut self, config: &PowerConfig, state: PowerState) -> Result<()> {
+        if self.state.is_some_and(|x| x.source != state.source) {
+            if let Some(pinned) = self.pinned.take() {
+                LOGGER.debug(format!("Power source changed, unpinning {}.", pinned));
+            }
+        }
+        self.state = Some(state);
+        if let Some(pinned) = &self.pinned {
+            LOGGER.debug(format!("Keeping pinned profile {}.", pinned));
+            return Ok(());
+        }
+
+        let snapshot = config.collect_snapshot(state, self.backends);
+        LOGGER.debug(format!("System snapshot:\n{:?}", snapshot));
+        let selection = config.select(&snapshot, self.active.as_ref());
+        self.apply(config, selection)
+    }
+
+    /// Selects the profile again for the last power state, as rules on the time, running
+    /// processes or displays can start or stop matching without a power event.
+    /// Only changes the profile if the selection changed.
+    pub fn recheck_rules(&mut self) -> Result<()> {
+        let config = &self.store.get();
+        let Some(state) = self.state.filter(|_| config.has_changing_rules()) else {
+            return Ok(());
+        };
+        self.change_power_state(config, state)
+    }
+
+    fn apply_profile(&mut self, config: &PowerConfig, name: String) -> Result<()> {
+        // Make sure the profile is applied, even if it happens to be active already.
+        self.active = None;
+        self.apply(config, Selection::profile(&name))?;
+        self.pinned = Some(name);
+        Ok(())
+    }
+
+    fn apply(&mut self, config: &PowerConfig, selection: Selection) -> Result<()> {
+        if self
+            .active
+            .as_ref()
+            .is_some_and(|x| x.profile == selection.profile)
+        {
+            LOGGER.debug(format!("Profile {} is already active.", selection.profile));
+            self.active = Some(selection);
+            return Ok(());
+        }
+
+        self.active = None;
+        let profile = config.get_profile(&selection.profile)?;
+        // The modes are read before the change, but only saved once it succeeded.
+        let modes = self
+            .saved
+            .read(&selection.profile, self.backends.display.as_ref())
+            .unwrap_or_else(|e| {
+                LOGGER.error(format!("Could not save the display modes!\n{}", e));
+                None
+            });
+        profile.change_to(self.backends, self.saved.get(&selection.profile))?;
+        if let Err(e) = self.saved.leave(&selection.profile, modes) {
+            LOGGER.error(format!("Could not save the display modes!\n{}", e));
+        }
+        self.active = Some(selection);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{BackendCall, FakeSystemInfo, RecordingBackend},
+        config::PowerScheme,
+        display::{ModeInfo, SavedMode},
+    };
+    use autopower_shared::proxy_command::PowerConfigSelection;
+
+    fn power_state(source: PowerConfigSelection, battery_percent: u8) -> ProxyCommand {
+        ProxyCommand::ChangePowerState(PowerState {
+            source,
+            battery_percent: Some(battery_percent),
+        })
+    }
+
+    fn schemes(recorder: &RecordingBackend) -> Vec<PowerScheme> {
+        recorder
+            .calls()
+            .into_iter()
+            .filter_map(|x| match x {
+                BackendCall::SetActiveScheme(x) => Some(x),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_profiles_are_applied_once() {
+        let recorder = RecordingBackend::default();
+        let backends = Backends::new(recorder.clone());
+        let store = ConfigStore::with_config(PowerConfig::default());
+        let mut saved = SavedModes::default();
+        let mut session = Session::new(&backends, &store, &mut saved);
+
+        for percent in [80, 79, 78] {
+            session
+                .execute(power_state(PowerConfigSelection::Battery, percent))
+                .unwrap();
+        }
+        session
+            .execute(power_state(PowerConfigSelection::Wired, 78))
+            .unwrap();
+
+        assert_eq!(
+            schemes(&recorder),
+            vec![PowerScheme::Balanced, PowerScheme::HighPerformance]
+        );
+    }
+
+    #[test]
+    fn applied_profiles_are_pinned_until_the_source_changes() {
+        let recorder = RecordingBackend::default();
+        let backends = Backends::new(recorder.clone());
+        let store = ConfigStore::with_config(PowerConfig::default());
+        let mut saved = SavedModes::default();
+        let mut session = Session::new(&backends, &store, &mut saved);
+
+        session
+            .execute(power_state(PowerConfigSelection::Battery, 80))
+            .unwrap();
+        session
+            .execute(ProxyCommand::ApplyProfile("Wired".to_owned()))
+            .unwrap();
+        session
+            .execute(power_state(PowerConfigSelection::Battery, 79))
+            .unwrap();
+        session
+            .execute(power_state(PowerConfigSelection::Wired, 79))
+            .unwrap();
+        session
+            .execute(power_state(PowerConfigSelection::Battery, 79))
+            .unwrap();
+
+        assert_eq!(
+            schemes(&recorder),
+            vec![
+                PowerScheme::Balanced,
+                PowerScheme::HighPerformance,
+                PowerScheme::Balanced,
+            ]
+        );
+    }
+
+    #[test]
+    fn restored_displays_get_the_mode_they_were_left_with() {
+        let recorder = RecordingBackend::default();
+        let backends = Backends::new(recorder.clone());
+        let mut config = serde_json::to_value(PowerConfig::default()).unwrap();
+        config["profiles"]["Battery"]["screen_refresh_rate"] = "Restore".into();
+        let store = ConfigStore::with_config(serde_json::from_value(config).unwrap());
+        let mut saved = SavedModes::default();
+        let mut session = Session::new(&backends, &store, &mut saved);
+        let tuned = ModeInfo {
+            width: 1280,
+            height: 720,
+            bits_per_pixel: 32,
+            frequency: 50,
+        };
+
+        // Nothing was saved yet, so the display is left alone.
+        session
+            .execute(power_state(PowerConfigSelection::Battery, 80))
+            .unwrap();
+        recorder.set_system_info(FakeSystemInfo {
+            modes: [(r"\\.\DISPLAY1".to_owned(), tuned)].into(),
+            ..Default::default()
+        });
+        session
+            .execute(power_state(PowerConfigSelection::Wired, 80))
+            .unwrap();
+        session
+            .execute(power_state(PowerConfigSelection::Battery, 80))
+            .unwrap();
+
+        let displays: Vec<_> = recorder
+            .calls()
+            .into_iter()
+            .filter(|x| {
+                matches!(
+                    x,
+                    BackendCall::SetMode { .. } | BackendCall::RestoreMode { .. }
+                )
+            })
+            .collect();
+        assert_eq!(displays.len(), 2);
+        assert_eq!(
+            displays[1],
+            BackendCall::RestoreMode {
+                monitor: r"\\.\DISPLAY1".to_owned(),
+                mode: SavedMode(serde_json::to_string(&tuned).unwrap()),
+            }
+        );
+    }
+
+    #[test]
+    fn modes_are_not_saved_when_the_change_fails() {
+        let recorder = RecordingBackend::default();
+        let backends = Backends::new(recorder.clone());
+        let mut config = serde_json::to_value(PowerConfig::default()).unwrap();
+        config["profiles"]["Battery"]["screen_refresh_rate"] = "Restore".into();
+        let store = ConfigStore::with_config(serde_json::from_value(config).unwrap());
+        let mut saved = SavedModes::default();
+        // Battery was left with a mode the display can not go back to.
+        saved.leave("Battery", None).unwrap();
+        let broken = [(r"\\.\DISPLAY1".to_owned(), SavedMode("broken".to_owned()))];
+        saved.leave("Wired", Some(broken.into())).unwrap();
+
+        let mut session = Session::new(&backends, &store, &mut saved);
+        assert!(session
+            .execute(power_state(PowerConfigSelection::Battery, 80))
+            .is_err());
+        assert_eq!(saved.get("Wired"), None);
+    }
+
+    #[test]
+    fn rules_are_rechecked_without_a_power_event() {
+        let recorder = RecordingBackend::default();
+        let backends = Backends::new(recorder.clone());
+        let mut config = serde_json::to_value(PowerConfig::default()).unwrap();
+        config["rules"] = serde_json::json!([
+            { "when": { "process_running": "game.exe" }, "profile": "Wired" }
+        ]);
+        let store = ConfigStore::with_config(serde_json::from_value(config).unwrap());
+        let mut saved = SavedModes::default();
+        let mut session = Session::new(&backends, &store, &mut saved);
+
+        // There is nothing to check before the first power event.
+        session.recheck_rules().unwrap();
+        session
+            .execute(power_state(PowerConfigSelection::Battery, 80))
+            .unwrap();
+        session.recheck_rules().unwrap();
+        recorder.set_system_info(FakeSystemInfo {
+            processes: vec!["game.exe".to_owned()],
+            ..Default::default()
+        });
+        session.recheck_rules().unwrap();
+        session.recheck_rules().unwrap();
+
+        assert_eq!(
+            schemes(&recorder),
+            vec![PowerScheme::Balanced, PowerScheme::HighPerformance]
+        );
+    }
+
+    #[test]
+    fn the_scheme_changes_when_monitors_cannot_be_listed() {
+        let recorder = RecordingBackend::default();
+        recorder.set_system_info(FakeSystemInfo {
+            monitor_error: Some("The display driver is not responding!".to_owned()),
+            ..Default::default()
+        });
+        let backends = Backends::new(recorder.clone());
+        let mut config = serde_json::to_value(PowerConfig::default()).unwrap();
+        config["rules"] = serde_json::json!([
+            { "when": { "external_display": true }, "profile": "Wired" }
+        ]);
+        let store = ConfigStore::with_config(serde_json::from_value(config).unwrap());
+        let mut saved = SavedModes::default();
+        let mut session = Session::new(&backends, &store, &mut saved);
+
+        // The rule cannot be checked and the displays cannot be changed, but the rest can.
+        assert!(session
+            .execute(power_state(PowerConfigSelection::Battery, 80))
+            .is_err());
+        assert_eq!(schemes(&recorder), vec![PowerScheme::Balanced]);
+    }
+
+    #[test]
+    fn unknown_profiles_are_an_error() {
+        let recorder = RecordingBackend::default();
+        let backends = Backends::new(recorder.clone());
+        let store = ConfigStore::with_config(PowerConfig::default());
+        let mut saved = SavedModes::default();
+        let mut session = Session::new(&backends, &store, &mut saved);
+
+        let command = ProxyCommand::ApplyProfile("Gaming".to_owned());
+        assert!(session.execute(command).is_err());
+        assert!(recorder.calls().is_empty());
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{
+    logging::Logger,
+    pipe::Pipe,
+    stream::{Readable, Writable},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// The protocol version this build speaks. Bump this whenever [`crate::proxy_command`] changes.
+pub const PROTOCOL_VERSION: u32 = 4;
+/// The oldest protocol version this build can still fall back to.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+static LOGGER: Logger = Logger::new("handshake", "autopower_shared");
+
+/// The first message both ends send after connecting.
+/// The layout of this must never change, so that any two versions can understand each other.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub min_protocol_version: u32,
+    pub crate_version: String,
+}
+
+impl Hello {
+    pub fn current() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            crate_version: env!("CARGO_PKG_VERSION").to_owned(),
+        }
+    }
+
+    /// Picks the newest protocol version both ends support.
+    /// Both ends arrive at the same result, so they agree on whether to continue.
+    pub fn negotiate(&self, peer: &Hello) -> Result<u32> {
+        let version = self.protocol_version.min(peer.protocol_version);
+        let min_version = self.min_protocol_version.max(peer.min_protocol_version);
+        if version < min_version {
+            return Err(format!(
+                "Incompatible protocol versions! This is {} and the other end is {}. Please make sure autopower.exe and autopower_proxy.exe are from the same release.",
+                self, peer
+            )
+            .into());
+        }
+        Ok(version)
+    }
+}
+
+impl Display for Hello {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "v{} (protocol {}, supports {}-{})",
+            self.crate_version,
+            self.protocol_version,
+            self.min_protocol_version,
+            self.protocol_version
+        )
+    }
+}
+
+impl<M, S: Readable + Writable> Pipe<M, S> {
+    /// Exchanges [`Hello`]s with the other end and returns the negotiated protocol version.
+    /// Must be done by both ends right after connecting.
+    pub fn handshake(&mut self) -> Result<u32> {
+        let own = Hello::current();
+        self.write_as(&own)?;
+        let peer: Hello = self
+            .read_to()
+            .map_err(|e| format!("Could not read handshake!\n{}", e))?;
+
+        let version = own.negotiate(&peer).inspect_err(|e| LOGGER.error(e))?;
+        if own.crate_version != peer.crate_version {
+            LOGGER.error(format!(
+                "Version mismatch! This is {} and the other end is {}. Continuing with protocol {}.",
+                own, peer, version
+            ));
+        } else {
+            LOGGER.debug(format!("Handshake done, using protocol {}.", version));
+        }
+        Ok(version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(min_protocol_version: u32, protocol_version: u32) -> Hello {
+        Hello {
+            protocol_version,
+            min_protocol_version,
+            crate_version: "0.0.0".to_owned(),
+        }
+    }
+
+    #[test]
+    fn same_versions_agree() {
+        assert_eq!(hello(1, 1).negotiate(&hello(1, 1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn falls_back_to_older_peer() {
+        let new = hello(1, 3);
+        let old = hello(1, 2);
+        assert_eq!(new.negotiate(&old).unwrap(), 2);
+        assert_eq!(old.negotiate(&new).unwrap(), 2);
+    }
+
+    #[test]
+    fn refuses_without_overlap() {
+        let new = hello(3, 4);
+        let old = hello(1, 2);
+        assert!(new.negotiate(&old).is_err());
+        assert!(old.negotiate(&new).is_err());
+    }
+}
@@ -1,6 +1,14 @@
-use std::{fmt::Display, io::Write, path::PathBuf, str::FromStr, sync::OnceLock};
+use std::{fmt::Display, io::Write, path::PathBuf, sync::OnceLock};
 
-const TEMP_PATH: &str = std::env!("TEMP");
+#[cfg(windows)]
+fn get_temp_path() -> PathBuf {
+    PathBuf::from(std::env!("TEMP"))
+}
+
+#[cfg(not(windows))]
+fn get_temp_path() -> PathBuf {
+    std::env::temp_dir()
+}
 
 #[cfg(debug_assertions)]
 const LOG_LEVEL: LogLevel = LogLevel::Debug;
@@ -32,9 +40,10 @@ impl Logger {
         self.log(input, LogLevel::Error);
     }
 
+    /// Also printed to stderr, which keeps stdout for the output of commands.
     #[cfg(debug_assertions)]
     pub fn debug<A: Display>(&self, input: A) {
-        println!("{}", input);
+        eprintln!("{}", input);
         self.log(input, LogLevel::Debug);
     }
 
@@ -49,12 +58,11 @@ impl Logger {
 
     pub fn log<A: Display>(&self, input: A, level: LogLevel) {
         let log_path = self.log_path.get_or_init(|| {
-            let mut log_root = PathBuf::from_str(TEMP_PATH).expect("Could not get debug path!");
-            log_root.push("autopower\\");
+            let mut log_root = get_temp_path();
+            log_root.push("autopower");
             std::fs::create_dir_all(&log_root).unwrap();
 
-            let mut log_path = PathBuf::from_str(TEMP_PATH).expect("Could not get debug path!");
-            log_path.push("autopower\\");
+            let mut log_path = log_root;
             log_path.push(format!("log_{}.txt", self.process_name));
             log_path
         });
@@ -64,7 +72,6 @@ impl Logger {
         }
 
         let mut file = std::fs::File::options()
-            .write(true)
             .append(true)
             .create(true)
             .read(true)
@@ -0,0 +1,115 @@
+use super::Result;
+
+/// Marks the start of every frame, so a desynchronized stream fails loudly instead of decoding garbage.
+pub const FRAME_MAGIC: [u8; 2] = *b"AP";
+pub const HEADER_SIZE: usize = FRAME_MAGIC.len() + size_of::<u32>();
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+fn check_size(size: usize) -> Result<()> {
+    if size > MAX_MESSAGE_SIZE {
+        return Err(format!(
+            "Message of {} bytes exceeds the limit of {} bytes!",
+            size, MAX_MESSAGE_SIZE
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Prefixes the payload with a header holding its length.
+pub fn encode(payload: &[u8]) -> Result<Vec<u8>> {
+    check_size(payload.len())?;
+    let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&FRAME_MAGIC);
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
+
+/// Reassembles frames from bytes as they arrive, however they are split across reads.
+#[derive(Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns `true` if no partial frame is pending.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Takes the next complete payload, if one has fully arrived.
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.buffer.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+        if self.buffer[..FRAME_MAGIC.len()] != FRAME_MAGIC {
+            return Err("Invalid frame header, the stream is out of sync!".into());
+        }
+
+        let mut len_bytes = [0; size_of::<u32>()];
+        len_bytes.copy_from_slice(&self.buffer[FRAME_MAGIC.len()..HEADER_SIZE]);
+        let len = u32::from_le_bytes(len_bytes) as usize;
+        check_size(len)?;
+
+        if self.buffer.len() < HEADER_SIZE + len {
+            return Ok(None);
+        }
+        let payload = self.buffer[HEADER_SIZE..HEADER_SIZE + len].to_vec();
+        self.buffer.drain(..HEADER_SIZE + len);
+        Ok(Some(payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reassembles_split_frames() {
+        let frame = encode(b"hello").unwrap();
+        let mut decoder = FrameDecoder::default();
+        for byte in &frame[..frame.len() - 1] {
+            decoder.push(std::slice::from_ref(byte));
+            assert_eq!(decoder.next_frame().unwrap(), None);
+        }
+        decoder.push(&frame[frame.len() - 1..]);
+        assert_eq!(decoder.next_frame().unwrap(), Some(b"hello".to_vec()));
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn splits_joined_frames() {
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&encode(b"first").unwrap());
+        decoder.push(&encode(b"").unwrap());
+        decoder.push(&encode(b"third").unwrap()[..4]);
+
+        assert_eq!(decoder.next_frame().unwrap(), Some(b"first".to_vec()));
+        assert_eq!(decoder.next_frame().unwrap(), Some(vec![]));
+        assert_eq!(decoder.next_frame().unwrap(), None);
+        assert!(!decoder.is_empty());
+    }
+
+    #[test]
+    fn rejects_oversized_messages() {
+        assert!(encode(&vec![0; MAX_MESSAGE_SIZE + 1]).is_err());
+
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&FRAME_MAGIC);
+        decoder.push(&(MAX_MESSAGE_SIZE as u32 + 1).to_le_bytes());
+        let err = decoder.next_frame().unwrap_err();
+        assert!(err.to_string().contains("exceeds the limit"));
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut decoder = FrameDecoder::default();
+        decoder.push(b"XX\x01\x00\x00\x00a");
+        assert!(decoder.next_frame().is_err());
+    }
+}
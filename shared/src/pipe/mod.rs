@@ -1,14 +1,29 @@
 pub mod client;
+pub mod frame;
+#[cfg(windows)]
+mod named_pipe;
+pub mod security;
 pub mod server;
+pub mod transport;
+#[cfg(unix)]
+mod unix_socket;
 
 pub use super::stream;
 pub use client::Client;
+#[cfg(windows)]
+pub use named_pipe::PIPE_PATH_ROOT;
 pub use server::Server;
+pub use transport::{PeerIdentity, Transport};
+#[cfg(unix)]
+pub use unix_socket::{
+    get_socket_dir, get_socket_path, DEFAULT_SOCKET_DIR, SOCKET_DIR_VAR, SOCKET_GROUP,
+};
 
 use crate::{
     logging::Logger,
-    stream::{FileStream, FileStreamMode},
+    stream::{FileStreamMode, Readable, Writable},
 };
+use frame::FrameDecoder;
 use std::{
     fmt::Debug,
     io::{Read, Write},
@@ -16,67 +31,100 @@ use std::{
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+/// The transport pipes are built on for the current platform.
+#[cfg(windows)]
+pub type PlatformTransport<S> = named_pipe::NamedPipe<S>;
+#[cfg(unix)]
+pub type PlatformTransport<S> = unix_socket::UnixSocket<S>;
+
 pub const PIPE_BUFFER_SIZE: usize = 1024;
-pub const PIPE_PATH_ROOT: &str = "\\\\.\\pipe\\";
 pub const PIPE_NAME: &str = "AutoPowerProxy";
+/// Where the service takes commands from `autopower` itself, such as applying a profile.
+pub const CONTROL_PIPE_NAME: &str = "AutoPowerControl";
 
 static LOGGER: Logger = Logger::new("pipe", "autopower_shared");
 
 pub struct Pipe<M, S: FileStreamMode> {
-    stream: FileStream<S>,
+    transport: PlatformTransport<S>,
+    decoder: FrameDecoder,
     mode: std::marker::PhantomData<M>,
 }
 
-impl<M> Pipe<M, stream::Read> {
+impl<M, S: Readable> Pipe<M, S> {
+    /// Reads the payload of the next message, waiting until all of it has arrived.
+    pub fn read_message(&mut self) -> Result<Vec<u8>> {
+        loop {
+            if let Some(message) = self.decoder.next_frame()? {
+                LOGGER.debug(format!("Got message of {} bytes.", message.len()));
+                return Ok(message);
+            }
+
+            let mut buf = [0; PIPE_BUFFER_SIZE];
+            let count = self.transport.read(&mut buf)?;
+            if count == 0 {
+                return Err(if self.decoder.is_empty() {
+                    "Pipe was closed by the other end.".into()
+                } else {
+                    "Pipe was closed by the other end in the middle of a message.".into()
+                });
+            }
+            self.decoder.push(&buf[..count]);
+        }
+    }
+
     pub fn read_to<T: serde::de::DeserializeOwned + Debug>(&mut self) -> Result<T> {
-        let mut buf = [0; PIPE_BUFFER_SIZE];
-        let count = self.read(&mut buf)?;
-        LOGGER.debug(format!("Got {} bytes. Deserializing...", count));
-        let obj = bincode::deserialize(&mut buf[..count])?;
+        let message = self.read_message()?;
+        LOGGER.debug("Deserializing...");
+        let obj = bincode::deserialize(&message)?;
         LOGGER.debug(format!("Deserialized to {:?}", obj));
         Ok(obj)
     }
 }
 
-impl<M> std::io::Read for Pipe<M, stream::Read> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.stream.read(buf)
+impl<M, S: Writable> Pipe<M, S> {
+    pub fn write_message(&mut self, message: &[u8]) -> Result<()> {
+        LOGGER.debug("Writing to pipe...");
+        let frame = frame::encode(message)?;
+        self.transport.write_all(&frame)?;
+        self.transport.flush()?;
+        Ok(())
     }
-}
 
-impl<M> Pipe<M, stream::Write> {
     pub fn write_as(&mut self, obj: impl serde::Serialize) -> Result<()> {
         let bytes = bincode::serialize(&obj)?;
-        self.write_all(&bytes)?;
-        Ok(())
+        self.write_message(&bytes)
     }
 }
 
-impl<M> std::io::Write for Pipe<M, stream::Write> {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        LOGGER.debug("Writing to pipe...");
-        self.stream.write(buf)
+impl<M, S: FileStreamMode> Pipe<M, S> {
+    fn from_transport(transport: PlatformTransport<S>) -> Self {
+        Self {
+            transport,
+            decoder: FrameDecoder::default(),
+            mode: std::marker::PhantomData,
+        }
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        Ok(())
+    pub fn get_peer(&self) -> Result<PeerIdentity> {
+        self.transport.get_peer()
     }
-}
 
-impl<M, S: FileStreamMode> Pipe<M, S> {
-    pub fn get_stream(&self) -> &FileStream<S> {
-        &self.stream
+    pub fn get_transport(&self) -> &PlatformTransport<S> {
+        &self.transport
     }
 
-    pub fn close(&self) -> Result<()> {
+    pub fn close(&mut self) -> Result<()> {
         LOGGER.debug("Closing pipe...");
-        self.stream.close()
+        self.transport.close()
     }
 }
 
 impl<M, S: FileStreamMode> Drop for Pipe<M, S> {
     fn drop(&mut self) {
         LOGGER.debug("Dropping pipe...");
-        self.close().unwrap();
+        // Closing fails if the peer is already gone, which is no reason to panic.
+        if let Err(e) = self.close() {
+            LOGGER.error(format!("Could not close the pipe!\n{}", e));
+        }
     }
 }
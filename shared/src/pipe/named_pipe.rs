@@ -0,0 +1,205 @@
+use super::{
+    security::{build_pipe_sddl, PIPE_TRUSTEES},
+    transport::{PeerIdentity, Transport},
+    Result, LOGGER, PIPE_BUFFER_SIZE,
+};
+use crate::{
+    stream::{FileStream, FileStreamMode, Readable, Writable},
+    util::{get_last_win32_err, get_process_exe_path_by_id},
+    winstr::Win32String,
+};
+use windows::Win32::{
+    Foundation::{LocalFree, ERROR_PIPE_CONNECTED, HLOCAL},
+    Security::{
+        Authorization::{ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1},
+        PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES,
+    },
+    Storage::FileSystem::{
+        CreateFileW, FILE_ATTRIBUTE_NORMAL, FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_SHARE_READ,
+        FILE_SHARE_WRITE, FILE_WRITE_ATTRIBUTES, OPEN_EXISTING,
+    },
+    System::{
+        Pipes::{
+            ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, GetNamedPipeClientProcessId,
+            GetNamedPipeServerProcessId, SetNamedPipeHandleState, PIPE_READMODE_BYTE,
+            PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES,
+        },
+        RemoteDesktop::ProcessIdToSessionId,
+    },
+};
+
+pub const PIPE_PATH_ROOT: &str = "\\\\.\\pipe\\";
+
+/// A Win32 named pipe in byte mode.
+pub struct NamedPipe<S: FileStreamMode> {
+    name: String,
+    server: bool,
+    stream: FileStream<S>,
+}
+
+/// A security descriptor allocated by Win32, which is freed when dropped.
+struct SecurityDescriptor(PSECURITY_DESCRIPTOR);
+
+impl SecurityDescriptor {
+    fn from_sddl(sddl: &str) -> Result<Self> {
+        let sddl = Win32String::from_str(sddl);
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+        unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.get_const(),
+                SDDL_REVISION_1,
+                &mut descriptor,
+                None,
+            )?
+        };
+        Ok(Self(descriptor))
+    }
+}
+
+impl Drop for SecurityDescriptor {
+    fn drop(&mut self) {
+        unsafe { LocalFree(HLOCAL(self.0 .0)) };
+    }
+}
+
+impl<S: FileStreamMode> NamedPipe<S> {
+    /// Creates another instance of the pipe, so several clients can be connected at once.
+    /// Only the first instance may claim the name.
+    fn create_instance(name: &str, first: bool) -> Result<Self> {
+        let security_desc = SecurityDescriptor::from_sddl(&build_pipe_sddl(PIPE_TRUSTEES))?;
+        let security = SECURITY_ATTRIBUTES {
+            nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+            bInheritHandle: false.into(),
+            lpSecurityDescriptor: security_desc.0 .0,
+        };
+
+        let mut access = S::as_pipe_access_rights();
+        if first {
+            access |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+        }
+
+        let pipe_name = Win32String::from_str(&format!("{}{}", PIPE_PATH_ROOT, name));
+        let pipe = unsafe {
+            CreateNamedPipeW(
+                pipe_name.get_const(),
+                access,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE,
+                PIPE_UNLIMITED_INSTANCES,
+                PIPE_BUFFER_SIZE as u32,
+                PIPE_BUFFER_SIZE as u32,
+                0,
+                Some(&security),
+            )
+        };
+        if pipe.is_invalid() {
+            let err = get_last_win32_err()?;
+            return Err(format!("Could not create named pipe!\n{}", err).into());
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            server: true,
+            stream: FileStream::create(pipe),
+        })
+    }
+}
+
+impl<S: FileStreamMode> Transport for NamedPipe<S> {
+    fn create_server(name: &str) -> Result<Self> {
+        Self::create_instance(name, true)
+    }
+
+    fn connect(&mut self) -> Result<()> {
+        match unsafe { ConnectNamedPipe(self.stream.get_raw_handle(), None) } {
+            Ok(()) => Ok(()),
+            // A client connected between creating the pipe and waiting for one.
+            Err(e) if e.code() == ERROR_PIPE_CONNECTED.to_hresult() => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn accept(&mut self) -> Result<Self> {
+        self.connect()?;
+        let next = Self::create_instance(&self.name, false)?;
+        Ok(std::mem::replace(self, next))
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        unsafe { DisconnectNamedPipe(self.stream.get_raw_handle())? };
+        Ok(())
+    }
+
+    fn create_client(name: &str) -> Result<Self> {
+        let pipe_name = Win32String::from_str(&format!("{}{}", PIPE_PATH_ROOT, name));
+        let access_rights = S::as_generic_access_rights();
+        LOGGER.debug(format!(
+            "Got following access rights for client pipe: {}",
+            access_rights
+        ));
+        let pipe = unsafe {
+            CreateFileW(
+                pipe_name.get_const(),
+                access_rights | FILE_WRITE_ATTRIBUTES.0,
+                FILE_SHARE_READ | FILE_SHARE_WRITE,
+                None,
+                OPEN_EXISTING,
+                FILE_ATTRIBUTE_NORMAL,
+                None,
+            )?
+        };
+        if pipe.is_invalid() {
+            let err = get_last_win32_err()?;
+            return Err(format!("Could not open pipe!\n{}", err).into());
+        }
+
+        unsafe { SetNamedPipeHandleState(pipe, Some(&PIPE_READMODE_BYTE), None, None)? };
+
+        Ok(Self {
+            name: name.to_owned(),
+            server: false,
+            stream: FileStream::create(pipe),
+        })
+    }
+
+    fn get_peer(&self) -> Result<PeerIdentity> {
+        let handle = self.stream.get_raw_handle();
+        let mut process_id = 0;
+        unsafe {
+            if self.server {
+                GetNamedPipeClientProcessId(handle, &mut process_id)?;
+            } else {
+                GetNamedPipeServerProcessId(handle, &mut process_id)?;
+            }
+        };
+        let mut session_id = 0;
+        let session_id = unsafe { ProcessIdToSessionId(process_id, &mut session_id) }
+            .ok()
+            .map(|_| session_id);
+        Ok(PeerIdentity {
+            process_id,
+            session_id,
+            user_id: None,
+            executable: get_process_exe_path_by_id(process_id).ok(),
+        })
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.stream.close()
+    }
+}
+
+impl<S: Readable> std::io::Read for NamedPipe<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        std::io::Read::read(&mut self.stream, buf)
+    }
+}
+
+impl<S: Writable> std::io::Write for NamedPipe<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        std::io::Write::write(&mut self.stream, buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::Write::flush(&mut self.stream)
+    }
+}
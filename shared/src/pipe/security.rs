@@ -0,0 +1,264 @@
+use super::{PeerIdentity, Result};
+use crate::util::get_process_exe_path;
+use std::path::{Path, PathBuf};
+
+pub const SERVICE_EXE_NAME: &str = "autopower";
+pub const PROXY_EXE_NAME: &str = "autopower_proxy";
+
+/// Accounts that are granted access to the pipe, see [`build_pipe_sddl`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trustee {
+    System,
+    Administrators,
+    InteractiveUsers,
+}
+
+impl Trustee {
+    fn as_sddl(&self) -> &'static str {
+        match self {
+            Self::System => "SY",
+            Self::Administrators => "BA",
+            Self::InteractiveUsers => "IU",
+        }
+    }
+}
+
+/// Only the services and the logged in users can open the pipe.
+pub const PIPE_TRUSTEES: &[Trustee] = &[Trustee::System, Trustee::InteractiveUsers];
+
+/// Builds a protected DACL in SDDL form that grants full access to the trustees and nobody else.
+pub fn build_pipe_sddl(trustees: &[Trustee]) -> String {
+    let mut sddl = String::from("D:P");
+    for trustee in trustees {
+        sddl.push_str(&format!("(A;;GA;;;{})", trustee.as_sddl()));
+    }
+    sddl
+}
+
+/// Which kind of session the process on the other end must run in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionRequirement {
+    Any,
+    /// A logged in user, which excludes services.
+    Interactive,
+    /// A privileged system process, such as the service.
+    System,
+}
+
+impl SessionRequirement {
+    #[cfg(windows)]
+    fn is_met_by(&self, peer: &PeerIdentity) -> bool {
+        // Services always run in session 0, and users never do.
+        match self {
+            Self::Any => true,
+            Self::Interactive => peer.session_id.is_some_and(|x| x != 0),
+            Self::System => peer.session_id == Some(0),
+        }
+    }
+
+    #[cfg(not(windows))]
+    fn is_met_by(&self, peer: &PeerIdentity) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Interactive => peer.session_id.is_some(),
+            Self::System => peer.user_id == Some(0),
+        }
+    }
+}
+
+/// Decides whether the process on the other end of a pipe may be talked to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerPolicy {
+    /// The peer must be one of these executables. Empty allows any executable.
+    pub allowed_executables: Vec<PathBuf>,
+    pub session: SessionRequirement,
+}
+
+impl PeerPolicy {
+    pub fn allow_any() -> Self {
+        Self {
+            allowed_executables: vec![],
+            session: SessionRequirement::Any,
+        }
+    }
+
+    /// The policy the service applies to proxies, which must be the proxy installed next to it
+    /// and run in a user's session.
+    pub fn for_proxies() -> Result<Self> {
+        Ok(Self {
+            allowed_executables: vec![get_sibling_exe_path(PROXY_EXE_NAME)?],
+            session: SessionRequirement::Interactive,
+        })
+    }
+
+    /// The policy proxies apply to the service, which must be the service installed next to
+    /// them, so no other process can pose as it.
+    pub fn for_service() -> Result<Self> {
+        Ok(Self {
+            allowed_executables: get_service_exe_paths()?,
+            session: SessionRequirement::System,
+        })
+    }
+
+    /// The policy the service applies to `autopower` commands, which must be run from the same
+    /// executable as the service.
+    pub fn for_controllers() -> Result<Self> {
+        Ok(Self {
+            allowed_executables: vec![get_process_exe_path()?],
+            session: SessionRequirement::Any,
+        })
+    }
+
+    pub fn validate(&self, peer: &PeerIdentity) -> Result<()> {
+        if !self.session.is_met_by(peer) {
+            return Err(format!(
+                "Process {} does not run in the required session ({:?})!",
+                peer.process_id, self.session
+            )
+            .into());
+        }
+
+        if self.allowed_executables.is_empty() {
+            return Ok(());
+        }
+        let executable = peer.executable.as_ref().ok_or_else(|| {
+            format!(
+                "Could not determine the executable of process {}!",
+                peer.process_id
+            )
+        })?;
+        if !self
+            .allowed_executables
+            .iter()
+            .any(|x| paths_match(x, executable))
+        {
+            return Err(format!(
+                "Process {} is not an allowed executable! ({})",
+                peer.process_id,
+                executable.display()
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+fn paths_match(a: &Path, b: &Path) -> bool {
+    a.as_os_str().eq_ignore_ascii_case(b.as_os_str())
+}
+
+#[cfg(not(windows))]
+fn paths_match(a: &Path, b: &Path) -> bool {
+    a == b
+}
+
+fn get_sibling_exe_path(name: &str) -> Result<PathBuf> {
+    Ok(get_process_exe_path()?.with_file_name(format!("{}{}", name, std::env::consts::EXE_SUFFIX)))
+}
+
+#[cfg(windows)]
+fn get_service_exe_paths() -> Result<Vec<PathBuf>> {
+    Ok(vec![get_sibling_exe_path(SERVICE_EXE_NAME)?])
+}
+
+/// Users cannot look up the executable of a root process, so being root identifies the
+/// service, see [`SessionRequirement::System`].
+#[cfg(not(windows))]
+fn get_service_exe_paths() -> Result<Vec<PathBuf>> {
+    Ok(vec![])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(session_id: Option<u32>, user_id: Option<u32>, executable: &str) -> PeerIdentity {
+        PeerIdentity {
+            process_id: 42,
+            session_id,
+            user_id,
+            executable: Some(PathBuf::from(executable)),
+        }
+    }
+
+    #[test]
+    fn sddl_grants_only_trustees() {
+        assert_eq!(
+            build_pipe_sddl(PIPE_TRUSTEES),
+            "D:P(A;;GA;;;SY)(A;;GA;;;IU)"
+        );
+        assert_eq!(build_pipe_sddl(&[]), "D:P");
+    }
+
+    #[test]
+    fn allow_any_allows_anything() {
+        let policy = PeerPolicy::allow_any();
+        assert!(policy.validate(&peer(None, None, "/tmp/evil")).is_ok());
+    }
+
+    #[test]
+    fn executables_must_be_allowed() {
+        let policy = PeerPolicy {
+            allowed_executables: vec![PathBuf::from("/opt/autopower/autopower_proxy")],
+            session: SessionRequirement::Any,
+        };
+        assert!(policy
+            .validate(&peer(None, None, "/opt/autopower/autopower_proxy"))
+            .is_ok());
+        assert!(policy
+            .validate(&peer(None, None, "/tmp/autopower_proxy"))
+            .is_err());
+
+        let mut unknown = peer(None, None, "");
+        unknown.executable = None;
+        assert!(policy.validate(&unknown).is_err());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn sessions_are_checked() {
+        let interactive = PeerPolicy {
+            allowed_executables: vec![],
+            session: SessionRequirement::Interactive,
+        };
+        assert!(interactive.validate(&peer(Some(3), Some(1000), "")).is_ok());
+        assert!(interactive.validate(&peer(None, Some(1000), "")).is_err());
+
+        let system = PeerPolicy::for_service().unwrap();
+        assert!(system.validate(&peer(None, Some(0), "")).is_ok());
+        assert!(system.validate(&peer(Some(3), Some(1000), "")).is_err());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn sessions_are_checked() {
+        let interactive = PeerPolicy {
+            allowed_executables: vec![],
+            session: SessionRequirement::Interactive,
+        };
+        assert!(interactive.validate(&peer(Some(1), None, "")).is_ok());
+        assert!(interactive.validate(&peer(Some(0), None, "")).is_err());
+
+        let system = PeerPolicy {
+            allowed_executables: vec![],
+            session: SessionRequirement::System,
+        };
+        assert!(system.validate(&peer(Some(0), None, "")).is_ok());
+        assert!(system.validate(&peer(Some(1), None, "")).is_err());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn only_the_installed_service_passes_for_the_service() {
+        let policy = PeerPolicy::for_service().unwrap();
+        let service = get_sibling_exe_path(SERVICE_EXE_NAME).unwrap();
+        let other = service.with_file_name("svchost.exe");
+        assert!(policy
+            .validate(&peer(Some(0), None, service.to_str().unwrap()))
+            .is_ok());
+        assert!(policy
+            .validate(&peer(Some(0), None, other.to_str().unwrap()))
+            .is_err());
+    }
+}
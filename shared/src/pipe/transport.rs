@@ -0,0 +1,43 @@
+use super::Result;
+use std::path::PathBuf;
+
+/// Identifies the process on the other end of a connection.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PeerIdentity {
+    pub process_id: u32,
+    /// The login session of the process, if the platform tracks one.
+    pub session_id: Option<u32>,
+    /// The user the process runs as on Unix.
+    pub user_id: Option<u32>,
+    /// Might not be known if the process belongs to a more privileged user.
+    pub executable: Option<PathBuf>,
+}
+
+/// A connection-oriented byte stream which a [`super::Pipe`] is built on.
+/// Message boundaries are added on top by the pipe, see [`super::frame`].
+///
+/// Implementors provide [`std::io::Read`] for their [`crate::stream::Readable`] modes and
+/// [`std::io::Write`] for their [`crate::stream::Writable`] modes.
+pub trait Transport: Sized {
+    /// Creates the listening end. This does not wait for a client, see [`Transport::connect`].
+    fn create_server(name: &str) -> Result<Self>;
+
+    /// Blocks until a client connects to a server created with [`Transport::create_server`].
+    fn connect(&mut self) -> Result<()>;
+
+    /// Blocks until a client connects and returns the connection to it,
+    /// while this keeps listening for further clients.
+    fn accept(&mut self) -> Result<Self>;
+
+    /// Drops the connected client of a server, so [`Transport::connect`] can wait for the next one.
+    fn disconnect(&mut self) -> Result<()>;
+
+    /// Opens a connection to an existing server.
+    fn create_client(name: &str) -> Result<Self>;
+
+    /// Looks up the process on the other end of the connection.
+    fn get_peer(&self) -> Result<PeerIdentity>;
+
+    /// Closes the connection. Closing more than once is a no-op.
+    fn close(&mut self) -> Result<()>;
+}
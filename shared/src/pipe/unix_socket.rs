@@ -0,0 +1,352 @@
+use super::{
+    transport::{PeerIdentity, Transport},
+    Result, LOGGER,
+};
+use crate::stream::{FileStreamMode, Readable, Writable};
+#[cfg(target_os = "linux")]
+use crate::util::get_process_exe_path_by_id;
+use std::{
+    ffi::CString,
+    fs::{DirBuilder, Permissions},
+    io::{ErrorKind, Read, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{DirBuilderExt, MetadataExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+/// Overrides the directory the sockets are created in, see [`DEFAULT_SOCKET_DIR`].
+pub const SOCKET_DIR_VAR: &str = "AUTOPOWER_SOCKET_DIR";
+/// Only the service account can write to it, so nobody else can replace the socket.
+pub const DEFAULT_SOCKET_DIR: &str = "/run/autopower";
+/// Members of this group may connect to the service, besides the service account itself.
+/// Without the group, only the service account can connect.
+pub const SOCKET_GROUP: &str = "autopower";
+
+pub fn get_socket_dir() -> PathBuf {
+    std::env::var_os(SOCKET_DIR_VAR)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET_DIR))
+}
+
+pub fn get_socket_path(name: &str) -> PathBuf {
+    get_socket_dir().join(format!("{}.sock", name))
+}
+
+fn get_group_id(name: &str) -> Option<libc::gid_t> {
+    let name = CString::new(name).ok()?;
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let result = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut group,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut found,
+            )
+        };
+        match result {
+            // Groups with many members need a larger buffer.
+            libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            0 if !found.is_null() => return Some(group.gr_gid),
+            _ => return None,
+        }
+    }
+}
+
+fn set_group(path: &Path, group: libc::gid_t) -> Result<()> {
+    let path_str = CString::new(path.as_os_str().as_bytes())?;
+    if unsafe { libc::chown(path_str.as_ptr(), libc::uid_t::MAX, group) } != 0 {
+        return Err(format!(
+            "Could not give {} to group {}!\n{}",
+            path.display(),
+            SOCKET_GROUP,
+            std::io::Error::last_os_error()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Creates the socket directory, or makes sure an existing one is private to this account.
+/// Directories others can access, such as the temp directory, are refused.
+fn prepare_socket_dir(dir: &Path, group: Option<libc::gid_t>) -> Result<()> {
+    match std::fs::metadata(dir) {
+        Ok(metadata) => {
+            let owner = unsafe { libc::geteuid() };
+            if metadata.uid() != owner || metadata.mode() & 0o027 != 0 {
+                return Err(format!(
+                    "{} must be a directory owned by the service account that nobody else can \
+                     write to!",
+                    dir.display()
+                )
+                .into());
+            }
+        }
+        Err(_) => DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .map_err(|e| format!("Could not create {}!\n{}", dir.display(), e))?,
+    }
+    let mode = match group {
+        Some(group) => {
+            set_group(dir, group)?;
+            0o750
+        }
+        None => 0o700,
+    };
+    std::fs::set_permissions(dir, Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn get_peer_credentials(stream: &UnixStream) -> Result<libc::ucred> {
+    use std::os::fd::AsRawFd;
+
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&mut credentials as *mut libc::ucred).cast(),
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(format!(
+            "Could not get peer credentials!\n{}",
+            std::io::Error::last_os_error()
+        )
+        .into());
+    }
+    Ok(credentials)
+}
+
+/// Whether the process belongs to the group, including its supplementary groups.
+#[cfg(target_os = "linux")]
+fn process_in_group(process_id: libc::pid_t, group: libc::gid_t) -> bool {
+    let Ok(status) = std::fs::read_to_string(format!("/proc/{}/status", process_id)) else {
+        return false;
+    };
+    status
+        .lines()
+        .find_map(|x| x.strip_prefix("Groups:"))
+        .is_some_and(|x| x.split_whitespace().any(|x| x.parse() == Ok(group)))
+}
+
+/// The socket permissions already keep others out, this makes sure of it for each client.
+#[cfg(target_os = "linux")]
+fn check_client(stream: &UnixStream, group: Option<libc::gid_t>) -> Result<()> {
+    let credentials = get_peer_credentials(stream)?;
+    let owner = unsafe { libc::geteuid() };
+    let allowed = credentials.uid == owner
+        || credentials.uid == 0
+        || group.is_some_and(|x| credentials.gid == x || process_in_group(credentials.pid, x));
+    if !allowed {
+        return Err(format!(
+            "User {} of process {} may not connect!",
+            credentials.uid, credentials.pid
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn check_client(_stream: &UnixStream, _group: Option<libc::gid_t>) -> Result<()> {
+    Ok(())
+}
+
+/// A Unix domain stream socket.
+pub struct UnixSocket<S: FileStreamMode> {
+    path: PathBuf,
+    listener: Option<UnixListener>,
+    /// The group whose members may connect to a server, see [`SOCKET_GROUP`].
+    group: Option<libc::gid_t>,
+    stream: Option<UnixStream>,
+    mode: std::marker::PhantomData<S>,
+}
+
+impl<S: FileStreamMode> UnixSocket<S> {
+    fn get_connected_stream(&mut self) -> std::io::Result<&mut UnixStream> {
+        self.stream
+            .as_mut()
+            .ok_or_else(|| std::io::Error::new(ErrorKind::NotConnected, "Socket is not connected!"))
+    }
+
+    /// Waits for the next client that is allowed to connect, refusing any others.
+    fn accept_client(&self) -> Result<UnixStream> {
+        let listener = self
+            .listener
+            .as_ref()
+            .ok_or("Only a server socket can wait for connections!")?;
+        loop {
+            let (stream, _) = listener.accept()?;
+            match check_client(&stream, self.group) {
+                Ok(()) => return Ok(stream),
+                Err(e) => LOGGER.error(format!("Refused a connection!\n{}", e)),
+            }
+        }
+    }
+}
+
+impl<S: FileStreamMode> Transport for UnixSocket<S> {
+    fn create_server(name: &str) -> Result<Self> {
+        let group = get_group_id(SOCKET_GROUP);
+        if group.is_none() {
+            LOGGER.error(format!(
+                "Group {} does not exist, so only the service account can connect!",
+                SOCKET_GROUP
+            ));
+        }
+        prepare_socket_dir(&get_socket_dir(), group)?;
+
+        let path = get_socket_path(name);
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(format!("Socket {} is already in use!", path.display()).into());
+            }
+            LOGGER.debug(format!("Removing stale socket at {}", path.display()));
+            std::fs::remove_file(&path)?;
+        }
+
+        let listener = UnixListener::bind(&path)
+            .map_err(|e| format!("Could not bind socket {}!\n{}", path.display(), e))?;
+        // The service and proxies run as different users, which share the group.
+        let mode = match group {
+            Some(group) => {
+                set_group(&path, group)?;
+                0o660
+            }
+            None => 0o600,
+        };
+        std::fs::set_permissions(&path, Permissions::from_mode(mode))?;
+
+        Ok(Self {
+            path,
+            listener: Some(listener),
+            group,
+            stream: None,
+            mode: std::marker::PhantomData,
+        })
+    }
+
+    fn connect(&mut self) -> Result<()> {
+        self.stream = Some(self.accept_client()?);
+        Ok(())
+    }
+
+    fn accept(&mut self) -> Result<Self> {
+        let stream = self.accept_client()?;
+        Ok(Self {
+            path: self.path.clone(),
+            listener: None,
+            group: self.group,
+            stream: Some(stream),
+            mode: std::marker::PhantomData,
+        })
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        if self.listener.is_none() {
+            return Err("Only a server socket can disconnect clients!".into());
+        }
+        if let Some(stream) = self.stream.take() {
+            stream.shutdown(std::net::Shutdown::Both).ok();
+        }
+        Ok(())
+    }
+
+    fn create_client(name: &str) -> Result<Self> {
+        let path = get_socket_path(name);
+        let stream = UnixStream::connect(&path)
+            .map_err(|e| format!("Could not open socket {}!\n{}", path.display(), e))?;
+        Ok(Self {
+            path,
+            listener: None,
+            group: None,
+            stream: Some(stream),
+            mode: std::marker::PhantomData,
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    fn get_peer(&self) -> Result<PeerIdentity> {
+        let stream = self.stream.as_ref().ok_or("Socket is not connected!")?;
+        let credentials = get_peer_credentials(stream)?;
+
+        let process_id = credentials.pid as u32;
+        // The audit session id, which is unset (all bits set) for processes outside of a login session.
+        let session_id = std::fs::read_to_string(format!("/proc/{}/sessionid", process_id))
+            .ok()
+            .and_then(|x| x.trim().parse::<u32>().ok())
+            .filter(|x| *x != u32::MAX);
+        Ok(PeerIdentity {
+            process_id,
+            session_id,
+            user_id: Some(credentials.uid),
+            executable: get_process_exe_path_by_id(process_id).ok(),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn get_peer(&self) -> Result<PeerIdentity> {
+        Err("Looking up socket peers is not supported on this platform.".into())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if let Some(stream) = self.stream.take() {
+            // The peer may already be gone, which is fine.
+            stream.shutdown(std::net::Shutdown::Both).ok();
+        }
+        if self.listener.take().is_some() {
+            std::fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Readable> Read for UnixSocket<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.get_connected_stream()?.read(buf)
+    }
+}
+
+impl<S: Writable> Write for UnixSocket<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.get_connected_stream()?.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.get_connected_stream()?.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_directories_are_refused() {
+        let dir = std::env::temp_dir().join(format!("AutoPowerSocketDir_{}", std::process::id()));
+        prepare_socket_dir(&dir, None).unwrap();
+        assert_eq!(std::fs::metadata(&dir).unwrap().mode() & 0o777, 0o700);
+        prepare_socket_dir(&dir, None).unwrap();
+
+        std::fs::set_permissions(&dir, Permissions::from_mode(0o1777)).unwrap();
+        assert!(prepare_socket_dir(&dir, None).is_err());
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -1,12 +1,123 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::fmt::Display;
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PowerConfigSelection {
     Wired,
     Battery,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+/// Everything the proxy needs to pick a config, including the battery tier.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PowerState {
+    pub source: PowerConfigSelection,
+    /// The remaining charge of the system battery. [`None`] if there is no battery.
+    pub battery_percent: Option<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ProxyCommand {
     ChangePowerConfig(PowerConfigSelection),
+    ChangePowerState(PowerState),
+    /// Applies a named profile, which stays active until the power source changes.
+    ApplyProfile(String),
+    /// Reads the config file again, instead of waiting for the proxy to notice it changed.
+    ReloadConfig,
+}
+
+impl ProxyCommand {
+    /// The protocol version that introduced this command.
+    pub fn get_protocol_version(&self) -> u32 {
+        match self {
+            Self::ChangePowerConfig(_) => 1,
+            Self::ChangePowerState(_) => 2,
+            Self::ApplyProfile(_) => 3,
+            Self::ReloadConfig => 4,
+        }
+    }
+
+    /// Converts the command for a peer that speaks an older protocol.
+    /// Returns [`None`] if that protocol has no equivalent command.
+    pub fn downgrade(&self, protocol_version: u32) -> Option<Self> {
+        if self.get_protocol_version() <= protocol_version {
+            return Some(self.clone());
+        }
+        match self {
+            Self::ChangePowerState(state) => Some(Self::ChangePowerConfig(state.source)),
+            _ => None,
+        }
+        .filter(|x| x.get_protocol_version() <= protocol_version)
+    }
+}
+
+/// The reply the proxy sends back for every [`ProxyCommand`] it receives.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ProxyReply {
+    Success,
+    /// The command failed. Holds the error followed by each of its sources.
+    Failure(Vec<String>),
+    /// The proxy did not understand the command.
+    Unsupported,
+}
+
+impl ProxyReply {
+    pub fn from_error(error: &(dyn std::error::Error + 'static)) -> Self {
+        let chain = std::iter::successors(Some(error), |e| e.source())
+            .map(|e| e.to_string())
+            .collect();
+        Self::Failure(chain)
+    }
+
+    pub fn from_result<T>(result: &Result<T, Box<dyn std::error::Error>>) -> Self {
+        match result {
+            Ok(_) => Self::Success,
+            Err(e) => Self::from_error(e.as_ref()),
+        }
+    }
+}
+
+impl Display for ProxyReply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Success => f.write_str("Success"),
+            Self::Failure(chain) => write!(f, "Failure\n{}", chain.join("\n")),
+            Self::Unsupported => f.write_str("Unsupported"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_state_downgrades_to_power_config() {
+        let command = ProxyCommand::ChangePowerState(PowerState {
+            source: PowerConfigSelection::Battery,
+            battery_percent: Some(42),
+        });
+        assert_eq!(command.downgrade(2), Some(command.clone()));
+        assert_eq!(
+            command.downgrade(1),
+            Some(ProxyCommand::ChangePowerConfig(
+                PowerConfigSelection::Battery
+            ))
+        );
+        assert_eq!(command.downgrade(0), None);
+    }
+
+    #[test]
+    fn apply_profile_has_no_fallback() {
+        let command = ProxyCommand::ApplyProfile("Gaming".to_owned());
+        assert_eq!(command.downgrade(3), Some(command.clone()));
+        assert_eq!(command.downgrade(2), None);
+    }
+
+    #[test]
+    fn reload_config_has_no_fallback() {
+        let command = ProxyCommand::ReloadConfig;
+        assert_eq!(command.downgrade(4), Some(command.clone()));
+        assert_eq!(command.downgrade(3), None);
+    }
 }
@@ -0,0 +1,22 @@
+use super::{FileStreamMode, Readable, Writable};
+#[cfg(windows)]
+use windows::Win32::{
+    Foundation::{GENERIC_READ, GENERIC_WRITE},
+    Storage::FileSystem::PIPE_ACCESS_DUPLEX,
+};
+
+pub struct Duplex;
+impl FileStreamMode for Duplex {
+    #[cfg(windows)]
+    fn as_generic_access_rights() -> u32 {
+        GENERIC_READ.0 | GENERIC_WRITE.0
+    }
+
+    #[cfg(windows)]
+    fn as_pipe_access_rights() -> windows::Win32::Storage::FileSystem::FILE_FLAGS_AND_ATTRIBUTES {
+        PIPE_ACCESS_DUPLEX
+    }
+}
+
+impl Readable for Duplex {}
+impl Writable for Duplex {}
@@ -1,26 +1,43 @@
+pub mod duplex;
 pub mod reader;
 pub mod writer;
 
+pub use duplex::Duplex;
 pub use reader::Read;
 pub use writer::Write;
 
+#[cfg(windows)]
 use windows::Win32::{
     Foundation::{CloseHandle, HANDLE},
     Storage::FileSystem::FILE_FLAGS_AND_ATTRIBUTES,
 };
 
-use crate::Result;
+#[cfg(windows)]
+use crate::{logging::Logger, Result};
+
+#[cfg(windows)]
+static LOGGER: Logger = Logger::new("stream", "autopower_shared");
 
 pub trait FileStreamMode {
+    #[cfg(windows)]
     fn as_generic_access_rights() -> u32;
+    #[cfg(windows)]
     fn as_pipe_access_rights() -> FILE_FLAGS_AND_ATTRIBUTES;
 }
 
+/// Modes that can be read from.
+pub trait Readable: FileStreamMode {}
+
+/// Modes that can be written to.
+pub trait Writable: FileStreamMode {}
+
+#[cfg(windows)]
 pub struct FileStream<M: FileStreamMode> {
     handle: HANDLE,
     mode: std::marker::PhantomData<M>,
 }
 
+#[cfg(windows)]
 impl<M: FileStreamMode> FileStream<M> {
     pub fn get_raw_handle(&self) -> HANDLE {
         self.handle
@@ -33,14 +50,21 @@ impl<M: FileStreamMode> FileStream<M> {
         }
     }
 
-    pub fn close(&self) -> Result<()> {
+    pub fn close(&mut self) -> Result<()> {
+        if self.handle.is_invalid() {
+            return Ok(());
+        }
         unsafe { CloseHandle(self.handle)? };
+        self.handle = HANDLE::default();
         Ok(())
     }
 }
 
+#[cfg(windows)]
 impl<M: FileStreamMode> Drop for FileStream<M> {
     fn drop(&mut self) {
-        self.close().unwrap();
+        if let Err(e) = self.close() {
+            LOGGER.error(format!("Could not close the stream!\n{}", e));
+        }
     }
 }
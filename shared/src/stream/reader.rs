@@ -1,27 +1,34 @@
+#[cfg(windows)]
+use super::FileStream;
+use super::{FileStreamMode, Readable};
+#[cfg(windows)]
 use crate::logging::Logger;
-
-use super::{FileStream, FileStreamMode};
+#[cfg(windows)]
 use windows::Win32::{
     Foundation::GENERIC_READ,
     Storage::FileSystem::{ReadFile, PIPE_ACCESS_INBOUND},
 };
 
+#[cfg(windows)]
 static LOGGER: Logger = Logger::new("stream_reader", "autopower_shared");
 
 pub struct Read;
 impl FileStreamMode for Read {
+    #[cfg(windows)]
     fn as_generic_access_rights() -> u32 {
         GENERIC_READ.0
     }
 
+    #[cfg(windows)]
     fn as_pipe_access_rights() -> windows::Win32::Storage::FileSystem::FILE_FLAGS_AND_ATTRIBUTES {
         PIPE_ACCESS_INBOUND
     }
 }
 
-impl FileStream<Read> {}
+impl Readable for Read {}
 
-impl std::io::Read for FileStream<Read> {
+#[cfg(windows)]
+impl<M: Readable> std::io::Read for FileStream<M> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         let mut bytes_read = 0;
         LOGGER.debug("Reading from file handle... (blocking)");
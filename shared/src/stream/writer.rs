@@ -1,4 +1,7 @@
-use super::{FileStreamMode, FileStream};
+#[cfg(windows)]
+use super::FileStream;
+use super::{FileStreamMode, Writable};
+#[cfg(windows)]
 use windows::Win32::{
     Foundation::GENERIC_WRITE,
     Storage::FileSystem::{WriteFile, PIPE_ACCESS_OUTBOUND},
@@ -6,18 +9,21 @@ use windows::Win32::{
 
 pub struct Write;
 impl FileStreamMode for Write {
+    #[cfg(windows)]
     fn as_generic_access_rights() -> u32 {
         GENERIC_WRITE.0
     }
 
+    #[cfg(windows)]
     fn as_pipe_access_rights() -> windows::Win32::Storage::FileSystem::FILE_FLAGS_AND_ATTRIBUTES {
         PIPE_ACCESS_OUTBOUND
     }
 }
 
-impl FileStream<Write> {}
+impl Writable for Write {}
 
-impl std::io::Write for FileStream<Write> {
+#[cfg(windows)]
+impl<M: Writable> std::io::Write for FileStream<M> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         let mut bytes_written = 0;
         unsafe { WriteFile(self.handle, Some(buf), Some(&mut bytes_written), None)? };
@@ -1,16 +1,24 @@
-use std::{ffi::OsString, os::windows::ffi::OsStringExt, path::PathBuf};
+use std::path::PathBuf;
 
+#[cfg(windows)]
+use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+#[cfg(windows)]
 use windows::{
     core::PWSTR,
     Win32::{
-        Foundation::GetLastError,
+        Foundation::{CloseHandle, GetLastError, ERROR_INSUFFICIENT_BUFFER, MAX_PATH},
         System::{
             Diagnostics::Debug::{FormatMessageW, FORMAT_MESSAGE_FROM_SYSTEM},
             LibraryLoader::GetModuleFileNameW,
+            Threading::{
+                OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+                PROCESS_QUERY_LIMITED_INFORMATION,
+            },
         },
     },
 };
 
+#[cfg(windows)]
 pub fn get_last_win32_err() -> super::Result<String> {
     let err = unsafe { GetLastError().0 };
     const BUF_SIZE: usize = 128;
@@ -30,20 +38,71 @@ pub fn get_last_win32_err() -> super::Result<String> {
     return Ok(str[..count as usize].to_owned());
 }
 
+/// Paths can be this long when long path support is enabled.
+#[cfg(windows)]
+const MAX_LONG_PATH: usize = 32768;
+
+#[cfg(windows)]
 pub fn get_process_exe_path() -> super::Result<PathBuf> {
-    let mut buf = [0; 512];
-    unsafe {
-        let count = GetModuleFileNameW(None, &mut buf);
-        let count_usize = count as usize;
-        if count_usize == buf.len() {
-            return Err("Process path is too long!".into());
-        }
-        if buf[count_usize] != 0 {
+    let mut buf = vec![0; MAX_PATH as usize];
+    loop {
+        let count = unsafe { GetModuleFileNameW(None, &mut buf) } as usize;
+        if count == 0 {
             return Err(
-                "Process path buffer did not end with a null terminator, possible overflow.".into(),
+                format!("Could not get the process path!\n{}", get_last_win32_err()?).into(),
             );
         }
-        let os_str = OsString::from_wide(&buf[0..(count_usize)]);
-        Ok(PathBuf::from(os_str))
+        // The path was cut off if it filled the whole buffer.
+        if count < buf.len() {
+            return Ok(PathBuf::from(OsString::from_wide(&buf[..count])));
+        }
+        if buf.len() >= MAX_LONG_PATH {
+            return Err("Process path is too long!".into());
+        }
+        buf.resize(buf.len() * 2, 0);
     }
 }
+
+#[cfg(not(windows))]
+pub fn get_process_exe_path() -> super::Result<PathBuf> {
+    Ok(std::env::current_exe()?)
+}
+
+#[cfg(windows)]
+pub fn get_process_exe_path_by_id(process_id: u32) -> super::Result<PathBuf> {
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id)? };
+    let mut buf = vec![0; MAX_PATH as usize];
+    let result = loop {
+        let mut count = buf.len() as u32;
+        let result = unsafe {
+            QueryFullProcessImageNameW(
+                process,
+                PROCESS_NAME_WIN32,
+                PWSTR::from_raw(buf.as_mut_ptr()),
+                &mut count,
+            )
+        };
+        match result {
+            Err(e)
+                if e.code() == ERROR_INSUFFICIENT_BUFFER.to_hresult()
+                    && buf.len() < MAX_LONG_PATH =>
+            {
+                buf.resize(buf.len() * 2, 0)
+            }
+            x => break x.map(|_| count as usize),
+        }
+    };
+    unsafe { CloseHandle(process)? };
+    let count = result?;
+    Ok(PathBuf::from(OsString::from_wide(&buf[..count])))
+}
+
+#[cfg(target_os = "linux")]
+pub fn get_process_exe_path_by_id(process_id: u32) -> super::Result<PathBuf> {
+    Ok(std::fs::read_link(format!("/proc/{}/exe", process_id))?)
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn get_process_exe_path_by_id(_process_id: u32) -> super::Result<PathBuf> {
+    Err("Looking up process executables is not supported on this platform.".into())
+}
@@ -0,0 +1,266 @@
+#![cfg(unix)]
+
+use autopower_shared::{
+    handshake::PROTOCOL_VERSION,
+    pipe::{
+        frame::MAX_MESSAGE_SIZE, get_socket_path, Client, Pipe, Server, PIPE_BUFFER_SIZE,
+        SOCKET_DIR_VAR,
+    },
+    proxy_command::{PowerConfigSelection, ProxyCommand, ProxyReply},
+    stream::{Duplex, Read, Write},
+};
+use std::{os::unix::fs::PermissionsExt, path::PathBuf, sync::Once, thread, time::Duration};
+
+fn socket_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("AutoPowerTest_{}", std::process::id()))
+}
+
+/// Also moves the sockets to a directory of their own, as only root can create the default one.
+fn unique_name(test: &str) -> String {
+    static SOCKET_DIR: Once = Once::new();
+    SOCKET_DIR.call_once(|| std::env::set_var(SOCKET_DIR_VAR, socket_dir()));
+    format!("AutoPowerTest_{}_{}", std::process::id(), test)
+}
+
+fn connect_pair(name: &str) -> (Pipe<Server, Write>, Pipe<Client, Read>) {
+    let mut server = Pipe::<Server, Write>::create_server(name).unwrap();
+    let client_name = name.to_owned();
+    let client = thread::spawn(move || Pipe::<Client, Read>::create_client(&client_name).unwrap());
+    server.connect().unwrap();
+    (server, client.join().unwrap())
+}
+
+#[test]
+fn commands_round_trip() {
+    let (mut server, mut client) = connect_pair(&unique_name("round_trip"));
+    let command = ProxyCommand::ChangePowerConfig(PowerConfigSelection::Battery);
+    server.write_as(&command).unwrap();
+    assert_eq!(client.read_to::<ProxyCommand>().unwrap(), command);
+}
+
+#[test]
+fn message_boundaries_are_kept() {
+    let (mut server, mut client) = connect_pair(&unique_name("boundaries"));
+    server
+        .write_as(ProxyCommand::ChangePowerConfig(PowerConfigSelection::Wired))
+        .unwrap();
+    server
+        .write_as(ProxyCommand::ChangePowerConfig(
+            PowerConfigSelection::Battery,
+        ))
+        .unwrap();
+
+    assert_eq!(
+        client.read_to::<ProxyCommand>().unwrap(),
+        ProxyCommand::ChangePowerConfig(PowerConfigSelection::Wired)
+    );
+    assert_eq!(
+        client.read_to::<ProxyCommand>().unwrap(),
+        ProxyCommand::ChangePowerConfig(PowerConfigSelection::Battery)
+    );
+}
+
+#[test]
+fn retrying_client_waits_for_server() {
+    let name = unique_name("retrying");
+    let client_name = name.clone();
+    let client =
+        thread::spawn(move || Pipe::<Client, Read>::create_client_retrying(&client_name).unwrap());
+    thread::sleep(Duration::from_millis(200));
+
+    let mut server = Pipe::<Server, Write>::create_server(&name).unwrap();
+    server.connect().unwrap();
+    let mut client = client.join().unwrap();
+
+    server
+        .write_as(ProxyCommand::ChangePowerConfig(PowerConfigSelection::Wired))
+        .unwrap();
+    assert_eq!(
+        client.read_to::<ProxyCommand>().unwrap(),
+        ProxyCommand::ChangePowerConfig(PowerConfigSelection::Wired)
+    );
+}
+
+#[test]
+fn name_can_only_be_served_once() {
+    let name = unique_name("served_once");
+    let _server = Pipe::<Server, Write>::create_server(&name).unwrap();
+    assert!(Pipe::<Server, Write>::create_server(&name).is_err());
+}
+
+#[test]
+fn closed_server_fails_reads() {
+    let (mut server, mut client) = connect_pair(&unique_name("closed"));
+    server.close().unwrap();
+    assert!(client.read_to::<ProxyCommand>().is_err());
+}
+
+#[test]
+fn duplex_commands_get_replies() {
+    let name = unique_name("duplex");
+    let mut server = Pipe::<Server, Duplex>::create_server(&name).unwrap();
+    let proxy = thread::spawn(move || {
+        let mut client = Pipe::<Client, Duplex>::create_client(&name).unwrap();
+        for _ in 0..2 {
+            let reply = match client.read_to::<ProxyCommand>().unwrap() {
+                ProxyCommand::ChangePowerConfig(PowerConfigSelection::Wired) => ProxyReply::Success,
+                ProxyCommand::ChangePowerConfig(PowerConfigSelection::Battery) => {
+                    let error: Box<dyn std::error::Error> =
+                        Box::new(std::io::Error::other("Could not set display mode!"));
+                    ProxyReply::from_result::<()>(&Err(error))
+                }
+                _ => ProxyReply::Unsupported,
+            };
+            client.write_as(reply).unwrap();
+        }
+    });
+    server.connect().unwrap();
+
+    server
+        .write_as(ProxyCommand::ChangePowerConfig(PowerConfigSelection::Wired))
+        .unwrap();
+    assert_eq!(server.read_to::<ProxyReply>().unwrap(), ProxyReply::Success);
+
+    server
+        .write_as(ProxyCommand::ChangePowerConfig(
+            PowerConfigSelection::Battery,
+        ))
+        .unwrap();
+    assert_eq!(
+        server.read_to::<ProxyReply>().unwrap(),
+        ProxyReply::Failure(vec!["Could not set display mode!".to_owned()])
+    );
+    proxy.join().unwrap();
+}
+
+#[test]
+fn messages_larger_than_the_buffer_arrive_whole() {
+    let (mut server, mut client) = connect_pair(&unique_name("large"));
+    let large = "x".repeat(PIPE_BUFFER_SIZE * 64);
+    let writer = thread::spawn(move || {
+        server.write_as(&large).unwrap();
+        server.write_as("small").unwrap();
+        server
+    });
+
+    assert_eq!(
+        client.read_to::<String>().unwrap().len(),
+        PIPE_BUFFER_SIZE * 64
+    );
+    assert_eq!(client.read_to::<String>().unwrap(), "small");
+    writer.join().unwrap();
+}
+
+#[test]
+fn oversized_messages_are_rejected() {
+    let (mut server, _client) = connect_pair(&unique_name("oversized"));
+    let err = server
+        .write_message(&vec![0; MAX_MESSAGE_SIZE + 1])
+        .unwrap_err();
+    assert!(err.to_string().contains("exceeds the limit"));
+}
+
+#[test]
+fn handshake_negotiates_protocol() {
+    let name = unique_name("handshake");
+    let mut server = Pipe::<Server, Duplex>::create_server(&name).unwrap();
+    let client = thread::spawn(move || {
+        let mut client = Pipe::<Client, Duplex>::create_client(&name).unwrap();
+        client.handshake().unwrap()
+    });
+    server.connect().unwrap();
+    assert_eq!(server.handshake().unwrap(), PROTOCOL_VERSION);
+    assert_eq!(client.join().unwrap(), PROTOCOL_VERSION);
+}
+
+#[test]
+fn server_can_wait_for_another_client() {
+    let name = unique_name("reconnect");
+    let mut server = Pipe::<Server, Duplex>::create_server(&name).unwrap();
+    for selection in [PowerConfigSelection::Wired, PowerConfigSelection::Battery] {
+        let client_name = name.clone();
+        let client = thread::spawn(move || {
+            let mut client = Pipe::<Client, Duplex>::create_client(&client_name).unwrap();
+            client.read_to::<ProxyCommand>().unwrap()
+        });
+        server.connect().unwrap();
+        server
+            .write_as(ProxyCommand::ChangePowerConfig(selection))
+            .unwrap();
+        assert_eq!(
+            client.join().unwrap(),
+            ProxyCommand::ChangePowerConfig(selection)
+        );
+        server.disconnect().unwrap();
+    }
+}
+
+#[test]
+fn server_accepts_many_clients() {
+    let name = unique_name("accept");
+    let mut listener = Pipe::<Server, Duplex>::create_server(&name).unwrap();
+    let clients: Vec<_> = (0..3)
+        .map(|_| {
+            let client_name = name.clone();
+            thread::spawn(move || {
+                let mut client = Pipe::<Client, Duplex>::create_client(&client_name).unwrap();
+                client.read_to::<ProxyCommand>().unwrap()
+            })
+        })
+        .collect();
+
+    let mut connections: Vec<_> = (0..3).map(|_| listener.accept().unwrap()).collect();
+    for connection in &mut connections {
+        assert_eq!(
+            connection.get_peer().unwrap().process_id,
+            std::process::id()
+        );
+        connection
+            .write_as(ProxyCommand::ChangePowerConfig(PowerConfigSelection::Wired))
+            .unwrap();
+    }
+    for client in clients {
+        assert_eq!(
+            client.join().unwrap(),
+            ProxyCommand::ChangePowerConfig(PowerConfigSelection::Wired)
+        );
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn peers_are_identified_on_both_ends() {
+    let (server, client) = connect_pair(&unique_name("peer_identity"));
+    let exe = std::env::current_exe().unwrap();
+    let uid = unsafe { libc::geteuid() };
+    for peer in [server.get_peer().unwrap(), client.get_peer().unwrap()] {
+        assert_eq!(peer.process_id, std::process::id());
+        assert_eq!(peer.user_id, Some(uid));
+        assert_eq!(peer.executable.as_deref(), Some(exe.as_path()));
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn peer_policy_rejects_unknown_executables() {
+    use autopower_shared::pipe::security::PeerPolicy;
+
+    let (server, _client) = connect_pair(&unique_name("peer_policy"));
+    let peer = server.get_peer().unwrap();
+    assert!(PeerPolicy::allow_any().validate(&peer).is_ok());
+    let policy = PeerPolicy {
+        allowed_executables: vec!["/nonexistent/autopower_proxy".into()],
+        ..PeerPolicy::allow_any()
+    };
+    assert!(policy.validate(&peer).is_err());
+}
+
+#[test]
+fn only_the_service_account_can_use_the_socket() {
+    let name = unique_name("permissions");
+    let _server = Pipe::<Server, Write>::create_server(&name).unwrap();
+    // There is no autopower group in tests, so the group gets no access either.
+    let mode = |path: PathBuf| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(socket_dir()), 0o700);
+    assert_eq!(mode(get_socket_path(&name)), 0o600);
+}
@@ -0,0 +1,171 @@
+use crate::control;
+use autopower_proxy::{
+    backend::Backends,
+    config::{edit, ConfigDirs, Effective, Plan, PowerConfig, Scope},
+    display::SavedModes,
+};
+use autopower_shared::proxy_command::{PowerConfigSelection, PowerState, ProxyCommand};
+use std::path::Path;
+
+type Result<T> = crate::Result<T>;
+
+const USAGE: &str = "Usage:
+  autopower config validate [path]
+  autopower config show [--effective]
+  autopower config schema
+  autopower config set [--user] <key>=<value>
+  autopower config reset [--user] [--backup]";
+
+const APPLY_USAGE: &str = "Usage:
+  autopower apply <profile>";
+
+const PLAN_USAGE: &str = "Usage:
+  autopower plan <wired|battery> [--percent=<percent>] [--json]
+  autopower plan profile <name> [--json]";
+
+/// Handles `autopower config ...`.
+pub fn config(args: &[String]) -> Result<()> {
+    let (flags, args): (Vec<_>, Vec<_>) = args
+        .iter()
+        .map(String::as_str)
+        .partition(|x| x.starts_with("--"));
+    let allowed: &[&str] = match args.first() {
+        Some(&"show") => &["--effective"],
+        Some(&"set") => &["--user"],
+        Some(&"reset") => &["--user", "--backup"],
+        _ => &[],
+    };
+    if let Some(flag) = flags.iter().find(|x| !allowed.contains(x)) {
+        return Err(format!("Unknown option {}!\n{}", flag, USAGE).into());
+    }
+    let has_flag = |flag| flags.contains(&flag);
+    let scope = match has_flag("--user") {
+        true => Scope::User,
+        false => Scope::Machine,
+    };
+
+    match args.as_slice() {
+        ["validate"] => {
+            Effective::read(&ConfigDirs::platform()?)?;
+            println!("The config is valid.");
+        }
+        ["validate", path] => {
+            edit::validate_file(Path::new(path))?;
+            println!("{} is valid.", path);
+        }
+        ["show"] if has_flag("--effective") => show_effective()?,
+        ["show"] => {
+            let effective = Effective::read(&ConfigDirs::platform()?)?;
+            println!("{}", serde_json::to_string_pretty(&effective.config)?);
+        }
+        ["schema"] => println!("{}", PowerConfig::schema()),
+        ["set", assignment] => {
+            let path = edit::set(&ConfigDirs::platform()?, scope, assignment)?;
+            println!("Updated {}", path.display());
+            reload_config();
+        }
+        ["reset"] => {
+            if let Some(backup) =
+                edit::reset(&ConfigDirs::platform()?, scope, has_flag("--backup"))?
+            {
+                println!("The old config is at {}", backup.display());
+            }
+            println!("Reset the config.");
+            reload_config();
+        }
+        _ => println!("{}", USAGE),
+    }
+    Ok(())
+}
+
+/// Has the proxies read the config now. They also notice the change on their own, so failing
+/// to reach the service is not an error.
+fn reload_config() {
+    if let Err(e) = control::send(&ProxyCommand::ReloadConfig) {
+        eprintln!(
+            "Could not ask the service to reload the config, the proxies pick it up on their own.\n{}",
+            e
+        );
+    }
+}
+
+/// Prints every value of the merged config, and the file it was set in.
+fn show_effective() -> Result<()> {
+    let effective = Effective::read(&ConfigDirs::platform()?)?;
+    let lines: Vec<_> = effective
+        .entries()
+        .into_iter()
+        .map(|(key, value, source)| (format!("{} = {}", key, value), source))
+        .collect();
+    let width = lines.iter().map(|(x, _)| x.len()).max().unwrap_or_default();
+    for (line, source) in lines {
+        println!("{:width$}  # {}", line, source);
+    }
+    Ok(())
+}
+
+/// Handles `autopower apply <profile>`, which switches every session to the profile until the
+/// power source changes.
+pub fn apply(args: &[String]) -> Result<()> {
+    let [name] = args else {
+        return Err(APPLY_USAGE.into());
+    };
+    let config = Effective::read(&ConfigDirs::platform()?)?.config;
+    config.get_profile(name)?;
+    control::send(&ProxyCommand::ApplyProfile(name.clone()))?;
+    println!("Applying {}.", name);
+    Ok(())
+}
+
+/// Handles `autopower plan ...`, which shows what switching would change without changing it.
+pub fn plan(args: &[String]) -> Result<()> {
+    let (flags, args): (Vec<_>, Vec<_>) = args
+        .iter()
+        .map(String::as_str)
+        .partition(|x| x.starts_with("--"));
+    let mut json = false;
+    let mut percent = None;
+    for flag in flags {
+        match flag.split_once('=') {
+            None if flag == "--json" => json = true,
+            Some(("--percent", value)) => {
+                let value = value
+                    .parse()
+                    .ok()
+                    .filter(|x| *x <= 100)
+                    .ok_or_else(|| format!("{} is not a battery percentage!", value))?;
+                percent = Some(value);
+            }
+            _ => return Err(format!("Unknown option {}!\n{}", flag, PLAN_USAGE).into()),
+        }
+    }
+
+    let dirs = ConfigDirs::platform()?;
+    let config = Effective::read(&dirs)?.config;
+    let backends = Backends::platform();
+    let saved = SavedModes::open(dirs.state()?);
+    let source = match args.as_slice() {
+        ["wired"] => PowerConfigSelection::Wired,
+        ["battery"] => PowerConfigSelection::Battery,
+        ["profile", name] => {
+            let plan = config.plan_profile(name, &backends, &saved)?;
+            return print_plan(&plan, json);
+        }
+        _ => return Err(PLAN_USAGE.into()),
+    };
+    // Without a percentage, battery tiers are skipped like they are for services that do not
+    // report one, so the plan does not depend on the charge of the machine it runs on.
+    let state = PowerState {
+        source,
+        battery_percent: percent,
+    };
+    print_plan(&config.plan_for_state(state, &backends, &saved)?, json)
+}
+
+fn print_plan(plan: &Plan, json: bool) -> Result<()> {
+    match json {
+        true => println!("{}", serde_json::to_string_pretty(plan)?),
+        false => print!("{}", plan),
+    }
+    Ok(())
+}
@@ -0,0 +1,191 @@
+use crate::proxy::ProxyHandle;
+use autopower_shared::{
+    logging::Logger,
+    pipe::{security::PeerPolicy, Client, Pipe, Server, CONTROL_PIPE_NAME},
+    proxy_command::{ProxyCommand, ProxyReply},
+    stream::Duplex,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+type Result<T> = crate::Result<T>;
+
+static LOGGER: Logger = Logger::new("control", "autopower");
+
+/// How long to wait before accepting again after a failed accept.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Takes commands from `autopower` itself and passes them on to every proxy. Each connection is
+/// served on a thread of its own, so a client that never sends anything holds up no one else.
+/// Power states only come from the power source, so those are refused.
+pub struct Control {
+    name: String,
+    stopping: Arc<AtomicBool>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl Control {
+    pub fn start(name: &str, policy: PeerPolicy, proxies: ProxyHandle) -> Result<Self> {
+        let listener = Pipe::create_server(name)?;
+        let stopping = Arc::new(AtomicBool::new(false));
+        let acceptor_stopping = stopping.clone();
+        let acceptor = std::thread::spawn(move || {
+            Self::run_acceptor(listener, Arc::new(policy), proxies, &acceptor_stopping)
+        });
+        Ok(Self {
+            name: name.to_owned(),
+            stopping,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    fn run_acceptor(
+        mut listener: Pipe<Server, Duplex>,
+        policy: Arc<PeerPolicy>,
+        proxies: ProxyHandle,
+        stopping: &AtomicBool,
+    ) {
+        while !stopping.load(Ordering::SeqCst) {
+            let pipe = match listener.accept() {
+                Ok(x) => x,
+                Err(e) => {
+                    LOGGER.error(format!("Could not wait for a command!\n{}", e));
+                    std::thread::sleep(ACCEPT_RETRY_DELAY);
+                    continue;
+                }
+            };
+            if stopping.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let policy = policy.clone();
+            let proxies = proxies.clone();
+            // Not joined when stopping, as a stalled client would hold that up too.
+            std::thread::spawn(move || {
+                if let Err(e) = Self::serve(pipe, &policy, &proxies) {
+                    LOGGER.error(format!("Could not take a command!\n{}", e));
+                }
+            });
+        }
+        LOGGER.debug("Stopped taking commands.");
+    }
+
+    fn serve(
+        mut pipe: Pipe<Server, Duplex>,
+        policy: &PeerPolicy,
+        proxies: &ProxyHandle,
+    ) -> Result<()> {
+        let peer = pipe.get_peer()?;
+        policy
+            .validate(&peer)
+            .map_err(|e| format!("Refusing commands from {:?}!\n{}", peer, e))?;
+        let protocol_version = pipe.handshake()?;
+        let command: ProxyCommand = pipe.read_to()?;
+        LOGGER.debug(format!("Got command from {:?}:\n{:?}", peer, command));
+        let reply = match command {
+            _ if command.get_protocol_version() > protocol_version => ProxyReply::Unsupported,
+            ProxyCommand::ApplyProfile(_) => {
+                proxies.send(command);
+                ProxyReply::Success
+            }
+            ProxyCommand::ReloadConfig => {
+                proxies.reload_config();
+                ProxyReply::Success
+            }
+            _ => ProxyReply::Unsupported,
+        };
+        pipe.write_as(reply)
+    }
+
+    pub fn terminate(&mut self) -> Result<()> {
+        self.stopping.store(true, Ordering::SeqCst);
+        // The acceptor is blocked waiting for a connection, which only a client can end.
+        Pipe::<Client, Duplex>::create_client(&self.name).ok();
+        if let Some(acceptor) = self.acceptor.take() {
+            acceptor.join().map_err(|_| "Control thread panicked!")?;
+        }
+        Ok(())
+    }
+}
+
+/// Asks the running service to pass the command on to every proxy.
+pub fn send(command: &ProxyCommand) -> Result<()> {
+    send_to(CONTROL_PIPE_NAME, &PeerPolicy::for_service()?, command)
+}
+
+fn send_to(name: &str, service: &PeerPolicy, command: &ProxyCommand) -> Result<()> {
+    let mut pipe = Pipe::<Client, Duplex>::create_client(name)
+        .map_err(|e| format!("Could not reach the service, is it running?\n{}", e))?;
+    let peer = pipe.get_peer()?;
+    service
+        .validate(&peer)
+        .map_err(|e| format!("Refusing to talk to {:?}!\n{}", peer, e))?;
+    pipe.handshake()?;
+    pipe.write_as(command)?;
+    match pipe.read_to()? {
+        ProxyReply::Success => Ok(()),
+        reply => Err(format!("The service did not take {:?}!\n{}", command, reply).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        power_source::FakePowerSource,
+        proxy::{unique_test_name as unique_name, Proxy},
+    };
+    use autopower_shared::proxy_command::{PowerConfigSelection, PowerState};
+
+    #[test]
+    fn profiles_are_applied_through_the_service() {
+        let name = unique_name("control_proxy");
+        let control_name = unique_name("control");
+        let source = FakePowerSource::new(PowerConfigSelection::Battery, Some(80));
+        let mut proxy = Proxy::start(&name, PeerPolicy::allow_any(), Box::new(source)).unwrap();
+        let mut control =
+            Control::start(&control_name, PeerPolicy::allow_any(), proxy.handle()).unwrap();
+
+        let mut fake = Pipe::<Client, Duplex>::create_client_retrying(&name).unwrap();
+        fake.handshake().unwrap();
+        assert_eq!(
+            fake.read_to::<ProxyCommand>().unwrap(),
+            ProxyCommand::ChangePowerState(PowerState {
+                source: PowerConfigSelection::Battery,
+                battery_percent: Some(80),
+            })
+        );
+        fake.write_as(ProxyReply::Success).unwrap();
+
+        // A client that connects and then stalls keeps no one else waiting.
+        let _stalled = Pipe::<Client, Duplex>::create_client_retrying(&control_name).unwrap();
+        let apply = ProxyCommand::ApplyProfile("Gaming".to_owned());
+        send_to(&control_name, &PeerPolicy::allow_any(), &apply).unwrap();
+        assert_eq!(fake.read_to::<ProxyCommand>().unwrap(), apply);
+        fake.write_as(ProxyReply::Success).unwrap();
+
+        send_to(
+            &control_name,
+            &PeerPolicy::allow_any(),
+            &ProxyCommand::ReloadConfig,
+        )
+        .unwrap();
+        assert_eq!(
+            fake.read_to::<ProxyCommand>().unwrap(),
+            ProxyCommand::ReloadConfig
+        );
+        fake.write_as(ProxyReply::Success).unwrap();
+
+        let power = ProxyCommand::ChangePowerConfig(PowerConfigSelection::Wired);
+        assert!(send_to(&control_name, &PeerPolicy::allow_any(), &power).is_err());
+
+        control.terminate().unwrap();
+        proxy.terminate().unwrap();
+    }
+}
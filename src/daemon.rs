@@ -0,0 +1,48 @@
+use crate::{
+    control::Control,
+    power_supply::{PowerSupplyMonitor, SysfsPowerSource},
+    proxy::Proxy,
+};
+use autopower_shared::{
+    logging::Logger,
+    pipe::{security::PeerPolicy, CONTROL_PIPE_NAME, PIPE_NAME},
+    proxy_command::ProxyCommand,
+};
+use std::time::Duration;
+
+type Result<T> = crate::Result<T>;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+static LOGGER: Logger = Logger::new("daemon", "autopower");
+
+pub fn run() -> Result<()> {
+    LOGGER.debug("Setting up proxy...");
+    let mut proxy = PeerPolicy::for_proxies()
+        .and_then(|policy| Proxy::start(PIPE_NAME, policy, Box::new(SysfsPowerSource::from_env())))
+        .map_err(|e| format!("Could not create proxy!\n{}", e))?;
+
+    LOGGER.debug("Setting up control...");
+    let mut control = PeerPolicy::for_controllers()
+        .and_then(|policy| Control::start(CONTROL_PIPE_NAME, policy, proxy.handle()))
+        .map_err(|e| format!("Could not create control!\n{}", e))?;
+
+    LOGGER.debug("Applying config for the current power source...");
+    if let Err(e) = proxy.refresh() {
+        LOGGER.error(format!(
+            "Could not apply config for the current power source!\n{}",
+            e
+        ));
+    }
+
+    LOGGER.debug("Starting power supply monitor...");
+    let mut monitor = PowerSupplyMonitor::new(SysfsPowerSource::from_env(), POLL_INTERVAL);
+    let result = monitor.run(|state| {
+        LOGGER.debug(format!("On {:?} power event.", state));
+        proxy.send(ProxyCommand::ChangePowerState(state));
+    });
+
+    control.terminate().ok();
+    proxy.terminate().ok();
+    result
+}
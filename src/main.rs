@@ -1,7 +1,16 @@
 use autopower_shared::logging::Logger;
 
+mod cli;
+mod control;
+#[cfg(target_os = "linux")]
+mod daemon;
+#[cfg(windows)]
 mod debug_utils;
+mod power_source;
+#[cfg(target_os = "linux")]
+mod power_supply;
 mod proxy;
+#[cfg(windows)]
 mod services;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
@@ -10,14 +19,47 @@ static LOGGER: Logger = Logger::new("main", "autopower");
 
 fn main() -> Result<()> {
     LOGGER.debug("Starting... (main)");
-    let mut args = std::env::args();
-    if let Some(cmd) = args.nth(1) {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    if let Some(cmd) = args.first() {
         match cmd.as_str() {
             "version" => println!(env!("CARGO_PKG_VERSION")),
+            "config" => {
+                if let Err(e) = cli::config(&args[1..]) {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            "apply" => {
+                if let Err(e) = cli::apply(&args[1..]) {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            "plan" => {
+                if let Err(e) = cli::plan(&args[1..]) {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
             _ => println!("Unknown command."),
         }
         return Ok(());
     }
 
+    start_service()
+}
+
+#[cfg(windows)]
+fn start_service() -> Result<()> {
     services::start::<services::PowerService>()
 }
+
+#[cfg(target_os = "linux")]
+fn start_service() -> Result<()> {
+    daemon::run()
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn start_service() -> Result<()> {
+    Err("The service is not supported on this platform.".into())
+}
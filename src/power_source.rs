@@ -0,0 +1,76 @@
+#[cfg(any(windows, test))]
+use autopower_shared::proxy_command::PowerConfigSelection;
+use autopower_shared::proxy_command::PowerState;
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+#[cfg(windows)]
+use windows::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+type Result<T> = crate::Result<T>;
+
+/// Tells which power source the machine is currently running on, and how charged the battery is.
+pub trait PowerSource: Send + Sync {
+    fn get_current(&self) -> Result<PowerState>;
+}
+
+/// Queries the power source from Win32.
+#[cfg(windows)]
+pub struct SystemPowerSource;
+
+#[cfg(windows)]
+impl PowerSource for SystemPowerSource {
+    fn get_current(&self) -> Result<PowerState> {
+        const NO_SYSTEM_BATTERY: u8 = 128;
+        const UNKNOWN_PERCENT: u8 = 255;
+
+        let mut status = SYSTEM_POWER_STATUS::default();
+        unsafe { GetSystemPowerStatus(&mut status) }
+            .map_err(|e| format!("Could not get system power status!\n{}", e))?;
+        let source = match status.ACLineStatus {
+            0 => PowerConfigSelection::Battery,
+            1 => PowerConfigSelection::Wired,
+            x => return Err(format!("Unknown AC line status: {}", x).into()),
+        };
+        let battery_percent = match (status.BatteryFlag, status.BatteryLifePercent) {
+            (NO_SYSTEM_BATTERY, _) | (_, UNKNOWN_PERCENT) => None,
+            (_, x) => Some(x),
+        };
+        Ok(PowerState {
+            source,
+            battery_percent,
+        })
+    }
+}
+
+/// A power source that tests can switch at will. Clones share the same state.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct FakePowerSource(Arc<Mutex<Option<PowerState>>>);
+
+#[cfg(test)]
+impl FakePowerSource {
+    pub fn new(source: PowerConfigSelection, battery_percent: Option<u8>) -> Self {
+        let fake = Self::default();
+        fake.set(source, battery_percent);
+        fake
+    }
+
+    pub fn set(&self, source: PowerConfigSelection, battery_percent: Option<u8>) {
+        *self.0.lock().unwrap() = Some(PowerState {
+            source,
+            battery_percent,
+        });
+    }
+
+    /// Makes the source fail, like an unknown AC line status would.
+    pub fn set_unknown(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+}
+
+#[cfg(test)]
+impl PowerSource for FakePowerSource {
+    fn get_current(&self) -> Result<PowerState> {
+        Ok((*self.0.lock().unwrap()).ok_or("The power source is unknown.")?)
+    }
+}
@@ -0,0 +1,246 @@
+use crate::power_source::PowerSource;
+use autopower_shared::{
+    logging::Logger,
+    proxy_command::{PowerConfigSelection, PowerState},
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+type Result<T> = crate::Result<T>;
+
+pub const DEFAULT_POWER_SUPPLY_ROOT: &str = "/sys/class/power_supply";
+pub const POWER_SUPPLY_ROOT_VAR: &str = "AUTOPOWER_POWER_SUPPLY_ROOT";
+
+static LOGGER: Logger = Logger::new("power_supply", "autopower");
+
+/// Reads the current power source from a sysfs `power_supply` class directory.
+pub struct SysfsPowerSource {
+    root: PathBuf,
+}
+
+impl SysfsPowerSource {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Uses the root from [`POWER_SUPPLY_ROOT_VAR`] if set, otherwise [`DEFAULT_POWER_SUPPLY_ROOT`].
+    pub fn from_env() -> Self {
+        match std::env::var_os(POWER_SUPPLY_ROOT_VAR) {
+            Some(root) => Self::new(root),
+            None => Self::new(DEFAULT_POWER_SUPPLY_ROOT),
+        }
+    }
+
+    fn read_attribute(supply: &Path, name: &str) -> Option<String> {
+        fs::read_to_string(supply.join(name))
+            .ok()
+            .map(|x| x.trim().to_owned())
+    }
+}
+
+impl PowerSource for SysfsPowerSource {
+    /// Wired if any external supply is online. Battery if none are, but a system battery exists.
+    /// A machine without any system battery can only be running on wired power.
+    /// The battery percentage is the average of all system batteries that report their capacity.
+    fn get_current(&self) -> Result<PowerState> {
+        let mut has_battery = false;
+        let mut online = false;
+        let mut capacities = vec![];
+        for entry in fs::read_dir(&self.root)
+            .map_err(|e| format!("Could not read {}!\n{}", self.root.display(), e))?
+        {
+            let supply = entry?.path();
+            let Some(supply_type) = Self::read_attribute(&supply, "type") else {
+                continue;
+            };
+
+            if supply_type == "Battery" {
+                // Batteries of peripherals like mice report a "Device" scope.
+                if Self::read_attribute(&supply, "scope").as_deref() != Some("Device") {
+                    has_battery = true;
+                    capacities.extend(
+                        Self::read_attribute(&supply, "capacity")
+                            .and_then(|x| x.parse::<u32>().ok())
+                            .map(|x| x.min(100)),
+                    );
+                }
+                continue;
+            }
+
+            if !online && Self::read_attribute(&supply, "online").as_deref() == Some("1") {
+                LOGGER.debug(format!("Power supply {} is online.", supply.display()));
+                online = true;
+            }
+        }
+
+        let source = if online || !has_battery {
+            PowerConfigSelection::Wired
+        } else {
+            PowerConfigSelection::Battery
+        };
+        let battery_percent = (!capacities.is_empty())
+            .then(|| (capacities.iter().sum::<u32>() / capacities.len() as u32) as u8);
+        Ok(PowerState {
+            source,
+            battery_percent,
+        })
+    }
+}
+
+/// Polls a [`PowerSource`] for changes, as sysfs attributes do not support file notifications.
+pub struct PowerSupplyMonitor<S: PowerSource> {
+    source: S,
+    poll_interval: Duration,
+    last: Option<PowerState>,
+}
+
+impl<S: PowerSource> PowerSupplyMonitor<S> {
+    pub fn new(source: S, poll_interval: Duration) -> Self {
+        Self {
+            source,
+            poll_interval,
+            last: None,
+        }
+    }
+
+    /// Returns the current power state if it differs from the last poll.
+    /// The first poll always reports, just like the Win32 power setting notification does.
+    pub fn poll(&mut self) -> Result<Option<PowerState>> {
+        let current = self.source.get_current()?;
+        if self.last == Some(current) {
+            return Ok(None);
+        }
+        LOGGER.debug(format!("Power state changed to {:?}", current));
+        self.last = Some(current);
+        Ok(Some(current))
+    }
+
+    /// Polls until the process ends. A failed read is logged and retried on the next poll, as
+    /// sysfs attributes can briefly fail to read while a supply is being added or removed.
+    pub fn run(&mut self, mut on_change: impl FnMut(PowerState)) -> Result<()> {
+        loop {
+            match self.poll() {
+                Ok(Some(state)) => on_change(state),
+                Ok(None) => (),
+                Err(e) => LOGGER.error(format!("Could not read the power state!\n{}", e)),
+            }
+            std::thread::sleep(self.poll_interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_supply(root: &Path, name: &str, attributes: &[(&str, &str)]) {
+        let supply = root.join(name);
+        fs::create_dir_all(&supply).unwrap();
+        for (attribute, value) in attributes {
+            fs::write(supply.join(attribute), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    fn laptop() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        add_supply(root.path(), "AC", &[("type", "Mains"), ("online", "1")]);
+        add_supply(
+            root.path(),
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("status", "Charging"),
+                ("capacity", "80"),
+            ],
+        );
+        root
+    }
+
+    fn state(source: PowerConfigSelection, battery_percent: Option<u8>) -> PowerState {
+        PowerState {
+            source,
+            battery_percent,
+        }
+    }
+
+    fn monitor(root: &Path) -> PowerSupplyMonitor<SysfsPowerSource> {
+        PowerSupplyMonitor::new(SysfsPowerSource::new(root), Duration::ZERO)
+    }
+
+    #[test]
+    fn reports_plug_and_unplug() {
+        let root = laptop();
+        let mut monitor = monitor(root.path());
+        let wired = state(PowerConfigSelection::Wired, Some(80));
+        assert_eq!(monitor.poll().unwrap(), Some(wired));
+        assert_eq!(monitor.poll().unwrap(), None);
+
+        fs::write(root.path().join("AC/online"), "0\n").unwrap();
+        let battery = state(PowerConfigSelection::Battery, Some(80));
+        assert_eq!(monitor.poll().unwrap(), Some(battery));
+        assert_eq!(monitor.poll().unwrap(), None);
+
+        fs::write(root.path().join("AC/online"), "1\n").unwrap();
+        assert_eq!(monitor.poll().unwrap(), Some(wired));
+    }
+
+    #[test]
+    fn reports_battery_percentage_changes() {
+        let root = laptop();
+        fs::write(root.path().join("AC/online"), "0\n").unwrap();
+        add_supply(
+            root.path(),
+            "BAT1",
+            &[("type", "Battery"), ("capacity", "40")],
+        );
+        let mut monitor = monitor(root.path());
+        let battery = |x| state(PowerConfigSelection::Battery, Some(x));
+        assert_eq!(monitor.poll().unwrap(), Some(battery(60)));
+
+        fs::write(root.path().join("BAT0/capacity"), "78\n").unwrap();
+        assert_eq!(monitor.poll().unwrap(), Some(battery(59)));
+        assert_eq!(monitor.poll().unwrap(), None);
+    }
+
+    #[test]
+    fn any_online_supply_is_wired() {
+        let root = laptop();
+        fs::write(root.path().join("AC/online"), "0\n").unwrap();
+        add_supply(
+            root.path(),
+            "ucsi-source-psy-1",
+            &[("type", "USB"), ("online", "1")],
+        );
+        let source = SysfsPowerSource::new(root.path());
+        assert_eq!(
+            source.get_current().unwrap().source,
+            PowerConfigSelection::Wired
+        );
+    }
+
+    #[test]
+    fn device_batteries_are_ignored() {
+        let root = tempfile::tempdir().unwrap();
+        add_supply(root.path(), "AC", &[("type", "Mains"), ("online", "0")]);
+        add_supply(
+            root.path(),
+            "hidpp_battery_0",
+            &[("type", "Battery"), ("scope", "Device"), ("capacity", "5")],
+        );
+        let source = SysfsPowerSource::new(root.path());
+        assert_eq!(
+            source.get_current().unwrap(),
+            state(PowerConfigSelection::Wired, None)
+        );
+    }
+
+    #[test]
+    fn missing_root_is_an_error() {
+        let root = tempfile::tempdir().unwrap();
+        let source = SysfsPowerSource::new(root.path().join("missing"));
+        assert!(source.get_current().is_err());
+    }
+}
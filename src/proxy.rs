@@ -1,34 +1,533 @@
+use crate::power_source::PowerSource;
 use autopower_shared::{
     logging::Logger,
-    pipe::{Pipe, Server, PIPE_NAME},
-    proxy_command::ProxyCommand,
-    stream::Write,
+    pipe::{security::PeerPolicy, Client, PeerIdentity, Pipe, Server},
+    proxy_command::{ProxyCommand, ProxyReply},
+    stream::Duplex,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Condvar, Mutex},
+    thread::JoinHandle,
+    time::Duration,
 };
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+const COMMAND_ATTEMPTS: u32 = 3;
+const COMMAND_RETRY_DELAY: Duration = Duration::from_millis(500);
+
 static LOGGER: Logger = Logger::new("proxy", "autopower");
 
+struct Connection {
+    peer: PeerIdentity,
+    /// The latest command that has not been delivered to this connection yet.
+    pending: Option<ProxyCommand>,
+    /// Whether the proxy still has to read the config again. Kept apart from `pending`, so it
+    /// does not replace a power state the proxy has not received yet.
+    reload: bool,
+}
+
+#[derive(Default)]
+struct ProxyState {
+    /// The latest command sent, which every newly connected proxy receives first.
+    latest: Option<ProxyCommand>,
+    connections: HashMap<u64, Connection>,
+    next_id: u64,
+    stopping: bool,
+}
+
+struct Shared {
+    /// Which processes may connect as a proxy.
+    policy: PeerPolicy,
+    source: Box<dyn PowerSource>,
+    state: Mutex<ProxyState>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn is_stopping(&self) -> bool {
+        self.state.lock().unwrap().stopping
+    }
+
+    /// The command matching the current power source, which might have changed while no proxy
+    /// was connected to receive it.
+    fn query_source(&self) -> Option<ProxyCommand> {
+        match self.source.get_current() {
+            Ok(x) => Some(ProxyCommand::ChangePowerState(x)),
+            Err(e) => {
+                LOGGER.error(format!("Could not query the power source!\n{}", e));
+                None
+            }
+        }
+    }
+
+    fn register(&self, peer: PeerIdentity) -> u64 {
+        let current = self.query_source();
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        let pending = current.or_else(|| state.latest.clone());
+        state.connections.insert(
+            id,
+            Connection {
+                peer,
+                pending,
+                reload: false,
+            },
+        );
+        id
+    }
+
+    fn send(&self, command: ProxyCommand) {
+        let mut state = self.state.lock().unwrap();
+        for connection in state.connections.values_mut() {
+            LOGGER.debug(format!(
+                "Queueing command for proxy {:?}:\n{:?}",
+                connection.peer, command
+            ));
+            connection.pending = Some(command.clone());
+        }
+        state.latest = Some(command);
+        self.changed.notify_all();
+    }
+
+    /// A newly connected proxy reads the config anyway, so this is not kept for later ones.
+    fn reload_config(&self) {
+        let mut state = self.state.lock().unwrap();
+        for connection in state.connections.values_mut() {
+            LOGGER.debug(format!(
+                "Queueing config reload for proxy {:?}",
+                connection.peer
+            ));
+            connection.reload = true;
+        }
+        self.changed.notify_all();
+    }
+
+    fn unregister(&self, id: u64) {
+        self.state.lock().unwrap().connections.remove(&id);
+    }
+
+    /// Blocks until there is a command to deliver to the connection. Returns [`None`] when stopping.
+    fn wait_for_command(&self, id: u64) -> Option<ProxyCommand> {
+        let mut state = self
+            .changed
+            .wait_while(self.state.lock().unwrap(), |x| {
+                !x.stopping
+                    && x.connections
+                        .get(&id)
+                        .is_some_and(|x| x.pending.is_none() && !x.reload)
+            })
+            .unwrap();
+        if state.stopping {
+            return None;
+        }
+        let connection = state.connections.get_mut(&id)?;
+        // Reloading first makes the pending command use the new config.
+        if std::mem::take(&mut connection.reload) {
+            return Some(ProxyCommand::ReloadConfig);
+        }
+        connection.pending.take()
+    }
+}
+
+/// Sends commands through a [`Proxy`] from another thread.
+#[derive(Clone)]
+pub struct ProxyHandle(Arc<Shared>);
+
+impl ProxyHandle {
+    /// See [`Proxy::send`].
+    pub fn send(&self, command: ProxyCommand) {
+        self.0.send(command);
+    }
+
+    /// Has every connected proxy read the config again, without touching the commands they have
+    /// not received yet.
+    pub fn reload_config(&self) {
+        self.0.reload_config();
+    }
+}
+
+/// Delivers commands to every connected proxy process, one per logged in session.
+/// Proxies can come and go at any time, and each one receives the latest command when it connects.
 pub struct Proxy {
-    pipe: Pipe<Server, Write>,
+    name: String,
+    shared: Arc<Shared>,
+    acceptor: Option<JoinHandle<()>>,
 }
 
 impl Proxy {
-    pub fn create() -> Result<Self> {
+    /// Starts listening for proxy connections in the background.
+    /// Connections from processes that do not satisfy the policy are refused.
+    /// Each proxy is sent the config for the current power source as soon as it connects.
+    pub fn start(name: &str, policy: PeerPolicy, source: Box<dyn PowerSource>) -> Result<Self> {
         LOGGER.debug("Creating pipe...");
-        let pipe = Pipe::create_server(PIPE_NAME)?;
-        LOGGER.debug("Created pipe, waiting for connection...");
-        pipe.connect()?;
-        Ok(Proxy { pipe })
+        let listener = Pipe::create_server(name)?;
+        let shared = Arc::new(Shared {
+            policy,
+            source,
+            state: Mutex::default(),
+            changed: Condvar::new(),
+        });
+        let acceptor_shared = shared.clone();
+        let acceptor = std::thread::spawn(move || Self::run_acceptor(listener, acceptor_shared));
+        Ok(Self {
+            name: name.to_owned(),
+            shared,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    /// Broadcasts the command to all connected proxies.
+    /// Replaces any older command that a proxy has not received yet.
+    pub fn send(&self, command: ProxyCommand) {
+        self.shared.send(command);
+    }
+
+    /// Lets other threads send commands, such as the ones `autopower` commands ask for.
+    pub fn handle(&self) -> ProxyHandle {
+        ProxyHandle(self.shared.clone())
+    }
+
+    /// Broadcasts the config for the current power source, without waiting for a power event.
+    pub fn refresh(&self) -> Result<()> {
+        let state = self
+            .shared
+            .source
+            .get_current()
+            .map_err(|e| format!("Could not query the power source!\n{}", e))?;
+        self.send(ProxyCommand::ChangePowerState(state));
+        Ok(())
+    }
+
+    fn run_acceptor(mut listener: Pipe<Server, Duplex>, shared: Arc<Shared>) {
+        let mut connections: Vec<JoinHandle<()>> = vec![];
+        while !shared.is_stopping() {
+            LOGGER.debug("Waiting for proxy connection...");
+            let pipe = match listener.accept() {
+                Ok(x) => x,
+                Err(e) => {
+                    LOGGER.error(format!("Could not wait for proxy connection!\n{}", e));
+                    std::thread::sleep(COMMAND_RETRY_DELAY);
+                    continue;
+                }
+            };
+            if shared.is_stopping() {
+                break;
+            }
+
+            let connection_shared = shared.clone();
+            connections.push(std::thread::spawn(move || {
+                Self::run_connection(pipe, &connection_shared)
+            }));
+            connections.retain(|x| !x.is_finished());
+        }
+
+        for connection in connections {
+            connection.join().ok();
+        }
+        LOGGER.debug("Stopped waiting for proxy connections.");
+    }
+
+    fn run_connection(mut pipe: Pipe<Server, Duplex>, shared: &Shared) {
+        let peer = match pipe.get_peer() {
+            Ok(x) => x,
+            Err(e) => {
+                LOGGER.error(format!("Refusing unidentifiable proxy connection!\n{}", e));
+                return;
+            }
+        };
+        if let Err(e) = shared.policy.validate(&peer) {
+            LOGGER.error(format!("Refusing proxy connection from {:?}!\n{}", peer, e));
+            return;
+        }
+        let protocol_version = match pipe.handshake() {
+            Ok(x) => x,
+            Err(e) => {
+                LOGGER.error(format!("Refusing proxy connection from {:?}!\n{}", peer, e));
+                return;
+            }
+        };
+
+        LOGGER.debug(format!("Proxy connected: {:?}", peer));
+        let id = shared.register(peer.clone());
+        while let Some(command) = shared.wait_for_command(id) {
+            if let Err(e) = Self::execute(&mut pipe, protocol_version, &command) {
+                LOGGER.error(format!("Lost connection to proxy {:?}\n{}", peer, e));
+                break;
+            }
+        }
+        shared.unregister(id);
     }
-    pub fn send_command(&mut self, command: ProxyCommand) -> Result<()> {
+
+    fn send_command(
+        pipe: &mut Pipe<Server, Duplex>,
+        protocol_version: u32,
+        command: &ProxyCommand,
+    ) -> Result<ProxyReply> {
+        let Some(command) = command.downgrade(protocol_version) else {
+            LOGGER.error(format!(
+                "Not sending {:?}, as the proxy only speaks protocol {}.",
+                command, protocol_version
+            ));
+            return Ok(ProxyReply::Unsupported);
+        };
+        pipe.write_as(&command)?;
         LOGGER.debug(format!("Sent command:\n{:?}", command));
-        self.pipe.write_as(command)?;
+        let reply = pipe.read_to()?;
+        LOGGER.debug(format!("Got reply:\n{}", reply));
+        Ok(reply)
+    }
+
+    /// Sends the command and logs the outcome, retrying a few times if the proxy reports a failure.
+    /// Only fails if the connection is broken.
+    fn execute(
+        pipe: &mut Pipe<Server, Duplex>,
+        protocol_version: u32,
+        command: &ProxyCommand,
+    ) -> Result<()> {
+        for attempt in 1..=COMMAND_ATTEMPTS {
+            match Self::send_command(pipe, protocol_version, command)? {
+                ProxyReply::Success => return Ok(()),
+                ProxyReply::Unsupported => {
+                    LOGGER.error(format!("Proxy does not support command {:?}", command));
+                    return Ok(());
+                }
+                ProxyReply::Failure(chain) => {
+                    LOGGER.error(format!(
+                        "Proxy failed to execute {:?} (attempt {}/{})\n{}",
+                        command,
+                        attempt,
+                        COMMAND_ATTEMPTS,
+                        chain.join("\n")
+                    ));
+                    if attempt < COMMAND_ATTEMPTS {
+                        std::thread::sleep(COMMAND_RETRY_DELAY);
+                    }
+                }
+            }
+        }
         Ok(())
     }
 
     pub fn terminate(&mut self) -> Result<()> {
         LOGGER.debug("Terminating proxy...");
-        self.pipe.close()
+        self.shared.state.lock().unwrap().stopping = true;
+        self.shared.changed.notify_all();
+
+        // The worker might be blocked waiting for a connection, which only a client can end.
+        Pipe::<Client, Duplex>::create_client(&self.name).ok();
+
+        if let Some(acceptor) = self.acceptor.take() {
+            acceptor
+                .join()
+                .map_err(|_| "Proxy connection thread panicked!")?;
+        }
+        Ok(())
+    }
+}
+
+/// A pipe name no other test uses. Also moves the sockets to a directory of their own, as only
+/// root can create the default one.
+#[cfg(test)]
+pub fn unique_test_name(test: &str) -> String {
+    #[cfg(unix)]
+    {
+        static SOCKET_DIR: std::sync::Once = std::sync::Once::new();
+        SOCKET_DIR.call_once(|| {
+            let dir = std::env::temp_dir().join(format!("AutoPowerTest_{}", std::process::id()));
+            std::env::set_var(autopower_shared::pipe::SOCKET_DIR_VAR, dir);
+        });
+    }
+    format!("AutoPowerTest_{}_{}", std::process::id(), test)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::power_source::FakePowerSource;
+    use autopower_shared::proxy_command::{PowerConfigSelection, PowerState};
+
+    const WIRED: ProxyCommand = ProxyCommand::ChangePowerConfig(PowerConfigSelection::Wired);
+    const BATTERY: ProxyCommand = ProxyCommand::ChangePowerConfig(PowerConfigSelection::Battery);
+
+    fn power_state(source: PowerConfigSelection, battery_percent: u8) -> ProxyCommand {
+        ProxyCommand::ChangePowerState(PowerState {
+            source,
+            battery_percent: Some(battery_percent),
+        })
+    }
+
+    fn unique_name(test: &str) -> String {
+        unique_test_name(test)
+    }
+
+    fn start_proxy(name: &str, source: &FakePowerSource) -> Proxy {
+        Proxy::start(name, PeerPolicy::allow_any(), Box::new(source.clone())).unwrap()
+    }
+
+    fn connect_fake_proxy(name: &str) -> Pipe<Client, Duplex> {
+        let mut pipe = Pipe::create_client_retrying(name).unwrap();
+        pipe.handshake().unwrap();
+        pipe
+    }
+
+    #[test]
+    fn delivers_latest_command_once_connected() {
+        let name = unique_name("latest");
+        let mut proxy = start_proxy(&name, &FakePowerSource::default());
+        proxy.send(WIRED);
+        proxy.send(BATTERY);
+
+        let mut fake = connect_fake_proxy(&name);
+        assert_eq!(fake.read_to::<ProxyCommand>().unwrap(), BATTERY);
+        fake.write_as(ProxyReply::Success).unwrap();
+
+        proxy.terminate().unwrap();
+    }
+
+    #[test]
+    fn sends_current_source_on_connect() {
+        let name = unique_name("source_connect");
+        let source = FakePowerSource::new(PowerConfigSelection::Wired, Some(90));
+        let mut proxy = start_proxy(&name, &source);
+
+        let mut fake = connect_fake_proxy(&name);
+        assert_eq!(
+            fake.read_to::<ProxyCommand>().unwrap(),
+            power_state(PowerConfigSelection::Wired, 90)
+        );
+        fake.write_as(ProxyReply::Success).unwrap();
+        drop(fake);
+
+        // Unplugged while no proxy was connected, so no event was sent.
+        source.set(PowerConfigSelection::Battery, Some(85));
+        let mut fake = connect_fake_proxy(&name);
+        assert_eq!(
+            fake.read_to::<ProxyCommand>().unwrap(),
+            power_state(PowerConfigSelection::Battery, 85)
+        );
+        fake.write_as(ProxyReply::Success).unwrap();
+
+        proxy.terminate().unwrap();
+    }
+
+    #[test]
+    fn refresh_broadcasts_current_source() {
+        let name = unique_name("refresh");
+        let source = FakePowerSource::new(PowerConfigSelection::Battery, Some(51));
+        let mut proxy = start_proxy(&name, &source);
+        let mut fake = connect_fake_proxy(&name);
+        assert_eq!(
+            fake.read_to::<ProxyCommand>().unwrap(),
+            power_state(PowerConfigSelection::Battery, 51)
+        );
+        fake.write_as(ProxyReply::Success).unwrap();
+
+        source.set(PowerConfigSelection::Battery, Some(50));
+        proxy.refresh().unwrap();
+        assert_eq!(
+            fake.read_to::<ProxyCommand>().unwrap(),
+            power_state(PowerConfigSelection::Battery, 50)
+        );
+        fake.write_as(ProxyReply::Success).unwrap();
+
+        source.set_unknown();
+        assert!(proxy.refresh().is_err());
+        proxy.terminate().unwrap();
+    }
+
+    #[test]
+    fn redelivers_after_proxy_restart() {
+        let name = unique_name("restart");
+        let mut proxy = start_proxy(&name, &FakePowerSource::default());
+
+        let mut fake = connect_fake_proxy(&name);
+        proxy.send(WIRED);
+        assert_eq!(fake.read_to::<ProxyCommand>().unwrap(), WIRED);
+        fake.write_as(ProxyReply::Success).unwrap();
+
+        // The proxy dies before it can reply.
+        proxy.send(BATTERY);
+        assert_eq!(fake.read_to::<ProxyCommand>().unwrap(), BATTERY);
+        drop(fake);
+
+        let mut fake = connect_fake_proxy(&name);
+        assert_eq!(fake.read_to::<ProxyCommand>().unwrap(), BATTERY);
+        fake.write_as(ProxyReply::Success).unwrap();
+
+        proxy.terminate().unwrap();
+    }
+
+    #[test]
+    fn broadcasts_to_all_proxies() {
+        let name = unique_name("broadcast");
+        let mut proxy = start_proxy(&name, &FakePowerSource::default());
+        let mut first = connect_fake_proxy(&name);
+        let mut second = connect_fake_proxy(&name);
+        while proxy.shared.state.lock().unwrap().connections.len() < 2 {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        proxy.send(WIRED);
+        for fake in [&mut first, &mut second] {
+            assert_eq!(fake.read_to::<ProxyCommand>().unwrap(), WIRED);
+            fake.write_as(ProxyReply::Success).unwrap();
+        }
+
+        // One session logging off must not affect the other.
+        drop(first);
+        proxy.send(BATTERY);
+        assert_eq!(second.read_to::<ProxyCommand>().unwrap(), BATTERY);
+        second.write_as(ProxyReply::Success).unwrap();
+
+        proxy.terminate().unwrap();
+    }
+
+    #[test]
+    fn reloading_keeps_pending_commands() {
+        let name = unique_name("reload");
+        let mut proxy = start_proxy(&name, &FakePowerSource::default());
+        let mut fake = connect_fake_proxy(&name);
+        proxy.send(WIRED);
+        assert_eq!(fake.read_to::<ProxyCommand>().unwrap(), WIRED);
+
+        // Both arrive while the proxy is still busy with the first command.
+        proxy.send(BATTERY);
+        proxy.handle().reload_config();
+        fake.write_as(ProxyReply::Success).unwrap();
+        assert_eq!(
+            fake.read_to::<ProxyCommand>().unwrap(),
+            ProxyCommand::ReloadConfig
+        );
+        fake.write_as(ProxyReply::Success).unwrap();
+        assert_eq!(fake.read_to::<ProxyCommand>().unwrap(), BATTERY);
+        fake.write_as(ProxyReply::Success).unwrap();
+
+        proxy.terminate().unwrap();
+    }
+
+    #[test]
+    fn refuses_disallowed_peers() {
+        let name = unique_name("refuse");
+        let policy = PeerPolicy {
+            allowed_executables: vec!["/nonexistent/autopower_proxy".into()],
+            ..PeerPolicy::allow_any()
+        };
+        let mut proxy = Proxy::start(&name, policy, Box::new(FakePowerSource::default())).unwrap();
+        proxy.send(WIRED);
+
+        let mut pipe: Pipe<Client, Duplex> = Pipe::create_client_retrying(&name).unwrap();
+        assert!(pipe.handshake().is_err());
+        assert!(proxy.shared.state.lock().unwrap().connections.is_empty());
+
+        proxy.terminate().unwrap();
+    }
+
+    #[test]
+    fn terminates_without_connection() {
+        let mut proxy = start_proxy(&unique_name("terminate"), &FakePowerSource::default());
+        proxy.terminate().unwrap();
     }
 }
@@ -2,10 +2,10 @@
 use crate::debug_utils::print_power_event_type;
 
 use super::{handler_data::HandlerData, WindowsService};
-use crate::proxy::Proxy;
+use crate::{control::Control, power_source::SystemPowerSource, proxy::Proxy};
 use autopower_shared::{
     logging::Logger,
-    proxy_command::{PowerConfigSelection, ProxyCommand},
+    pipe::{security::PeerPolicy, CONTROL_PIPE_NAME, PIPE_NAME},
     winstr::Win32String,
 };
 use std::{ffi::c_void, mem::ManuallyDrop};
@@ -25,7 +25,7 @@ use windows::{
                 SERVICE_STATUS_HANDLE, SERVICE_STOPPED, SERVICE_STOP_PENDING,
                 SERVICE_WIN32_OWN_PROCESS,
             },
-            SystemServices::GUID_ACDC_POWER_SOURCE,
+            SystemServices::{GUID_ACDC_POWER_SOURCE, GUID_BATTERY_PERCENTAGE_REMAINING},
             Threading::{CreateEventW, SetEvent, WaitForSingleObject, INFINITE},
         },
         UI::WindowsAndMessaging::{self, PBT_POWERSETTINGCHANGE},
@@ -42,6 +42,7 @@ pub struct PowerService {
     status_handle: Option<SERVICE_STATUS_HANDLE>,
     stop_event: Option<HANDLE>,
     proxy: Option<Proxy>,
+    control: Option<Control>,
 }
 
 impl PowerService {
@@ -51,6 +52,7 @@ impl PowerService {
             status_handle: None,
             stop_event: None,
             proxy: None,
+            control: None,
         }
     }
 
@@ -78,22 +80,13 @@ impl PowerService {
         Ok(())
     }
 
-    fn handle_on_wired_power(&mut self) -> Result<()> {
-        LOGGER.debug("On wired power event.");
+    /// Sends the full power state, as the proxy needs both the source and the battery percentage
+    /// to pick a config, while each notification only carries one of them.
+    fn handle_power_state_change(&mut self) -> Result<()> {
         self.proxy
-            .as_mut()
-            .unwrap()
-            .send_command(ProxyCommand::ChangePowerConfig(PowerConfigSelection::Wired))
-    }
-
-    fn handle_on_battery_power(&mut self) -> Result<()> {
-        LOGGER.debug("On battery power event.");
-        self.proxy
-            .as_mut()
-            .unwrap()
-            .send_command(ProxyCommand::ChangePowerConfig(
-                PowerConfigSelection::Battery,
-            ))
+            .as_ref()
+            .ok_or("Proxy was not started!")?
+            .refresh()
     }
 
     fn handle_power_event(&mut self, data: HandlerData) {
@@ -111,21 +104,28 @@ impl PowerService {
         }
 
         let pbs = event_data as *mut POWERBROADCAST_SETTING;
-        unsafe {
-            if (*pbs).PowerSetting != GUID_ACDC_POWER_SOURCE {
-                LOGGER.debug(format!(
-                    "Power event GUID was not GUID_ACDC_POWER_SOURCE\nGUID was: {:?}",
-                    (*pbs).PowerSetting
-                ));
-                return;
+        let (setting, data) = unsafe { ((*pbs).PowerSetting, (*pbs).Data[0]) };
+        if setting == GUID_ACDC_POWER_SOURCE {
+            match SYSTEM_POWER_CONDITION(data as i32) {
+                Power::PoAc => LOGGER.debug("On wired power event."),
+                Power::PoDc => LOGGER.debug("On battery power event."),
+                _ => {
+                    LOGGER.debug("Unknown SYSTEM_POWER_CONDITION");
+                    return;
+                }
             }
+        } else if setting == GUID_BATTERY_PERCENTAGE_REMAINING {
+            LOGGER.debug(format!("On battery percentage event: {}%", data));
+        } else {
+            LOGGER.debug(format!(
+                "Power event GUID was not GUID_ACDC_POWER_SOURCE or GUID_BATTERY_PERCENTAGE_REMAINING\nGUID was: {:?}",
+                setting
+            ));
+            return;
         }
 
-        let new_power = unsafe { (*pbs).Data[0] };
-        match SYSTEM_POWER_CONDITION(new_power as i32) {
-            Power::PoAc => self.handle_on_wired_power().unwrap(),
-            Power::PoDc => self.handle_on_battery_power().unwrap(),
-            _ => LOGGER.debug("Unknown SYSTEM_POWER_CONDITION"),
+        if let Err(e) = self.handle_power_state_change() {
+            LOGGER.error(format!("Could not change power config!\n{}", e));
         }
     }
 
@@ -219,13 +219,26 @@ impl WindowsService for PowerService {
         }
 
         LOGGER.debug("Setting up proxy...");
-        me.proxy = Some(match Proxy::create() {
-            Ok(x) => x,
-            Err(e) => {
-                LOGGER.error(format!("Could not create proxy!\n{}", e));
-                panic!();
-            }
-        });
+        me.proxy = Some(
+            match PeerPolicy::for_proxies()
+                .and_then(|x| Proxy::start(PIPE_NAME, x, Box::new(SystemPowerSource)))
+            {
+                Ok(x) => x,
+                Err(e) => {
+                    LOGGER.error(format!("Could not create proxy!\n{}", e));
+                    panic!();
+                }
+            },
+        );
+
+        // The service still switches profiles without it, so this is not fatal.
+        LOGGER.debug("Setting up control...");
+        match PeerPolicy::for_controllers()
+            .and_then(|x| Control::start(CONTROL_PIPE_NAME, x, me.proxy.as_ref().unwrap().handle()))
+        {
+            Ok(x) => me.control = Some(x),
+            Err(e) => LOGGER.error(format!("Could not create control!\n{}", e)),
+        }
 
         LOGGER.debug("Creating stop event...");
         me.stop_event = Some(match CreateEventW(None, TRUE, FALSE, None) {
@@ -242,18 +255,32 @@ impl WindowsService for PowerService {
         }
 
         LOGGER.debug("Registering power setting notification handling...");
-        let mut power_notif_handle = match RegisterPowerSettingNotification(
-            HANDLE(me.status_handle.unwrap().0),
-            &GUID_ACDC_POWER_SOURCE,
-            WindowsAndMessaging::DEVICE_NOTIFY_SERVICE_HANDLE,
-        ) {
-            Ok(x) => x,
-            Err(e) => {
-                let msg = format!("Could not register power settings notification!\n{}", e);
-                LOGGER.error(&msg);
-                panic!("{}", msg);
-            }
-        };
+        let mut power_notif_handles = vec![];
+        for setting in [&GUID_ACDC_POWER_SOURCE, &GUID_BATTERY_PERCENTAGE_REMAINING] {
+            power_notif_handles.push(
+                match RegisterPowerSettingNotification(
+                    HANDLE(me.status_handle.unwrap().0),
+                    setting,
+                    WindowsAndMessaging::DEVICE_NOTIFY_SERVICE_HANDLE,
+                ) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        let msg = format!("Could not register power settings notification!\n{}", e);
+                        LOGGER.error(&msg);
+                        panic!("{}", msg);
+                    }
+                },
+            );
+        }
+
+        // Power events only report changes, so the config is stale until the next one.
+        LOGGER.debug("Applying config for the current power source...");
+        if let Err(e) = me.proxy.as_ref().unwrap().refresh() {
+            LOGGER.error(format!(
+                "Could not apply config for the current power source!\n{}",
+                e
+            ));
+        }
 
         // Wait for exit.
         WaitForSingleObject(me.stop_event.unwrap(), INFINITE);
@@ -263,10 +290,15 @@ impl WindowsService for PowerService {
         if let Err(e) = me.set_service_status(SERVICE_STOPPED, Some(3), None) {
             LOGGER.error(format!("Could not set service status!\n{}", e));
         }
+        if let Some(control) = me.control.as_mut() {
+            control.terminate().ok();
+        }
         me.proxy.as_mut().unwrap().terminate().ok();
 
         use windows::core::Free;
-        power_notif_handle.free();
+        for handle in &mut power_notif_handles {
+            handle.free();
+        }
 
         drop(Box::from_raw(me));
     }